@@ -1,9 +1,17 @@
 use crate::commands::{
-    config::Config, create_key::CreateKey, error::CommandError, file::FileCommand,
+    config::Config,
+    create_key::CreateKey,
+    error::CommandError,
+    file::FileCommand,
+    fs::FsCommand,
+    key::{read_key_file, KeyCommand},
+    sync::SyncCommand,
     util::UtilCommand,
 };
 use clap::{Parser, Subcommand};
 use jsonrpsee::ws_client::WsClientBuilder;
+use server::server::MAX_RPC_BODY_SIZE;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -13,13 +21,21 @@ pub struct Cli {
 
     #[arg(long, default_value = "ws://localhost:8008", global = true)]
     server_url: String,
+
+    /// Encrypt and decrypt with the key in this file, written by `key export`, instead of
+    /// unlocking the keystore
+    #[arg(long, env = "LOCAL_IPFS_KEY_FILE", global = true)]
+    key_file: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
     File(FileCommand),
+    Fs(FsCommand),
     Util(UtilCommand),
     CreateKey(CreateKey),
+    Key(KeyCommand),
+    Sync(SyncCommand),
 }
 
 pub async fn run() {
@@ -29,26 +45,48 @@ pub async fn run() {
         Ok(config) => config,
         Err(err) => {
             eprintln!("Error parsing config: {}", err);
-            return;
+            std::process::exit(1);
         }
     };
 
-    let result = if let Command::CreateKey(cmd) = args.command {
-        cmd.handle(&mut config).await
-    } else {
-        match WsClientBuilder::default().build(&args.server_url).await {
-            Ok(client) => match args.command {
+    if let Some(key_file) = &args.key_file {
+        match read_key_file(key_file).await {
+            Ok(key) => config.set_key_override(key),
+            Err(err) => {
+                eprintln!("Error reading key file: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let result = match args.command {
+        Command::CreateKey(cmd) => cmd.handle(&mut config).await,
+        Command::Key(cmd) => cmd.handle(&mut config).await,
+        Command::File(cmd) if cmd.is_offline() => cmd.handle_offline().await,
+        command => match WsClientBuilder::default()
+            .max_request_size(MAX_RPC_BODY_SIZE)
+            .max_response_size(MAX_RPC_BODY_SIZE)
+            .build(&args.server_url)
+            .await
+        {
+            Ok(client) => match command {
                 Command::File(cmd) => cmd.handle(client, &mut config).await,
+                Command::Fs(cmd) => cmd.handle(client, &config).await,
                 Command::Util(cmd) => cmd.handle(client).await,
+                Command::Sync(cmd) => cmd.handle(client, &mut config).await,
                 _ => Ok(()),
             },
             Err(err) => Err(CommandError::JsonRpsee { source: err }),
-        }
+        },
     };
 
-    if let Err(err) = result {
-        eprintln!("{}", err)
-    } else if let Err(err) = config.update_config_file().await {
+    // saved even when the command fails, as it may have added or removed files before failing
+    if let Err(err) = config.update_config_file().await {
         eprintln!("Error updating config file: {}", err);
     }
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        // a failing status lets scripts act on checks such as `file get --verify-only`
+        std::process::exit(1);
+    }
 }
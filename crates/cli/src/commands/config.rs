@@ -1,18 +1,47 @@
 use home::home_dir;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf};
-use tokio::fs;
+use server::crypto::{
+    encryption::{Header, KeyId},
+    envelope::{Envelope, Identity, PublicKey},
+};
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+use tokio::{fs, io::AsyncWriteExt};
+use zeroize::Zeroizing;
 
 use super::error::CommandError;
+use crate::services::keystore::{self, Keyring, Keystore};
 
 const CONFIG_FILE_NAME: &str = ".local_ipfs_config.json";
+/// Name given to the key of configs written before the keyring.
+pub const DEFAULT_KEY_NAME: &str = "default";
 type FilePath = String;
 type Hash = String;
+type KeyName = String;
 
-#[derive(Deserialize, Serialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Default)]
 pub struct Config {
+    /// The single key of configs written before the keyring, moved into the keystore on parse.
+    #[serde(default, skip_serializing)]
     encryption_key: Option<Vec<u8>>,
+    #[serde(flatten)]
+    keystore: Keystore,
+    /// Key new files are encrypted with.
+    #[serde(default)]
+    active_key: Option<KeyName>,
+    /// Team members every new file is shared with.
+    #[serde(default)]
+    recipients: BTreeMap<String, PublicKey>,
     hashes: HashMap<Hash, FilePath>,
+    /// The keystore's keys, unlocked the first time a command needs one.
+    #[serde(skip)]
+    keyring: OnceCell<Keyring>,
+    /// Key given with `--key-file`, used instead of the keystore.
+    #[serde(skip)]
+    key_override: Option<Zeroizing<Vec<u8>>>,
 }
 
 impl Config {
@@ -20,39 +49,290 @@ impl Config {
         let config_path = Self::config_path()?;
 
         let config = if config_path.exists() {
-            let contents = fs::read_to_string(config_path).await?;
-            serde_json::from_str::<Config>(&contents)?
+            let contents = fs::read_to_string(&config_path).await?;
+            let mut config = serde_json::from_str::<Config>(&contents)?;
+            config.migrate_encryption_key()?;
+            // keys from before the keystore are sealed right away rather than whenever a
+            // command next needs the keyring, prompting for a new passphrase once
+            if config.keystore.has_plain_keys() {
+                config.keyring()?;
+                config.seal()?;
+                write_private(config_path, serde_json::to_string(&config)?).await?;
+            }
+            config
         } else {
             let config = Config::default();
             let contents = serde_json::to_string(&config)?;
-            fs::write(config_path, contents).await?;
+            write_private(config_path, contents).await?;
             config
         };
         Ok(config)
     }
 
-    fn config_path() -> Result<PathBuf, CommandError> {
+    fn migrate_encryption_key(&mut self) -> Result<(), CommandError> {
+        if let Some(encryption_key) = self.encryption_key.take() {
+            if self.keystore.contains(DEFAULT_KEY_NAME) {
+                return Err(CommandError::Error(format!(
+                    "Key {} already exists",
+                    DEFAULT_KEY_NAME
+                )));
+            }
+            self.keystore
+                .insert_plain(DEFAULT_KEY_NAME.into(), encryption_key);
+            self.active_key.get_or_insert(DEFAULT_KEY_NAME.into());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn config_path() -> Result<PathBuf, CommandError> {
         let mut config_path =
             home_dir().ok_or_else(|| CommandError::Error("Unable to get home directory".into()))?;
         config_path.push(CONFIG_FILE_NAME);
         Ok(config_path)
     }
 
-    pub async fn update_config_file(self) -> Result<(), CommandError> {
+    /// Writes the config back, sealing the keyring's keys if it was unlocked.
+    pub async fn update_config_file(mut self) -> Result<(), CommandError> {
+        if let Some(keyring) = self.keyring.get() {
+            self.keystore.seal(keyring)?;
+        }
+
         let config_path = Self::config_path()?;
         let contents = serde_json::to_string(&self)?;
-        fs::write(config_path, contents).await?;
+        write_private(config_path, contents).await?;
 
         Ok(())
     }
 
-    pub fn encryption_key(&self) -> Result<&Vec<u8>, CommandError> {
-        let key = self
-            .encryption_key
+    /// Uses `encryption_key` for every file instead of the keys in the keystore.
+    pub fn set_key_override(&mut self, encryption_key: Vec<u8>) {
+        self.key_override = Some(Zeroizing::new(encryption_key));
+    }
+
+    /// The active key, which new files are encrypted with.
+    pub fn encryption_key(&self) -> Result<&[u8], CommandError> {
+        if let Some(key) = &self.key_override {
+            return Ok(key);
+        }
+
+        let name = self
+            .active_key
             .as_ref()
             .ok_or_else(|| CommandError::Error("Encryption key not set".into()))?;
+        self.key(name)
+    }
+
+    pub fn active_key_name(&self) -> Option<&str> {
+        self.active_key.as_deref()
+    }
+
+    /// The key that opens `data`, and the payload it opens. Files shared through an
+    /// [`Envelope`] are opened with their data key. Other files name their key in their
+    /// header, except those encrypted before headers were written, which are tried with the
+    /// active key.
+    pub fn decryption_key<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> Result<(Zeroizing<Vec<u8>>, &'a [u8]), CommandError> {
+        if let Some((envelope, payload)) = Envelope::parse(data)? {
+            return Ok((self.data_key(&envelope)?, payload));
+        }
+        if let Some(key) = &self.key_override {
+            return Ok((key.clone(), data));
+        }
+
+        let key = match Header::parse(data)? {
+            Some(header) => {
+                let (name, _) = self
+                    .keys()
+                    .find(|(_, key_id)| *key_id == header.key_id)
+                    .ok_or_else(|| {
+                        CommandError::Error(format!(
+                            "Encrypted with key {}, which is not in the keyring",
+                            header.key_id
+                        ))
+                    })?;
+                self.key(name)?
+            }
+            None => self.encryption_key()?,
+        };
+        Ok((Zeroizing::new(key.to_vec()), data))
+    }
+
+    /// Unwraps the data key of `envelope` with a key from the keyring or with the identity.
+    pub fn data_key(&self, envelope: &Envelope) -> Result<Zeroizing<Vec<u8>>, CommandError> {
+        let data_key = match &self.key_override {
+            Some(key) => envelope.unwrap_with_key(key)?,
+            None => {
+                let name = envelope.key_ids().find_map(|id| {
+                    self.keys()
+                        .find(|(_, key_id)| *key_id == id)
+                        .map(|(name, _)| name)
+                });
+                match name {
+                    Some(name) => envelope.unwrap_with_key(self.key(name)?)?,
+                    None if self.keystore.has_identity() => {
+                        let identity = self.existing_identity()?;
+                        envelope.unwrap_with_identity(&identity)?
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        data_key.ok_or_else(|| {
+            CommandError::Error(
+                "Not shared with any key in the keyring or with this identity".into(),
+            )
+        })
+    }
+
+    /// Names and ids of the keys in the keystore, listed without unlocking it.
+    pub fn keys(&self) -> impl Iterator<Item = (&str, KeyId)> {
+        self.keystore.key_ids()
+    }
+
+    pub fn key(&self, name: &str) -> Result<&[u8], CommandError> {
+        self.keyring()?
+            .get(name)
+            .ok_or_else(|| CommandError::Error(format!("No key named {}", name)))
+    }
+
+    /// Adds `encryption_key` to the keyring, refusing to replace a key or to hold the same
+    /// key twice.
+    pub fn add_key(&mut self, name: KeyName, encryption_key: Vec<u8>) -> Result<(), CommandError> {
+        if self.keystore.contains(&name) {
+            return Err(CommandError::Error(format!("Key {} already exists", name)));
+        }
+        let key_id = KeyId::of(&encryption_key);
+        if let Some((existing, _)) = self.keys().find(|(_, id)| *id == key_id) {
+            return Err(CommandError::Error(format!(
+                "Key is already in the keyring as {}",
+                existing
+            )));
+        }
+
+        self.keyring_mut()?.insert(name, encryption_key);
+        self.seal()
+    }
+
+    pub fn set_active_key(&mut self, name: &str) -> Result<(), CommandError> {
+        if !self.keystore.contains(name) {
+            return Err(CommandError::Error(format!("No key named {}", name)));
+        }
+        self.active_key = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn remove_key(&mut self, name: &str) -> Result<(), CommandError> {
+        if self.active_key.as_deref() == Some(name) {
+            return Err(CommandError::Error(format!(
+                "Cannot delete the active key {}, rotate to another key first",
+                name
+            )));
+        }
+        if !self.keystore.remove(name) {
+            return Err(CommandError::Error(format!("No key named {}", name)));
+        }
+        if let Some(keyring) = self.keyring.get_mut() {
+            keyring.remove(name);
+        }
+        Ok(())
+    }
+
+    /// The identity files are shared with, created the first time it is asked for.
+    pub fn identity(&mut self) -> Result<Identity, CommandError> {
+        if self.keystore.has_identity() {
+            return self.existing_identity();
+        }
+
+        let identity = Identity::generate();
+        self.keyring_mut()?.set_identity(identity.to_bytes());
+        self.seal()?;
+        Ok(identity)
+    }
+
+    fn existing_identity(&self) -> Result<Identity, CommandError> {
+        self.keyring()?
+            .identity()
+            .and_then(Identity::from_bytes)
+            .ok_or_else(|| CommandError::Error("Stored identity is corrupt".into()))
+    }
 
-        Ok(key)
+    pub fn recipients(&self) -> impl Iterator<Item = (&str, &PublicKey)> {
+        self.recipients
+            .iter()
+            .map(|(name, public_key)| (name.as_str(), public_key))
+    }
+
+    /// A recipient from the team list by name, or a public key given directly.
+    pub fn recipient(&self, recipient: &str) -> Result<PublicKey, CommandError> {
+        match self.recipients.get(recipient) {
+            Some(public_key) => Ok(*public_key),
+            None => recipient.parse().map_err(|_| {
+                CommandError::Error(format!(
+                    "{} is neither a recipient nor an x25519 public key",
+                    recipient
+                ))
+            }),
+        }
+    }
+
+    pub fn add_recipient(
+        &mut self,
+        name: String,
+        public_key: PublicKey,
+    ) -> Result<(), CommandError> {
+        if self.recipients.contains_key(&name) {
+            return Err(CommandError::Error(format!(
+                "Recipient {} already exists",
+                name
+            )));
+        }
+        self.recipients.insert(name, public_key);
+        Ok(())
+    }
+
+    pub fn remove_recipient(&mut self, name: &str) -> Result<(), CommandError> {
+        self.recipients
+            .remove(name)
+            .ok_or_else(|| CommandError::Error(format!("No recipient named {}", name)))?;
+        Ok(())
+    }
+
+    /// Seals the keystore with a new passphrase.
+    pub fn change_passphrase(&mut self) -> Result<(), CommandError> {
+        self.keyring()?;
+        let passphrase = keystore::read_passphrase(true)?;
+        self.keyring_mut()?.change_passphrase(&passphrase)?;
+        self.seal()
+    }
+
+    /// Unlocks the keystore, prompting for its passphrase, or for a new one if the keystore
+    /// has never been sealed.
+    fn keyring(&self) -> Result<&Keyring, CommandError> {
+        if let Some(keyring) = self.keyring.get() {
+            return Ok(keyring);
+        }
+        let passphrase = keystore::read_passphrase(!self.keystore.is_sealed())?;
+        self.unlock(&passphrase)
+    }
+
+    fn keyring_mut(&mut self) -> Result<&mut Keyring, CommandError> {
+        self.keyring()?;
+        Ok(self.keyring.get_mut().expect("keyring was unlocked"))
+    }
+
+    fn unlock(&self, passphrase: &str) -> Result<&Keyring, CommandError> {
+        let keyring = self.keystore.unlock(passphrase)?;
+        Ok(self.keyring.get_or_init(|| keyring))
+    }
+
+    fn seal(&mut self) -> Result<(), CommandError> {
+        if let Some(keyring) = self.keyring.get() {
+            self.keystore.seal(keyring)?;
+        }
+        Ok(())
     }
 
     pub fn hash<S: Into<String>>(&self, file_path: S) -> Result<&str, CommandError> {
@@ -66,15 +346,107 @@ impl Config {
         Ok(hash)
     }
 
-    pub fn update_encryption_key(&mut self, encryption_key: Vec<u8>) {
-        self.encryption_key = Some(encryption_key)
+    pub fn file_path(&self, hash: &str) -> Option<&str> {
+        self.hashes.get(hash).map(String::as_str)
     }
 
     pub fn add_hash<S: Into<String>>(&mut self, file_path: S, hash: String) {
         self.hashes.insert(hash, file_path.into());
     }
 
-    pub fn remove_hash(&mut self, hash: &str) {
-        self.hashes.remove(hash);
+    pub fn remove_hash(&mut self, hash: &str) -> Option<FilePath> {
+        self.hashes.remove(hash)
+    }
+}
+
+/// Writes `contents` readable by the current user only, as the config holds keys.
+pub(crate) async fn write_private(path: PathBuf, contents: String) -> Result<(), CommandError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path).await?;
+
+    #[cfg(unix)]
+    {
+        use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+        file.set_permissions(Permissions::from_mode(0o600)).await?;
+    }
+    file.write_all(contents.as_bytes()).await?;
+    file.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use server::crypto::encryption::{EncryptWriter, Encryption};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn files_are_decrypted_with_the_key_they_name() {
+        let old_key = Encryption::generate_key().to_vec();
+        let mut config = serde_json::from_str::<Config>(&format!(
+            r#"{{"encryption_key":{:?},"hashes":{{}}}}"#,
+            old_key
+        ))
+        .unwrap();
+        config.migrate_encryption_key().unwrap();
+        assert_eq!(config.active_key_name(), Some(DEFAULT_KEY_NAME));
+        config.unlock("passphrase").unwrap();
+
+        let mut writer = EncryptWriter::new(&old_key, vec![]);
+        writer.shutdown().await.unwrap();
+        let data = writer.into_inner();
+
+        let new_key = Encryption::generate_key().to_vec();
+        config.add_key("new".into(), new_key.clone()).unwrap();
+        config.set_active_key("new").unwrap();
+        assert_eq!(config.encryption_key().unwrap(), new_key);
+        assert_eq!(*config.decryption_key(&data).unwrap().0, old_key);
+
+        assert!(config.add_key("copy".into(), new_key).is_err());
+        assert!(config.remove_key("new").is_err());
+        config.remove_key(DEFAULT_KEY_NAME).unwrap();
+        assert!(config.decryption_key(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn shared_files_are_decrypted_with_the_identity() {
+        let mut owner = Config::default();
+        owner.unlock("owner").unwrap();
+        owner
+            .add_key(DEFAULT_KEY_NAME.into(), Encryption::generate_key().to_vec())
+            .unwrap();
+        let mut teammate = Config::default();
+        teammate.unlock("teammate").unwrap();
+        let public_key = teammate.identity().unwrap().public_key();
+        owner.add_recipient("teammate".into(), public_key).unwrap();
+        assert!(owner.add_recipient("teammate".into(), public_key).is_err());
+
+        let data_key = Envelope::generate_data_key();
+        let mut envelope = Envelope::default();
+        envelope
+            .wrap_for_key(&data_key, owner.key(DEFAULT_KEY_NAME).unwrap())
+            .unwrap();
+        envelope
+            .wrap_for_recipient(&data_key, &owner.recipient("teammate").unwrap())
+            .unwrap();
+        let mut writer = EncryptWriter::new(&data_key, envelope.to_bytes());
+        writer.write_all(b"shared").await.unwrap();
+        writer.shutdown().await.unwrap();
+        let data = writer.into_inner();
+
+        for config in [&owner, &teammate] {
+            let (key, payload) = config.decryption_key(&data).unwrap();
+            assert_eq!(key, data_key);
+            assert_eq!(Encryption::decrypt(&key, payload).unwrap(), b"shared");
+        }
+
+        let mut outsider = Config::default();
+        outsider.unlock("outsider").unwrap();
+        outsider.identity().unwrap();
+        assert!(outsider.decryption_key(&data).is_err());
     }
 }
@@ -1,6 +1,10 @@
 use clap::Parser;
+use server::crypto::encryption::Encryption;
 
-use super::{super::services::encryption::Encryption, config::Config, error::CommandError};
+use super::{
+    config::{Config, DEFAULT_KEY_NAME},
+    error::CommandError,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -8,12 +12,13 @@ pub struct CreateKey;
 
 impl CreateKey {
     pub async fn handle(self, config: &mut Config) -> Result<(), CommandError> {
-        if config.encryption_key().is_err() {
+        if config.active_key_name().is_none() {
             println!("Generating encryption key...");
             let key = Encryption::generate_key();
-            config.update_encryption_key(key.to_vec());
+            config.add_key(DEFAULT_KEY_NAME.into(), key.to_vec())?;
+            config.set_active_key(DEFAULT_KEY_NAME)?;
         } else {
-            println!("Encryption key already exists in config");
+            println!("Encryption key already exists in config, use `key rotate` to replace it");
         }
 
         Ok(())
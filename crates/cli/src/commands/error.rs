@@ -18,8 +18,41 @@ pub enum CommandError {
         source: serde_json::Error,
     },
 
-    #[error("Error: {0}")]
-    Aead(String),
+    #[error("{source}")]
+    InvalidCid {
+        #[from]
+        source: server::api::types::ipfs::InvalidCid,
+    },
+
+    #[error("{source}")]
+    InvalidMfsPath {
+        #[from]
+        source: server::api::types::mfs::InvalidMfsPath,
+    },
+
+    #[error("{source}")]
+    Encryption {
+        #[from]
+        source: server::crypto::encryption::EncryptionError,
+    },
+
+    #[error("{source}")]
+    DigestMismatch {
+        #[from]
+        source: crate::services::manifest::DigestMismatch,
+    },
+
+    #[error("{source}")]
+    Keystore {
+        #[from]
+        source: crate::services::keystore::KeystoreError,
+    },
+
+    #[error("{source}")]
+    Watch {
+        #[from]
+        source: notify::Error,
+    },
 
     #[error("Error: {0}")]
     Error(String),
@@ -1,13 +1,31 @@
 use clap::{Parser, Subcommand};
 use jsonrpsee::async_client::Client;
-use server::api::{
-    ipfs::IpfsClient,
-    types::ipfs::{IpfsPinResponse, PinAction},
+use server::{
+    api::{
+        ipfs::IpfsClient,
+        mfs::MfsClient,
+        transfer,
+        types::{
+            ipfs::{CidVersion, IpfsCid, IpfsPinResponse, PinAction, ReplicationFactor},
+            mfs::MfsPath,
+        },
+    },
+    backend::car::read_car,
+    crypto::{
+        compression::CompressReader,
+        convergent::Convergent,
+        encryption::{Compression, DecryptReader, EncryptWriter, Header, KeyId, SEGMENT_SIZE},
+        envelope::Envelope,
+    },
+};
+use std::{collections::HashMap, fmt::Debug, path::Path};
+use tokio::{
+    fs::{self, File},
+    io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt},
 };
-use std::{fmt::Debug, path::Path};
-use tokio::{fs::File, io::AsyncReadExt};
+use zeroize::Zeroizing;
 
-use crate::services::encryption::Encryption;
+use crate::services::manifest::{DigestReader, Digested, Manifest, MANIFEST_DIR};
 
 use super::{config::Config, error::CommandError};
 
@@ -20,14 +38,81 @@ pub struct FileCommand {
 
 #[derive(Subcommand, Debug)]
 enum Command {
+    /// Encrypt and add a file
     Add {
         #[arg(long)]
         file_path: String,
+
+        #[command(flatten)]
+        replication: ReplicationArgs,
+
+        /// Unpin the file across the cluster after this long, e.g. 90s, 30m, 12h or 7d
+        #[arg(long, value_parser = parse_ttl)]
+        ttl: Option<u64>,
+
+        /// Cid version to hash the file with, 0 or 1
+        #[arg(long, value_parser = parse_cid_version)]
+        cid_version: Option<CidVersion>,
+
+        #[command(flatten)]
+        encryption: EncryptionArgs,
     },
 
     Get {
         #[arg(long)]
-        hash: Option<String>,
+        hash: Option<IpfsCid>,
+
+        #[arg(long)]
+        file_path: Option<String>,
+
+        /// Write the decrypted file here instead of under the name in its manifest
+        #[arg(long)]
+        out: Option<String>,
+
+        /// Print the decrypted file instead of writing it
+        #[arg(long, conflicts_with = "out")]
+        print: bool,
+
+        /// Check that the file decrypts to the digest in its manifest, without writing it
+        #[arg(long, conflicts_with_all = ["out", "print"])]
+        verify_only: bool,
+    },
+
+    /// List the files in the cluster whose manifests this keyring can decrypt
+    Ls,
+
+    /// Encrypt a file again with the active key, add it and unpin the old copy
+    Reencrypt {
+        #[arg(long)]
+        hash: Option<IpfsCid>,
+
+        #[arg(long)]
+        file_path: Option<String>,
+
+        #[command(flatten)]
+        replication: ReplicationArgs,
+    },
+
+    /// Let more recipients decrypt a file, add it and unpin the old copy
+    Share {
+        #[arg(long)]
+        hash: Option<IpfsCid>,
+
+        #[arg(long)]
+        file_path: Option<String>,
+
+        /// A recipient from `key recipient`, or an x25519 public key
+        #[arg(long, required = true)]
+        recipient: Vec<String>,
+
+        #[command(flatten)]
+        replication: ReplicationArgs,
+    },
+
+    /// Print the size and block count of a file's dag
+    Stat {
+        #[arg(long)]
+        hash: Option<IpfsCid>,
 
         #[arg(long)]
         file_path: Option<String>,
@@ -35,6 +120,52 @@ enum Command {
 
     #[command(subcommand)]
     Pin(Pin),
+
+    /// Export a file's dag to a car file, for moving it to another cluster
+    Export {
+        #[arg(long)]
+        hash: Option<IpfsCid>,
+
+        #[arg(long)]
+        file_path: Option<String>,
+
+        #[arg(long)]
+        out: String,
+    },
+
+    /// Import a car file and pin its roots across the cluster
+    Import {
+        car_path: String,
+
+        #[command(flatten)]
+        replication: ReplicationArgs,
+
+        /// Unpin the roots across the cluster after this long, e.g. 90s, 30m, 12h or 7d
+        #[arg(long, value_parser = parse_ttl)]
+        ttl: Option<u64>,
+    },
+
+    /// List the roots and blocks of a car file, without contacting the server
+    Inspect { car_path: String },
+
+    /// Point an ipns name at a file, peers pin the new file and unpin the previous one
+    Publish {
+        /// Name of the ipns key to publish with, created if it does not exist
+        #[arg(long)]
+        name: String,
+
+        #[arg(long)]
+        hash: Option<IpfsCid>,
+
+        #[arg(long)]
+        file_path: Option<String>,
+    },
+
+    /// Print the path an ipns name points at
+    Resolve {
+        #[arg(long)]
+        name: String,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -42,73 +173,656 @@ enum Pin {
     Ls,
     Rm {
         #[arg(long)]
-        hash: Option<String>,
+        hash: Option<IpfsCid>,
 
         #[arg(long)]
         file_path: Option<String>,
     },
     Add {
         #[arg(long)]
-        hash: Option<String>,
+        hash: Option<IpfsCid>,
 
         #[arg(long)]
         file_path: Option<String>,
+
+        #[command(flatten)]
+        replication: ReplicationArgs,
+
+        /// Unpin the file across the cluster after this long, e.g. 90s, 30m, 12h or 7d
+        #[arg(long, value_parser = parse_ttl)]
+        ttl: Option<u64>,
     },
+    Status {
+        #[arg(long)]
+        hash: Option<IpfsCid>,
+
+        #[arg(long)]
+        file_path: Option<String>,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct ReplicationArgs {
+    /// Minimum number of cluster peers that must hold the content
+    #[arg(long, requires = "replication_max")]
+    replication_min: Option<usize>,
+
+    /// Maximum number of cluster peers that should hold the content
+    #[arg(long, requires = "replication_min")]
+    replication_max: Option<usize>,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct EncryptionArgs {
+    /// Share the file with a recipient from `key recipient`, or an x25519 public key,
+    /// as well as with the team list
+    #[arg(long)]
+    recipient: Vec<String>,
+
+    /// Derive the key from the file's contents, so adding an identical file again, from
+    /// here or from anyone holding the same key, gives the same cid and is stored once. This
+    /// shows anyone who can list cids which files are identical, and lets holders of the key
+    /// confirm a guess of a file's contents
+    #[arg(long)]
+    convergent: bool,
+
+    /// Compress the file with zstd or gzip before encrypting it, `file get` decompresses it.
+    /// The size of the ciphertext then says how well the file compressed
+    #[arg(long)]
+    compress: Option<Compression>,
+}
+
+impl ReplicationArgs {
+    fn replication_factor(&self) -> Option<ReplicationFactor> {
+        match (self.replication_min, self.replication_max) {
+            (Some(min), Some(max)) => Some(ReplicationFactor::new(min, max)),
+            _ => None,
+        }
+    }
 }
 
 impl FileCommand {
     pub async fn handle(self, client: Client, config: &mut Config) -> Result<(), CommandError> {
         match self.command {
-            Command::Add { ref file_path } => Self::add(&client, file_path, config).await?,
-            Command::Get { hash, file_path } => Self::get(&client, config, hash, file_path).await?,
+            Command::Add {
+                ref file_path,
+                ref replication,
+                ttl,
+                cid_version,
+                ref encryption,
+            } => {
+                Self::add(
+                    &client,
+                    file_path,
+                    replication,
+                    ttl,
+                    cid_version,
+                    encryption,
+                    config,
+                )
+                .await?;
+            }
+            Command::Get {
+                hash,
+                file_path,
+                out,
+                print,
+                verify_only,
+            } => Self::get(&client, config, hash, file_path, out, print, verify_only).await?,
+            Command::Ls => Self::ls(&client, config).await?,
+            Command::Reencrypt {
+                hash,
+                file_path,
+                replication,
+            } => Self::reencrypt(&client, config, hash, file_path, replication).await?,
+            Command::Share {
+                hash,
+                file_path,
+                recipient,
+                replication,
+            } => Self::share(&client, config, hash, file_path, recipient, replication).await?,
+            Command::Stat { hash, file_path } => {
+                Self::stat(&client, config, hash, file_path).await?
+            }
             Command::Pin(pin) => Self::pin(&client, config, pin).await?,
+            Command::Export {
+                hash,
+                file_path,
+                out,
+            } => Self::export(&client, config, hash, file_path, out).await?,
+            Command::Import {
+                car_path,
+                replication,
+                ttl,
+            } => Self::import(&client, car_path, replication, ttl).await?,
+            Command::Inspect { ref car_path } => Self::inspect(car_path).await?,
+            Command::Publish {
+                name,
+                hash,
+                file_path,
+            } => Self::publish(&client, config, name, hash, file_path).await?,
+            Command::Resolve { name } => Self::resolve(&client, name).await?,
         };
 
         Ok(())
     }
 
-    async fn add<F>(client: &Client, file_path: F, config: &mut Config) -> Result<(), CommandError>
+    /// Whether the command can run without a server connection.
+    pub fn is_offline(&self) -> bool {
+        matches!(self.command, Command::Inspect { .. })
+    }
+
+    pub async fn handle_offline(self) -> Result<(), CommandError> {
+        match self.command {
+            Command::Inspect { ref car_path } => Self::inspect(car_path).await,
+            _ => Err(CommandError::Error(
+                "Command requires a server connection".to_string(),
+            )),
+        }
+    }
+
+    /// Encrypts and adds a file, returning its cid and manifest.
+    pub(crate) async fn add<F>(
+        client: &Client,
+        file_path: F,
+        replication: &ReplicationArgs,
+        ttl: Option<u64>,
+        cid_version: Option<CidVersion>,
+        encryption: &EncryptionArgs,
+        config: &mut Config,
+    ) -> Result<(String, Manifest), CommandError>
     where
         F: AsRef<Path> + Into<String> + Debug + std::marker::Copy,
     {
-        let encryption_key = config.encryption_key()?;
+        let mut recipients = encryption
+            .recipient
+            .iter()
+            .map(|recipient| config.recipient(recipient))
+            .collect::<Result<Vec<_>, _>>()?;
+        recipients.extend(config.recipients().map(|(_, public_key)| *public_key));
 
-        let mut file = File::open(file_path).await?;
-        let mut contents = vec![];
-        file.read_to_end(&mut contents).await?;
+        let compression = encryption.compress.unwrap_or(Compression::None);
 
-        let data = Encryption::encrypt(encryption_key, &contents)
-            .map_err(|err| CommandError::Aead(err.to_string()))?;
-        let data = bytes_to_string_literal(&data);
+        // files shared with anyone are encrypted with a data key of their own
+        let mut nonce_prefix = None;
+        let (encryption_key, prefix) = if encryption.convergent {
+            // convergent keys come from the compressed bytes, so should the compressor's
+            // output change, the key and nonce prefix change with it
+            let file = CompressReader::new(compression, File::open(file_path).await?);
+            let convergent = Convergent::derive(config.encryption_key()?, file).await?;
+            let envelope =
+                Envelope::convergent(&convergent, config.encryption_key()?, &recipients)?;
+            nonce_prefix = Some(convergent.nonce_prefix());
+            (convergent.data_key(), envelope.to_bytes())
+        } else if recipients.is_empty() {
+            (Zeroizing::new(config.encryption_key()?.to_vec()), vec![])
+        } else {
+            let data_key = Envelope::generate_data_key();
+            let mut envelope = Envelope::default();
+            envelope.wrap_for_key(&data_key, config.encryption_key()?)?;
+            for recipient in &recipients {
+                envelope.wrap_for_recipient(&data_key, recipient)?;
+            }
+            (data_key, envelope.to_bytes())
+        };
+
+        let file = File::open(file_path).await?;
+        let metadata = file.metadata().await?;
+        let mut reader = CompressReader::new(compression, DigestReader::new(file));
 
-        let add_response = client.add(data.as_bytes().to_vec()).await?;
+        // encrypted segments are uploaded as they are written, so the file is never held
+        // in memory whole
+        let (pipe, uploaded) = io::duplex(SEGMENT_SIZE);
+        let encrypt = async {
+            let mut pipe = pipe;
+            pipe.write_all(&prefix).await?;
+            let mut writer = match nonce_prefix {
+                Some(nonce_prefix) => {
+                    EncryptWriter::with_nonce_prefix(&encryption_key, nonce_prefix, pipe)
+                }
+                None => EncryptWriter::new(&encryption_key, pipe),
+            }
+            .compressed(compression);
+            io::copy(&mut reader, &mut writer).await?;
+            writer.shutdown().await
+        };
+        let upload = transfer::upload(client, transfer::encode_json_array(uploaded));
+        let (_, upload) = tokio::try_join!(encrypt, upload)?;
+
+        let digest = reader.into_inner().finish();
+        let manifest = Manifest::new(file_path.as_ref(), &metadata, digest);
+
+        let add_response = client
+            .add_upload(upload, replication.replication_factor(), ttl, cid_version)
+            .await?;
+        if encryption.convergent {
+            Self::report_identical(client, config, &add_response.hash).await?;
+        }
+        let sealed = manifest.seal(&encryption_key, prefix).await?;
+        Self::write_manifest(client, &add_response.hash, &sealed).await?;
         config.add_hash(file_path, add_response.hash.clone());
         println!("File {:?} added to ipfs: {}", file_path, &add_response.hash);
 
+        Ok((add_response.hash, manifest))
+    }
+
+    /// Reads the encrypted file at `hash` a chunk at a time, decoded from the json array
+    /// it is stored as.
+    fn cat(client: &Client, hash: IpfsCid) -> impl AsyncRead + Unpin + '_ {
+        transfer::decode_json_array(transfer::cat(client, hash))
+    }
+
+    /// Says when a convergent file was already in the cluster, which anyone who can list
+    /// cids can tell as well.
+    async fn report_identical(
+        client: &Client,
+        config: &Config,
+        hash: &str,
+    ) -> Result<(), CommandError> {
+        let name = match config.file_path(hash) {
+            Some(file_path) => Some(file_path.to_string()),
+            None => Self::read_manifest(client, config, hash)
+                .await?
+                .map(|manifest| manifest.name),
+        };
+        if let Some(name) = name {
+            println!(
+                "Identical to {} already in the cluster as {}. Convergent encryption shows \
+                 this to anyone who can list cids",
+                name, hash
+            );
+        }
+
         Ok(())
     }
 
-    async fn get<H>(
+    async fn get(
         client: &Client,
         config: &Config,
-        hash: Option<H>,
-        file_path: Option<H>,
-    ) -> Result<(), CommandError>
-    where
-        H: Into<String> + Debug + std::clone::Clone,
-    {
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+        out: Option<String>,
+        print: bool,
+        verify_only: bool,
+    ) -> Result<(), CommandError> {
         let hash = Self::handle_file_args(config, hash, file_path)?;
-        let encryption_key = config.encryption_key()?;
 
-        let cat_response = client.cat(hash.clone()).await?;
-        let data = string_literal_to_bytes(&cat_response)?;
-        let decrypted_data = Encryption::decrypt(encryption_key, &data)
-            .map_err(|err| CommandError::Aead(err.to_string()))?;
+        // only the envelope and header are read up front, the payload is decrypted as it
+        // arrives
+        let mut data = Self::cat(client, hash);
+        let mut head = Vec::new();
+        (&mut data)
+            .take(MAX_PREFIX_SIZE)
+            .read_to_end(&mut head)
+            .await?;
+        let (encryption_key, payload_start) = {
+            let (encryption_key, payload) = config.decryption_key(&head)?;
+            (encryption_key, head.len() - payload.len())
+        };
+        let mut payload = std::io::Cursor::new(head);
+        payload.set_position(payload_start as u64);
+        let manifest = Self::read_manifest(client, config, &hash.to_string()).await?;
+        let mut reader =
+            DigestReader::new(DecryptReader::new(&encryption_key, payload.chain(data)));
 
-        println!(
-            "Ipfs file {:?} contents:\n{}",
+        if verify_only {
+            let manifest = manifest.ok_or_else(|| {
+                CommandError::Error(format!("{} has no manifest to verify it against", hash))
+            })?;
+            io::copy(&mut reader, &mut io::sink()).await?;
+            manifest.verify(&reader.finish())?;
+            println!("Ipfs file {} matches its manifest", hash);
+            return Ok(());
+        }
+
+        // restore the file under its original name, unless told where to put it
+        let out = match (out, print) {
+            (Some(out), _) => Some(out),
+            (None, true) => None,
+            (None, false) => match manifest.as_ref().and_then(Manifest::file_name) {
+                Some(name) if Path::new(name).exists() => {
+                    return Err(CommandError::Error(format!(
+                        "{} already exists, pass --out to write the file elsewhere",
+                        name
+                    )));
+                }
+                name => name.map(String::from),
+            },
+        };
+
+        match out {
+            Some(out) => {
+                // only moved to `out` once verified, so a bad file never replaces a good one
+                let partial = format!("{}.partial", out);
+                let mut file = File::create(&partial).await?;
+                let written = async {
+                    let len = io::copy(&mut reader, &mut file).await?;
+                    file.flush().await?;
+                    Self::verify(hash, manifest.as_ref(), reader.finish())?;
+                    Ok::<_, CommandError>(len)
+                }
+                .await;
+                let len = match written {
+                    Ok(len) => len,
+                    Err(err) => {
+                        fs::remove_file(&partial).await?;
+                        return Err(err);
+                    }
+                };
+                fs::rename(&partial, &out).await?;
+                println!("Ipfs file {} written to {} ({} bytes)", hash, out, len);
+            }
+            None => {
+                let mut decrypted_data = Vec::new();
+                reader.read_to_end(&mut decrypted_data).await?;
+                Self::verify(hash, manifest.as_ref(), reader.finish())?;
+                println!(
+                    "Ipfs file {} contents:\n{}",
+                    hash,
+                    String::from_utf8_lossy(&decrypted_data)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks a decrypted file against its manifest. Files added before manifests were
+    /// written have no digest to check.
+    fn verify(
+        hash: IpfsCid,
+        manifest: Option<&Manifest>,
+        digest: Digested,
+    ) -> Result<(), CommandError> {
+        match manifest {
+            Some(manifest) => manifest.verify(&digest)?,
+            None => eprintln!(
+                "Ipfs file {} has no manifest, its contents were not verified",
+                hash
+            ),
+        }
+        Ok(())
+    }
+
+    async fn reencrypt(
+        client: &Client,
+        config: &mut Config,
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+        replication: ReplicationArgs,
+    ) -> Result<(), CommandError> {
+        let hash = Self::handle_file_args(config, hash, file_path)?;
+        let encryption_key = Zeroizing::new(config.encryption_key()?.to_vec());
+        let key_id = KeyId::of(&encryption_key);
+
+        let mut data = Vec::new();
+        Self::cat(client, hash).read_to_end(&mut data).await?;
+
+        let (key, prefix, payload) = match Envelope::parse(&data)? {
+            // shared files keep their data key, only its wrapping changes
+            Some((mut envelope, payload)) => {
+                if envelope.key_ids().any(|id| id == key_id) {
+                    println!("{} is already encrypted with the active key", hash);
+                    return Ok(());
+                }
+                let data_key = config.data_key(&envelope)?;
+                envelope.wrap_for_key(&data_key, &encryption_key)?;
+                (data_key, envelope.to_bytes(), payload.to_vec())
+            }
+            None => {
+                if let Some(header) = Header::parse(&data)? {
+                    if header.key_id == key_id {
+                        println!("{} is already encrypted with the active key", hash);
+                        return Ok(());
+                    }
+                }
+                let (old_key, payload) = config.decryption_key(&data)?;
+                let payload = Self::reencrypt_payload(&old_key, payload, &encryption_key).await?;
+                (encryption_key, vec![], payload)
+            }
+        };
+
+        let new_hash =
+            Self::replace(client, config, hash, &key, prefix, &payload, &replication).await?;
+        println!("Reencrypted {} as {}", hash, new_hash);
+
+        Ok(())
+    }
+
+    async fn share(
+        client: &Client,
+        config: &mut Config,
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+        recipients: Vec<String>,
+        replication: ReplicationArgs,
+    ) -> Result<(), CommandError> {
+        let hash = Self::handle_file_args(config, hash, file_path)?;
+        let recipients = recipients
+            .iter()
+            .map(|recipient| config.recipient(recipient))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut data = Vec::new();
+        Self::cat(client, hash).read_to_end(&mut data).await?;
+
+        let existing = Envelope::parse(&data)?;
+        let converted = existing.is_none();
+        let (mut envelope, data_key, payload) = match existing {
+            Some((envelope, payload)) => {
+                let data_key = config.data_key(&envelope)?;
+                (envelope, data_key, payload.to_vec())
+            }
+            // files encrypted directly with a key move to a data key of their own
+            None => {
+                let (old_key, payload) = config.decryption_key(&data)?;
+                let data_key = Envelope::generate_data_key();
+                let mut envelope = Envelope::default();
+                envelope.wrap_for_key(&data_key, config.encryption_key()?)?;
+                let payload = Self::reencrypt_payload(&old_key, payload, &data_key).await?;
+                (envelope, data_key, payload)
+            }
+        };
+
+        let new_recipients = recipients
+            .iter()
+            .filter(|recipient| !envelope.has_recipient(recipient))
+            .collect::<Vec<_>>();
+        if new_recipients.is_empty() && !converted {
+            println!("{} is already shared with every recipient", hash);
+            return Ok(());
+        }
+        for recipient in new_recipients {
+            envelope.wrap_for_recipient(&data_key, recipient)?;
+        }
+
+        let new_hash = Self::replace(
+            client,
+            config,
             hash,
-            String::from_utf8_lossy(&decrypted_data)
+            &data_key,
+            envelope.to_bytes(),
+            &payload,
+            &replication,
+        )
+        .await?;
+        println!("Shared {} as {}", hash, new_hash);
+
+        Ok(())
+    }
+
+    /// Decrypts `payload` with `old_key` and encrypts it again with `new_key`, compressed
+    /// as it was.
+    async fn reencrypt_payload(
+        old_key: &[u8],
+        payload: &[u8],
+        new_key: &[u8],
+    ) -> Result<Vec<u8>, CommandError> {
+        let compression =
+            Header::parse(payload)?.map_or(Compression::None, |header| header.compression);
+        let mut reader = CompressReader::new(compression, DecryptReader::new(old_key, payload));
+        let mut writer = EncryptWriter::new(new_key, vec![]).compressed(compression);
+        io::copy(&mut reader, &mut writer).await?;
+        writer.shutdown().await?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Adds `payload` after `prefix` in place of the file at `hash`, keeping its path in the
+    /// config and its manifest, sealed with `encryption_key`, and unpins the old file.
+    async fn replace(
+        client: &Client,
+        config: &mut Config,
+        hash: IpfsCid,
+        encryption_key: &[u8],
+        prefix: Vec<u8>,
+        payload: &[u8],
+        replication: &ReplicationArgs,
+    ) -> Result<String, CommandError> {
+        let manifest = Self::read_manifest(client, config, &hash.to_string()).await?;
+
+        let data = [prefix.as_slice(), payload].concat();
+        let upload = transfer::upload(client, transfer::encode_json_array(&data[..])).await?;
+        let add_response = client
+            .add_upload(upload, replication.replication_factor(), None, None)
+            .await?;
+        if let Some(manifest) = manifest {
+            let sealed = manifest.seal(encryption_key, prefix).await?;
+            Self::write_manifest(client, &add_response.hash, &sealed).await?;
+            Self::remove_manifest(client, &hash.to_string()).await?;
+        }
+        let file_path = config
+            .remove_hash(&hash.to_string())
+            .unwrap_or_else(|| hash.to_string());
+        config.add_hash(file_path, add_response.hash.clone());
+
+        client.pin(PinAction::rm, Some(hash), None, None).await?;
+
+        Ok(add_response.hash)
+    }
+
+    async fn ls(client: &Client, config: &Config) -> Result<(), CommandError> {
+        let dir = MANIFEST_DIR.parse::<MfsPath>()?;
+        let mut hashes = match MfsClient::stat(client, Some(dir.clone())).await {
+            Ok(_) => MfsClient::ls(client, Some(dir))
+                .await?
+                .entries
+                .unwrap_or_default()
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<String>>(),
+            Err(_) => vec![],
+        };
+        let mut peer_manifests = Self::peer_manifests(client).await?;
+        peer_manifests.retain(|hash, _| !hashes.contains(hash));
+        hashes.extend(peer_manifests.into_keys());
+        if hashes.is_empty() {
+            println!("No files have manifests");
+            return Ok(());
+        }
+
+        for hash in hashes {
+            match Self::read_manifest(client, config, &hash).await {
+                Ok(Some(manifest)) => println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    hash,
+                    manifest.name,
+                    manifest.size,
+                    manifest.mime,
+                    manifest
+                        .mtime
+                        .map_or_else(|| "-".to_string(), |mtime| mtime.to_string())
+                ),
+                Ok(None) => {}
+                Err(err) => println!("{}\t({})", hash, err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts the manifest of the file at `hash`, if it has one, from this node's tree or
+    /// else from the tree of the peer that added the file.
+    async fn read_manifest(
+        client: &Client,
+        config: &Config,
+        hash: &str,
+    ) -> Result<Option<Manifest>, CommandError> {
+        let path = Manifest::path(hash)?;
+        let sealed = if MfsClient::stat(client, Some(path.clone())).await.is_ok() {
+            MfsClient::read(client, path).await?
+        } else {
+            match Self::peer_manifests(client).await?.remove(hash) {
+                Some(cid) => IpfsClient::cat(client, cid).await?,
+                None => return Ok(None),
+            }
+        };
+
+        let sealed = string_literal_to_bytes(&sealed)?;
+        let (encryption_key, payload) = config.decryption_key(&sealed)?;
+        Ok(Some(Manifest::open(&encryption_key, payload)?))
+    }
+
+    /// Finds the manifests in the trees peers gossiped, by the cid of their file. Peers
+    /// whose trees cannot be listed are skipped.
+    async fn peer_manifests(client: &Client) -> Result<HashMap<String, IpfsCid>, CommandError> {
+        let dir_name = MANIFEST_DIR.trim_start_matches('/');
+        let mut manifests = HashMap::new();
+        for peer in MfsClient::roots(client).await?.roots {
+            let Ok(root) = IpfsClient::ls(client, peer.root).await else {
+                continue;
+            };
+            let Some(dir) = root.links.into_iter().find(|link| link.name == dir_name) else {
+                continue;
+            };
+            let Ok(dir) = dir.hash.parse::<IpfsCid>() else {
+                continue;
+            };
+            let Ok(dir) = IpfsClient::ls(client, dir).await else {
+                continue;
+            };
+            for link in dir.links {
+                if let Ok(cid) = link.hash.parse::<IpfsCid>() {
+                    manifests.entry(link.name).or_insert(cid);
+                }
+            }
+        }
+
+        Ok(manifests)
+    }
+
+    async fn write_manifest(
+        client: &Client,
+        hash: &str,
+        sealed: &[u8],
+    ) -> Result<(), CommandError> {
+        MfsClient::mkdir(client, MANIFEST_DIR.parse()?, Some(true)).await?;
+        let data = bytes_to_string_literal(sealed);
+        MfsClient::write(client, Manifest::path(hash)?, data.into_bytes(), Some(true)).await?;
+
+        Ok(())
+    }
+
+    async fn remove_manifest(client: &Client, hash: &str) -> Result<(), CommandError> {
+        let path = Manifest::path(hash)?;
+        if MfsClient::stat(client, Some(path.clone())).await.is_ok() {
+            MfsClient::rm(client, path, None).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn stat(
+        client: &Client,
+        config: &Config,
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+    ) -> Result<(), CommandError> {
+        let hash = Self::handle_file_args(config, hash, file_path)?;
+        let response = IpfsClient::stat(client, hash).await?;
+        println!(
+            "{}: {} bytes in {} blocks",
+            response.hash, response.cumulative_size, response.num_blocks
         );
 
         Ok(())
@@ -117,8 +831,16 @@ impl FileCommand {
     async fn pin(client: &Client, config: &mut Config, pin: Pin) -> Result<(), CommandError> {
         let pin_response = match pin {
             Pin::Ls => Self::pin_ls(client).await?,
-            Pin::Add { hash, file_path } => Self::pin_add(client, config, hash, file_path).await?,
+            Pin::Add {
+                hash,
+                file_path,
+                replication,
+                ttl,
+            } => Self::pin_add(client, config, hash, file_path, replication, ttl).await?,
             Pin::Rm { hash, file_path } => Self::pin_rm(client, config, hash, file_path).await?,
+            Pin::Status { hash, file_path } => {
+                return Self::pin_status(client, config, hash, file_path).await;
+            }
         };
 
         match pin_response {
@@ -130,50 +852,168 @@ impl FileCommand {
     }
 
     async fn pin_ls(client: &Client) -> Result<IpfsPinResponse, CommandError> {
-        let response = client.pin(PinAction::ls, None).await?;
+        let response = client.pin(PinAction::ls, None, None, None).await?;
         Ok(response)
     }
 
-    async fn pin_add<H>(
+    async fn pin_add(
         client: &Client,
         config: &Config,
-        hash: Option<H>,
-        file_path: Option<H>,
-    ) -> Result<IpfsPinResponse, CommandError>
-    where
-        H: Into<String>,
-    {
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+        replication: ReplicationArgs,
+        ttl: Option<u64>,
+    ) -> Result<IpfsPinResponse, CommandError> {
         let hash = Self::handle_file_args(config, hash, file_path)?;
-        let response = client.pin(PinAction::add, Some(hash)).await?;
+        let response = client
+            .pin(
+                PinAction::add,
+                Some(hash),
+                replication.replication_factor(),
+                ttl,
+            )
+            .await?;
 
         Ok(response)
     }
 
-    async fn pin_rm<H>(
+    async fn pin_rm(
         client: &Client,
         config: &mut Config,
-        hash: Option<H>,
-        file_path: Option<H>,
-    ) -> Result<IpfsPinResponse, CommandError>
-    where
-        H: Into<String>,
-    {
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+    ) -> Result<IpfsPinResponse, CommandError> {
         let hash = Self::handle_file_args(config, hash, file_path)?;
-        config.remove_hash(&hash);
-        let response = client.pin(PinAction::rm, Some(hash)).await?;
+        Self::remove(client, config, hash).await
+    }
+
+    /// Unpins a file across the cluster and forgets it, along with its manifest.
+    pub(crate) async fn remove(
+        client: &Client,
+        config: &mut Config,
+        hash: IpfsCid,
+    ) -> Result<IpfsPinResponse, CommandError> {
+        config.remove_hash(&hash.to_string());
+        let response = client.pin(PinAction::rm, Some(hash), None, None).await?;
+        Self::remove_manifest(client, &hash.to_string()).await?;
 
         Ok(response)
     }
 
-    fn handle_file_args<H>(
+    async fn pin_status(
+        client: &Client,
         config: &Config,
-        hash: Option<H>,
-        file_path: Option<H>,
-    ) -> Result<String, CommandError>
-    where
-        H: Into<String>,
-    {
-        let hash: String = match (hash, file_path) {
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+    ) -> Result<(), CommandError> {
+        let hash = Self::handle_file_args(config, hash, file_path)?;
+        let response = client.pin_status(hash).await?;
+
+        if response.peers.is_empty() {
+            println!("No pin status reported for {}", response.hash);
+            return Ok(());
+        }
+
+        println!("Pin status for {}:", response.hash);
+        for peer in response.peers {
+            let status = peer.status;
+            match status.error {
+                Some(error) => println!(
+                    "  {}: {:?} (retries: {}, error: {})",
+                    peer.peer_id, status.state, status.retries, error
+                ),
+                None => println!(
+                    "  {}: {:?} (retries: {})",
+                    peer.peer_id, status.state, status.retries
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn export(
+        client: &Client,
+        config: &Config,
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+        out: String,
+    ) -> Result<(), CommandError> {
+        let hash = Self::handle_file_args(config, hash, file_path)?;
+        let download = client.dag_export(hash).await?;
+        let mut car = transfer::download(client, download);
+        let mut file = File::create(&out).await?;
+        let size = io::copy(&mut car, &mut file).await?;
+        file.flush().await?;
+        println!("Exported {} to {} ({} bytes)", hash, out, size);
+
+        Ok(())
+    }
+
+    async fn import(
+        client: &Client,
+        car_path: String,
+        replication: ReplicationArgs,
+        ttl: Option<u64>,
+    ) -> Result<(), CommandError> {
+        let car = File::open(&car_path).await?;
+        let transfer = transfer::upload(client, car).await?;
+        let response = client
+            .dag_import(transfer, replication.replication_factor(), ttl)
+            .await?;
+        for root in response.roots {
+            println!("Imported and pinned {} from {}", root, car_path);
+        }
+
+        Ok(())
+    }
+
+    async fn inspect(car_path: &str) -> Result<(), CommandError> {
+        let car = fs::read(car_path).await?;
+        let car = read_car(&car).map_err(|err| CommandError::Error(err.to_string()))?;
+
+        for root in &car.roots {
+            println!("Root: {}", root);
+        }
+        println!(
+            "{} blocks, {} bytes",
+            car.blocks.len(),
+            car.blocks.iter().map(|b| b.data.len()).sum::<usize>()
+        );
+        for block in &car.blocks {
+            println!("  {}\t{}", block.cid, block.data.len());
+        }
+
+        Ok(())
+    }
+
+    async fn publish(
+        client: &Client,
+        config: &Config,
+        name: String,
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+    ) -> Result<(), CommandError> {
+        let hash = Self::handle_file_args(config, hash, file_path)?;
+        let response = client.name_publish(hash, Some(name)).await?;
+        println!("Published {} to /ipns/{}", response.value, response.name);
+
+        Ok(())
+    }
+
+    async fn resolve(client: &Client, name: String) -> Result<(), CommandError> {
+        let response = client.name_resolve(name).await?;
+        println!("{}", response.path);
+
+        Ok(())
+    }
+
+    fn handle_file_args(
+        config: &Config,
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+    ) -> Result<IpfsCid, CommandError> {
+        let hash = match (hash, file_path) {
             (None, None) => {
                 return Err(CommandError::Error(
                     "Must pass either --hash or --file-path".to_string(),
@@ -184,14 +1024,49 @@ impl FileCommand {
                     "Cannot pass both --hash and --file-path".to_string(),
                 ));
             }
-            (None, Some(file_path)) => config.hash(file_path)?.into(),
-            (Some(hash), None) => hash.into(),
+            (None, Some(file_path)) => config.hash(file_path)?.parse()?,
+            (Some(hash), None) => hash,
         };
 
         Ok(hash)
     }
 }
 
+/// Parses a duration such as `604800`, `90s`, `30m`, `12h` or `7d` into seconds.
+fn parse_ttl(ttl: &str) -> Result<u64, String> {
+    let ttl = ttl.trim();
+    let (value, unit) = match ttl.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => ttl.split_at(index),
+        None => (ttl, "s"),
+    };
+
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| format!("Unable to parse ttl: {}", ttl))?;
+    let multiplier: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Unknown ttl unit: {}", unit)),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Ttl is too large: {}", ttl))
+}
+
+fn parse_cid_version(version: &str) -> Result<CidVersion, String> {
+    match version.trim() {
+        "0" => Ok(CidVersion::V0),
+        "1" => Ok(CidVersion::V1),
+        _ => Err(format!("Unsupported cid version: {}", version)),
+    }
+}
+
+/// Enough of an encrypted file to hold the largest envelope and the header after it.
+const MAX_PREFIX_SIZE: u64 = 64 * 1024;
+
 fn bytes_to_string_literal(bytes: &[u8]) -> String {
     let mut result = String::from("[");
 
@@ -225,4 +1100,14 @@ mod test {
 
         assert_eq!(expected.to_vec(), result);
     }
+
+    #[test]
+    fn parse_ttl_with_units() {
+        assert_eq!(parse_ttl("45").unwrap(), 45);
+        assert_eq!(parse_ttl("30m").unwrap(), 30 * 60);
+        assert_eq!(parse_ttl("7d").unwrap(), 7 * 24 * 60 * 60);
+        assert!(parse_ttl("1w").is_err());
+        assert!(parse_ttl("d").is_err());
+        assert!(parse_ttl("18446744073709551615d").is_err());
+    }
 }
@@ -0,0 +1,132 @@
+use clap::{Parser, Subcommand};
+use jsonrpsee::async_client::Client;
+use server::api::{
+    mfs::MfsClient,
+    types::{
+        ipfs::IpfsCid,
+        mfs::{MfsEntryType, MfsPath, MfsSource},
+    },
+};
+
+use super::{config::Config, error::CommandError};
+
+/// Organize files added to the cluster into folders
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct FsCommand {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Ls {
+        #[arg(long)]
+        path: Option<MfsPath>,
+    },
+
+    Mkdir {
+        #[arg(long)]
+        path: MfsPath,
+
+        /// Create any missing parent directories
+        #[arg(long)]
+        parents: bool,
+    },
+
+    /// Copy a folder entry, or a file added to the cluster, to `--to`
+    Cp {
+        /// A path such as /docs/a.txt, or /ipfs/<cid>
+        #[arg(long)]
+        from: Option<MfsSource>,
+
+        #[arg(long)]
+        hash: Option<IpfsCid>,
+
+        #[arg(long)]
+        file_path: Option<String>,
+
+        #[arg(long)]
+        to: MfsPath,
+    },
+
+    Mv {
+        #[arg(long)]
+        from: MfsPath,
+
+        #[arg(long)]
+        to: MfsPath,
+    },
+}
+
+impl FsCommand {
+    pub async fn handle(self, client: Client, config: &Config) -> Result<(), CommandError> {
+        match self.command {
+            Command::Ls { path } => Self::ls(&client, path).await?,
+            Command::Mkdir { path, parents } => Self::mkdir(&client, path, parents).await?,
+            Command::Cp {
+                from,
+                hash,
+                file_path,
+                to,
+            } => Self::cp(&client, config, from, hash, file_path, to).await?,
+            Command::Mv { from, to } => Self::mv(&client, from, to).await?,
+        };
+
+        Ok(())
+    }
+
+    async fn ls(client: &Client, path: Option<MfsPath>) -> Result<(), CommandError> {
+        let response = client.ls(path).await?;
+
+        for entry in response.entries.unwrap_or_default() {
+            match entry.entry_type {
+                MfsEntryType::Directory => println!("{}/\t{}", entry.name, entry.hash),
+                MfsEntryType::File => {
+                    println!("{}\t{}\t{}", entry.name, entry.hash, entry.size)
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn mkdir(client: &Client, path: MfsPath, parents: bool) -> Result<(), CommandError> {
+        let response = client.mkdir(path.clone(), Some(parents)).await?;
+        println!("Created {}, root is now {}", path, response.root);
+
+        Ok(())
+    }
+
+    async fn cp(
+        client: &Client,
+        config: &Config,
+        from: Option<MfsSource>,
+        hash: Option<IpfsCid>,
+        file_path: Option<String>,
+        to: MfsPath,
+    ) -> Result<(), CommandError> {
+        let from = match (from, hash, file_path) {
+            (Some(from), None, None) => from,
+            (None, Some(hash), None) => MfsSource::Ipfs(hash),
+            (None, None, Some(file_path)) => MfsSource::Ipfs(config.hash(file_path)?.parse()?),
+            _ => {
+                return Err(CommandError::Error(
+                    "Must pass exactly one of --from, --hash or --file-path".to_string(),
+                ));
+            }
+        };
+
+        let response = client.cp(from.clone(), to.clone()).await?;
+        println!("Copied {} to {}, root is now {}", from, to, response.root);
+
+        Ok(())
+    }
+
+    async fn mv(client: &Client, from: MfsPath, to: MfsPath) -> Result<(), CommandError> {
+        let response = client.mv(from.clone(), to.clone()).await?;
+        println!("Moved {} to {}, root is now {}", from, to, response.root);
+
+        Ok(())
+    }
+}
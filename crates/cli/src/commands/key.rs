@@ -0,0 +1,206 @@
+use clap::{Parser, Subcommand};
+use server::crypto::{
+    encryption::{Encryption, KeyId},
+    envelope::PublicKey,
+    hex,
+};
+use std::path::{Path, PathBuf};
+use tokio::{fs, io::AsyncWriteExt};
+use zeroize::Zeroizing;
+
+use super::{config::Config, error::CommandError};
+use crate::services::keystore::PASSPHRASE_ENV;
+
+/// Manage the keys files are encrypted with
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct KeyCommand {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the keys in the keyring and the key id each one writes into encrypted files
+    List,
+
+    /// Generate a new key and encrypt new files with it. Older keys are kept so files
+    /// encrypted with them can still be read, see `file reencrypt`
+    Rotate { name: String },
+
+    /// Add a key written by `key export` to the keyring
+    Import {
+        name: String,
+
+        #[arg(long)]
+        file: PathBuf,
+
+        /// Encrypt new files with the imported key
+        #[arg(long)]
+        activate: bool,
+    },
+
+    /// Write a key to a file, to import it on another machine
+    Export {
+        name: String,
+
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Encrypt new files with another key in the keyring
+    Use { name: String },
+
+    /// Remove a key. Files encrypted with it can no longer be decrypted
+    Delete { name: String },
+
+    /// Change the passphrase the keystore is sealed with
+    Passwd,
+
+    /// Print the public key teammates share files with, creating it on first use
+    Identity,
+
+    /// Manage the team list, whose recipients every new file is shared with
+    #[command(subcommand)]
+    Recipient(Recipient),
+}
+
+#[derive(Subcommand, Debug)]
+enum Recipient {
+    /// List the recipients in the team list
+    List,
+
+    /// Add a teammate's public key, printed by their `key identity`
+    Add { name: String, public_key: PublicKey },
+
+    /// Stop sharing new files with a recipient. Files already shared stay readable to them
+    Delete { name: String },
+}
+
+impl KeyCommand {
+    pub async fn handle(self, config: &mut Config) -> Result<(), CommandError> {
+        match self.command {
+            Command::List => Self::list(config),
+            Command::Rotate { name } => Self::rotate(config, name),
+            Command::Import {
+                name,
+                file,
+                activate,
+            } => Self::import(config, name, file, activate).await,
+            Command::Export { name, out } => Self::export(config, name, out).await,
+            Command::Use { name } => {
+                config.set_active_key(&name)?;
+                println!("New files will be encrypted with key {}", name);
+                Ok(())
+            }
+            Command::Delete { name } => {
+                config.remove_key(&name)?;
+                println!("Deleted key {}", name);
+                Ok(())
+            }
+            Command::Passwd => {
+                if std::env::var_os(PASSPHRASE_ENV).is_some() {
+                    return Err(CommandError::Error(format!(
+                        "Unset {} to change the passphrase",
+                        PASSPHRASE_ENV
+                    )));
+                }
+                config.change_passphrase()?;
+                println!("Keystore passphrase changed");
+                Ok(())
+            }
+            Command::Identity => {
+                println!("{}", config.identity()?.public_key());
+                Ok(())
+            }
+            Command::Recipient(recipient) => Self::recipient(config, recipient),
+        }
+    }
+
+    fn list(config: &Config) -> Result<(), CommandError> {
+        for (name, key_id) in config.keys() {
+            let active = if config.active_key_name() == Some(name) {
+                " (active)"
+            } else {
+                ""
+            };
+            println!("{}\t{}{}", name, key_id, active);
+        }
+
+        Ok(())
+    }
+
+    fn recipient(config: &mut Config, recipient: Recipient) -> Result<(), CommandError> {
+        match recipient {
+            Recipient::List => {
+                for (name, public_key) in config.recipients() {
+                    println!("{}\t{}", name, public_key);
+                }
+            }
+            Recipient::Add { name, public_key } => {
+                config.add_recipient(name.clone(), public_key)?;
+                println!("New files will be shared with {}", name);
+            }
+            Recipient::Delete { name } => {
+                config.remove_recipient(&name)?;
+                println!("Deleted recipient {}", name);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rotate(config: &mut Config, name: String) -> Result<(), CommandError> {
+        let key = Encryption::generate_key();
+        config.add_key(name.clone(), key.to_vec())?;
+        config.set_active_key(&name)?;
+        println!(
+            "New files will be encrypted with key {} ({})",
+            name,
+            KeyId::of(&key)
+        );
+
+        Ok(())
+    }
+
+    async fn import(
+        config: &mut Config,
+        name: String,
+        file: PathBuf,
+        activate: bool,
+    ) -> Result<(), CommandError> {
+        let key = read_key_file(&file).await?;
+        let key_id = KeyId::of(&key);
+
+        config.add_key(name.clone(), key)?;
+        if activate {
+            config.set_active_key(&name)?;
+        }
+        println!("Imported key {} ({})", name, key_id);
+
+        Ok(())
+    }
+
+    async fn export(config: &Config, name: String, out: PathBuf) -> Result<(), CommandError> {
+        let key = config.key(&name)?;
+        let contents = Zeroizing::new(hex::encode(key) + "\n");
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&out).await?;
+        file.write_all(contents.as_bytes()).await?;
+        println!("Exported key {} to {}", name, out.display());
+
+        Ok(())
+    }
+}
+
+/// Reads a key written by `key export`.
+pub(crate) async fn read_key_file(file: &Path) -> Result<Vec<u8>, CommandError> {
+    let contents = Zeroizing::new(fs::read_to_string(file).await?);
+    hex::decode(contents.trim())
+        .filter(|key| key.len() == 32)
+        .ok_or_else(|| CommandError::Error(format!("{} does not hold a key", file.display())))
+}
@@ -2,4 +2,7 @@ pub(crate) mod config;
 pub(crate) mod create_key;
 pub(crate) mod error;
 pub(crate) mod file;
+pub(crate) mod fs;
+pub(crate) mod key;
+pub(crate) mod sync;
 pub(crate) mod util;
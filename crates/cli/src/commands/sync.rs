@@ -0,0 +1,618 @@
+use clap::Parser;
+use home::home_dir;
+use jsonrpsee::async_client::Client;
+use notify::{
+    event::{AccessKind, AccessMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::Metadata,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File},
+    io, signal,
+    sync::mpsc,
+    time,
+};
+
+use super::{
+    config::{write_private, Config},
+    error::CommandError,
+    file::{EncryptionArgs, FileCommand, ReplicationArgs},
+};
+use crate::services::manifest::DigestReader;
+
+const SYNC_INDEX_FILE_NAME: &str = ".local_ipfs_sync.json";
+/// How long watch mode lets changes settle before syncing them, so a file being written is
+/// added once.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Mirror a local directory into the cluster: add new and changed files and unpin the files
+/// of those deleted
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct SyncCommand {
+    dir: PathBuf,
+
+    /// Keep running and sync the directory whenever the file system reports a change
+    #[arg(long)]
+    watch: bool,
+
+    #[command(flatten)]
+    replication: ReplicationArgs,
+
+    #[command(flatten)]
+    encryption: EncryptionArgs,
+}
+
+/// What each synced directory held when it was last synced, by path relative to it. Kept
+/// next to the config, which maps cids to paths, as the config is only written on exit.
+#[derive(Serialize, Deserialize, Default)]
+struct SyncIndex(BTreeMap<PathBuf, DirIndex>);
+
+type DirIndex = BTreeMap<String, SyncedFile>;
+
+/// A synced file's cid, the digest of what was added, and its size and modification time
+/// in nanoseconds when it was synced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SyncedFile {
+    hash: String,
+    content_hash: String,
+    size: u64,
+    mtime: Option<u64>,
+}
+
+impl SyncedFile {
+    /// Whether the file looks as it did when synced, without reading it.
+    fn is_unmodified(&self, metadata: &Metadata) -> bool {
+        let mtime = mtime(metadata);
+        mtime.is_some() && self.mtime == mtime && self.size == metadata.len()
+    }
+}
+
+impl SyncIndex {
+    async fn load() -> Result<Self, CommandError> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path).await?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    async fn save(&self) -> Result<(), CommandError> {
+        write_private(Self::path()?, serde_json::to_string(self)?).await
+    }
+
+    fn path() -> Result<PathBuf, CommandError> {
+        let mut path =
+            home_dir().ok_or_else(|| CommandError::Error("Unable to get home directory".into()))?;
+        path.push(SYNC_INDEX_FILE_NAME);
+        Ok(path)
+    }
+}
+
+#[derive(Default)]
+struct Synced {
+    added: usize,
+    unpinned: usize,
+    unchanged: usize,
+    failed: usize,
+}
+
+/// How a directory differs from its index: the files to add, new or changed, and the
+/// index entries of deleted ones. Entries of touched but unchanged files are updated in
+/// place.
+#[derive(Default, Debug, PartialEq)]
+struct Diff {
+    changed: Vec<ChangedFile>,
+    deleted: Vec<String>,
+    unchanged: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct ChangedFile {
+    relative: String,
+    path: PathBuf,
+    size: u64,
+    mtime: Option<u64>,
+}
+
+impl Diff {
+    /// Compares the files under `dir` with `files`. Unmodified files are skipped without
+    /// reading them, touched ones are hashed and only count as changed if their contents
+    /// did. The metadata is from before a file is read, so changes made while it is added
+    /// are picked up by the next sync.
+    async fn of(dir: &Path, files: &mut DirIndex, ignored: &[PathBuf]) -> io::Result<Self> {
+        let mut diff = Self::default();
+        let mut seen = BTreeSet::new();
+
+        for path in walk(dir, ignored).await? {
+            let metadata = match fs::metadata(&path).await {
+                Ok(metadata) => metadata,
+                // deleted since the walk, unpinned below
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let relative = path
+                .strip_prefix(dir)
+                .expect("walked paths are under the directory")
+                .to_string_lossy()
+                .into_owned();
+            seen.insert(relative.clone());
+
+            if let Some(file) = files.get_mut(&relative) {
+                if file.is_unmodified(&metadata) {
+                    diff.unchanged += 1;
+                    continue;
+                }
+                if file.content_hash == content_hash(&path).await? {
+                    file.size = metadata.len();
+                    file.mtime = mtime(&metadata);
+                    diff.unchanged += 1;
+                    continue;
+                }
+            }
+
+            diff.changed.push(ChangedFile {
+                relative,
+                path,
+                size: metadata.len(),
+                mtime: mtime(&metadata),
+            });
+        }
+
+        diff.deleted = files
+            .keys()
+            .filter(|relative| !seen.contains(*relative))
+            .cloned()
+            .collect();
+
+        Ok(diff)
+    }
+}
+
+impl SyncCommand {
+    pub async fn handle(self, client: Client, config: &mut Config) -> Result<(), CommandError> {
+        let dir = fs::canonicalize(&self.dir).await?;
+        if !fs::metadata(&dir).await?.is_dir() {
+            return Err(CommandError::Error(format!(
+                "{} is not a directory",
+                self.dir.display()
+            )));
+        }
+
+        let mut index = SyncIndex::load().await?;
+        self.sync_and_save(&client, config, &dir, &mut index)
+            .await?;
+        if self.watch {
+            self.watch(&client, config, &dir, &mut index).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Syncs `dir` on every change until interrupted. A failed sync is reported and tried
+    /// again on the next change.
+    async fn watch(
+        &self,
+        client: &Client,
+        config: &mut Config,
+        dir: &Path,
+        index: &mut SyncIndex,
+    ) -> Result<(), CommandError> {
+        let ignored = ignored_paths()?;
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+        println!(
+            "Watching {} for changes, press ctrl-c to stop",
+            dir.display()
+        );
+
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(Ok(event)) if is_change(&event, &ignored) => {}
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => {
+                        eprintln!("Error watching {}: {}", dir.display(), err);
+                        continue;
+                    }
+                    None => break,
+                },
+                _ = signal::ctrl_c() => break,
+            }
+
+            time::sleep(SETTLE_TIME).await;
+            while events.try_recv().is_ok() {}
+            if let Err(err) = self.sync_and_save(client, config, dir, index).await {
+                eprintln!("Error syncing {}: {}", dir.display(), err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Syncs `dir` and saves the index, even when syncing fails part way, so files already
+    /// added are not added again. Fails after saving if any file failed to sync.
+    async fn sync_and_save(
+        &self,
+        client: &Client,
+        config: &mut Config,
+        dir: &Path,
+        index: &mut SyncIndex,
+    ) -> Result<(), CommandError> {
+        let files = index.0.entry(dir.to_path_buf()).or_default();
+        let synced = self.sync(client, config, dir, files).await;
+        index.save().await?;
+
+        let synced = synced?;
+        println!(
+            "Synced {}: {} added, {} unpinned, {} unchanged, {} failed",
+            dir.display(),
+            synced.added,
+            synced.unpinned,
+            synced.unchanged,
+            synced.failed
+        );
+        if synced.failed > 0 {
+            return Err(CommandError::Error(format!(
+                "{} files failed to sync",
+                synced.failed
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn sync(
+        &self,
+        client: &Client,
+        config: &mut Config,
+        dir: &Path,
+        files: &mut DirIndex,
+    ) -> Result<Synced, CommandError> {
+        let diff = Diff::of(dir, files, &ignored_paths()?).await?;
+        let mut cluster = Cluster {
+            command: self,
+            client,
+            config,
+        };
+        Ok(diff.apply(files, &mut cluster).await)
+    }
+}
+
+/// Where a sync adds and unpins files.
+trait Target {
+    /// Adds the file at `path`, returning its cid and content hash.
+    async fn add(&mut self, path: &Path) -> Result<(String, String), CommandError>;
+
+    async fn remove(&mut self, hash: &str) -> Result<(), CommandError>;
+}
+
+struct Cluster<'a> {
+    command: &'a SyncCommand,
+    client: &'a Client,
+    config: &'a mut Config,
+}
+
+impl Target for Cluster<'_> {
+    async fn add(&mut self, path: &Path) -> Result<(String, String), CommandError> {
+        let (hash, manifest) = FileCommand::add(
+            self.client,
+            path.to_string_lossy().as_ref(),
+            &self.command.replication,
+            None,
+            None,
+            &self.command.encryption,
+            self.config,
+        )
+        .await?;
+        Ok((hash, manifest.content_hash))
+    }
+
+    async fn remove(&mut self, hash: &str) -> Result<(), CommandError> {
+        FileCommand::remove(self.client, self.config, hash.parse()?).await?;
+        Ok(())
+    }
+}
+
+impl Diff {
+    /// Adds the changed files and unpins the deleted ones, updating `files` as each one
+    /// succeeds. A file that fails is reported and not recorded, so the next sync tries it
+    /// again, without holding up the others.
+    async fn apply<T: Target>(self, files: &mut DirIndex, target: &mut T) -> Synced {
+        let mut synced = Synced {
+            unchanged: self.unchanged,
+            ..Synced::default()
+        };
+
+        for changed in self.changed {
+            let (hash, content_hash) = match target.add(&changed.path).await {
+                Ok(added) => added,
+                Err(err) => {
+                    eprintln!("Error adding {}: {}", changed.path.display(), err);
+                    synced.failed += 1;
+                    continue;
+                }
+            };
+            synced.added += 1;
+            let file = SyncedFile {
+                hash,
+                content_hash,
+                size: changed.size,
+                mtime: changed.mtime,
+            };
+            if let Some(old) = files.insert(changed.relative, file) {
+                if is_unused(files, &old) {
+                    if let Err(err) = target.remove(&old.hash).await {
+                        eprintln!("Error unpinning the old copy {}: {}", old.hash, err);
+                    }
+                }
+            }
+        }
+
+        for relative in self.deleted {
+            let old = files
+                .remove(&relative)
+                .expect("deleted files are in the index");
+            if is_unused(files, &old) {
+                if let Err(err) = target.remove(&old.hash).await {
+                    eprintln!("Error unpinning deleted file {}: {}", relative, err);
+                    // kept so the next sync tries again
+                    files.insert(relative, old);
+                    synced.failed += 1;
+                    continue;
+                }
+            }
+            println!("Unpinned deleted file {} ({})", relative, old.hash);
+            synced.unpinned += 1;
+        }
+
+        synced
+    }
+}
+
+/// Whether a file's old cid can be unpinned, which it cannot while another synced file
+/// still has it, as identical files do under convergent encryption.
+fn is_unused(files: &DirIndex, old: &SyncedFile) -> bool {
+    !files.values().any(|file| file.hash == old.hash)
+}
+
+/// The config and sync index, which are written on every sync and so are neither synced
+/// nor changes to watch for when the synced directory holds them, as the home directory
+/// does.
+fn ignored_paths() -> Result<Vec<PathBuf>, CommandError> {
+    [Config::config_path()?, SyncIndex::path()?]
+        .into_iter()
+        .map(|path| {
+            // canonical like the synced directory, though the file may not exist yet
+            let dir = path.parent().expect("home directory files have a parent");
+            let name = path.file_name().expect("home directory files have a name");
+            Ok(std::fs::canonicalize(dir)?.join(name))
+        })
+        .collect()
+}
+
+/// Whether an event can mean files were added, changed or deleted, unlike those for
+/// files being opened and read, by this command among others, or for the files a sync
+/// writes itself.
+fn is_change(event: &Event, ignored: &[PathBuf]) -> bool {
+    let is_change = match event.kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    };
+    is_change && (event.paths.is_empty() || event.paths.iter().any(|path| !ignored.contains(path)))
+}
+
+fn mtime(metadata: &Metadata) -> Option<u64> {
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(mtime.as_nanos()).ok()
+}
+
+/// The regular files under `dir` other than `ignored`, without following symlinks.
+async fn walk(dir: &Path, ignored: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() && !ignored.contains(&entry.path()) {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+async fn content_hash(path: &Path) -> io::Result<String> {
+    let mut reader = DigestReader::new(File::open(path).await?);
+    io::copy(&mut reader, &mut io::sink()).await?;
+    Ok(reader.finish().content_hash)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use notify::event::{ModifyKind, RemoveKind};
+
+    async fn synced(path: &Path, hash: &str) -> SyncedFile {
+        let metadata = fs::metadata(path).await.unwrap();
+        SyncedFile {
+            hash: hash.into(),
+            content_hash: content_hash(path).await.unwrap(),
+            size: metadata.len(),
+            mtime: mtime(&metadata),
+        }
+    }
+
+    #[tokio::test]
+    async fn diff_finds_added_changed_and_deleted_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        for name in ["unchanged", "touched", "changed", "deleted"] {
+            fs::write(path(name), name).await.unwrap();
+        }
+        let mut files = DirIndex::new();
+        for name in ["unchanged", "touched", "changed", "deleted"] {
+            files.insert(name.into(), synced(&path(name), name).await);
+        }
+
+        files.get_mut("touched").unwrap().mtime = Some(0);
+        fs::write(path("changed"), "changed contents")
+            .await
+            .unwrap();
+        fs::remove_file(path("deleted")).await.unwrap();
+        fs::write(path("added"), "added").await.unwrap();
+
+        let diff = Diff::of(dir.path(), &mut files, &[]).await.unwrap();
+        let changed = diff
+            .changed
+            .iter()
+            .map(|file| file.relative.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec!["added", "changed"]);
+        assert_eq!(diff.changed[1].size, "changed contents".len() as u64);
+        assert_eq!(diff.deleted, vec!["deleted".to_string()]);
+        assert_eq!(diff.unchanged, 2);
+        // touched files keep their cid and are not read again next time
+        assert_eq!(files["touched"], synced(&path("touched"), "touched").await);
+    }
+
+    /// Adds every file but `failing`, named by its path, and records what was unpinned.
+    #[derive(Default)]
+    struct FakeTarget {
+        failing: PathBuf,
+        removed: Vec<String>,
+    }
+
+    impl Target for FakeTarget {
+        async fn add(&mut self, path: &Path) -> Result<(String, String), CommandError> {
+            if path == self.failing {
+                return Err(CommandError::Error("add failed".into()));
+            }
+            let name = path.to_string_lossy().into_owned();
+            Ok((name.clone(), name))
+        }
+
+        async fn remove(&mut self, hash: &str) -> Result<(), CommandError> {
+            self.removed.push(hash.into());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failing_file_does_not_hold_up_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        for name in ["a", "failing", "c", "deleted"] {
+            fs::write(path(name), name).await.unwrap();
+        }
+        let mut files = DirIndex::new();
+        files.insert("deleted".into(), synced(&path("deleted"), "deleted").await);
+        fs::remove_file(path("deleted")).await.unwrap();
+
+        let diff = Diff::of(dir.path(), &mut files, &[]).await.unwrap();
+        let mut target = FakeTarget {
+            failing: path("failing"),
+            ..FakeTarget::default()
+        };
+        let synced = diff.apply(&mut files, &mut target).await;
+
+        assert_eq!(synced.added, 2);
+        assert_eq!(synced.failed, 1);
+        assert_eq!(synced.unpinned, 1);
+        assert_eq!(target.removed, vec!["deleted".to_string()]);
+        // the failed file is left out of the index to be added on the next sync
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec!["a", "c"]);
+        let diff = Diff::of(dir.path(), &mut files, &[]).await.unwrap();
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].relative, "failing");
+    }
+
+    #[test]
+    fn cids_shared_by_identical_files_are_unpinned_with_the_last_of_them() {
+        let file = |hash: &str| SyncedFile {
+            hash: hash.into(),
+            content_hash: hash.into(),
+            size: 1,
+            mtime: None,
+        };
+        let mut files = DirIndex::from([
+            ("a".into(), file("shared")),
+            ("b".into(), file("shared")),
+            ("c".into(), file("other")),
+        ]);
+
+        let a = files.remove("a").unwrap();
+        assert!(!is_unused(&files, &a));
+        let b = files.remove("b").unwrap();
+        assert!(is_unused(&files, &b));
+    }
+
+    #[tokio::test]
+    async fn walk_finds_nested_files_without_following_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("logs/2024");
+        fs::create_dir_all(&nested).await.unwrap();
+        fs::write(dir.path().join("a.txt"), b"a").await.unwrap();
+        fs::write(nested.join("b.json"), b"{}").await.unwrap();
+        fs::write(dir.path().join(SYNC_INDEX_FILE_NAME), b"{}")
+            .await
+            .unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("a.txt"), dir.path().join("link")).unwrap();
+
+        assert_eq!(
+            walk(dir.path(), &[dir.path().join(SYNC_INDEX_FILE_NAME)])
+                .await
+                .unwrap(),
+            vec![dir.path().join("a.txt"), nested.join("b.json")]
+        );
+    }
+
+    #[test]
+    fn reads_and_own_writes_are_not_changes() {
+        let index = PathBuf::from("/home/user").join(SYNC_INDEX_FILE_NAME);
+        let ignored = [index.clone()];
+        let event = |kind, path: &PathBuf| Event::new(kind).add_path(path.clone());
+        let notes = PathBuf::from("/home/user/notes.txt");
+
+        assert!(!is_change(
+            &event(
+                EventKind::Access(AccessKind::Open(AccessMode::Read)),
+                &notes
+            ),
+            &ignored
+        ));
+        assert!(is_change(
+            &event(
+                EventKind::Access(AccessKind::Close(AccessMode::Write)),
+                &notes
+            ),
+            &ignored
+        ));
+        assert!(is_change(
+            &event(EventKind::Remove(RemoveKind::File), &notes),
+            &ignored
+        ));
+        assert!(!is_change(
+            &event(EventKind::Modify(ModifyKind::Any), &index),
+            &ignored
+        ));
+    }
+}
@@ -0,0 +1,343 @@
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, Key, KeyInit, OsRng, Payload},
+    Aes256Gcm,
+};
+use argon2::{Argon2, Params};
+use serde::{Deserialize, Serialize};
+use server::crypto::encryption::KeyId;
+use std::{collections::BTreeMap, env, io};
+use zeroize::Zeroizing;
+
+/// Unlocks the keystore without prompting, for scripts.
+pub const PASSPHRASE_ENV: &str = "LOCAL_IPFS_PASSPHRASE";
+const SALT_LEN: usize = 16;
+/// Authenticated with the sealed identity, as key names are with sealed keys.
+const IDENTITY_AAD: &[u8] = b"local-ipfs identity";
+
+/// Encryption keys at rest, each sealed with AES-256-GCM under a key derived from the
+/// user's passphrase with Argon2id.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Keystore {
+    /// Unset until a passphrase is chosen.
+    #[serde(default)]
+    kdf: Option<KdfParams>,
+    #[serde(default)]
+    keys: BTreeMap<String, StoredKey>,
+    /// Secret of the x25519 identity files are shared with, created on first use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identity: Option<StoredKey>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct KdfParams {
+    salt: Vec<u8>,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+enum StoredKey {
+    Sealed {
+        key_id: KeyId,
+        nonce: Vec<u8>,
+        ciphertext: Vec<u8>,
+    },
+    /// Written before the keystore, sealed as soon as the config holding it is loaded.
+    Plain(Vec<u8>),
+}
+
+/// The keys of an unlocked [`Keystore`], wiped from memory when dropped.
+pub struct Keyring {
+    kdf: KdfParams,
+    wrapping_key: Zeroizing<[u8; 32]>,
+    keys: BTreeMap<String, Zeroizing<Vec<u8>>>,
+    identity: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Keystore {
+    /// Whether a passphrase has been chosen.
+    pub fn is_sealed(&self) -> bool {
+        self.kdf.is_some()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.keys.contains_key(name)
+    }
+
+    /// Whether any key is stored unsealed, as keys written before the keystore are.
+    pub fn has_plain_keys(&self) -> bool {
+        self.keys
+            .values()
+            .any(|key| matches!(key, StoredKey::Plain(_)))
+    }
+
+    pub fn has_identity(&self) -> bool {
+        self.identity.is_some()
+    }
+
+    /// Names and ids of the stored keys, readable without the passphrase.
+    pub fn key_ids(&self) -> impl Iterator<Item = (&str, KeyId)> {
+        self.keys.iter().map(|(name, key)| {
+            let key_id = match key {
+                StoredKey::Sealed { key_id, .. } => *key_id,
+                StoredKey::Plain(key) => KeyId::of(key),
+            };
+            (name.as_str(), key_id)
+        })
+    }
+
+    /// Stores a key written before the keystore existed.
+    pub fn insert_plain(&mut self, name: String, key: Vec<u8>) {
+        self.keys.insert(name, StoredKey::Plain(key));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.keys.remove(name).is_some()
+    }
+
+    /// Opens every stored key, or creates a keyring protected by `passphrase` when no
+    /// passphrase has been chosen yet.
+    pub fn unlock(&self, passphrase: &str) -> Result<Keyring, KeystoreError> {
+        let kdf = match &self.kdf {
+            Some(kdf) => kdf.clone(),
+            None => KdfParams::generate(),
+        };
+        let wrapping_key = kdf.derive(passphrase)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(wrapping_key.as_slice()));
+
+        let mut keys = BTreeMap::new();
+        for (name, key) in &self.keys {
+            keys.insert(name.clone(), open(&cipher, key, name, name.as_bytes())?);
+        }
+        let identity = match &self.identity {
+            Some(identity) => Some(open(&cipher, identity, "identity", IDENTITY_AAD)?),
+            None => None,
+        };
+
+        Ok(Keyring {
+            kdf,
+            wrapping_key,
+            keys,
+            identity,
+        })
+    }
+
+    /// Replaces the stored keys with the keys of `keyring`, sealed with fresh nonces.
+    pub fn seal(&mut self, keyring: &Keyring) -> Result<(), KeystoreError> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(
+            keyring.wrapping_key.as_slice(),
+        ));
+
+        let mut keys = BTreeMap::new();
+        for (name, key) in &keyring.keys {
+            keys.insert(name.clone(), seal(&cipher, key, name, name.as_bytes())?);
+        }
+        self.identity = match &keyring.identity {
+            Some(identity) => Some(seal(&cipher, identity, "identity", IDENTITY_AAD)?),
+            None => None,
+        };
+
+        self.kdf = Some(keyring.kdf.clone());
+        self.keys = keys;
+        Ok(())
+    }
+}
+
+impl Keyring {
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.keys.get(name).map(|key| key.as_slice())
+    }
+
+    pub fn insert(&mut self, name: String, key: Vec<u8>) {
+        self.keys.insert(name, Zeroizing::new(key));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.keys.remove(name);
+    }
+
+    pub fn identity(&self) -> Option<&[u8]> {
+        self.identity.as_deref().map(|identity| identity.as_slice())
+    }
+
+    pub fn set_identity(&mut self, identity: Zeroizing<Vec<u8>>) {
+        self.identity = Some(identity);
+    }
+
+    /// Protects the keyring with a new passphrase from the next save.
+    pub fn change_passphrase(&mut self, passphrase: &str) -> Result<(), KeystoreError> {
+        let kdf = KdfParams::generate();
+        self.wrapping_key = kdf.derive(passphrase)?;
+        self.kdf = kdf;
+        Ok(())
+    }
+}
+
+/// Opens a stored key, authenticating `aad` with it. `name` is reported if it is corrupt.
+fn open(
+    cipher: &Aes256Gcm,
+    key: &StoredKey,
+    name: &str,
+    aad: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, KeystoreError> {
+    match key {
+        StoredKey::Sealed {
+            nonce, ciphertext, ..
+        } => {
+            let nonce = <[u8; 12]>::try_from(nonce.as_slice())
+                .map_err(|_| KeystoreError::Corrupt(name.to_string()))?;
+            let payload = Payload {
+                msg: ciphertext,
+                aad,
+            };
+            cipher
+                .decrypt(&nonce.into(), payload)
+                .map(Zeroizing::new)
+                .map_err(|_| KeystoreError::WrongPassphrase)
+        }
+        StoredKey::Plain(key) => Ok(Zeroizing::new(key.clone())),
+    }
+}
+
+fn seal(
+    cipher: &Aes256Gcm,
+    key: &[u8],
+    name: &str,
+    aad: &[u8],
+) -> Result<StoredKey, KeystoreError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload { msg: key, aad };
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| KeystoreError::Corrupt(name.to_string()))?;
+
+    Ok(StoredKey::Sealed {
+        key_id: KeyId::of(key),
+        nonce: nonce.to_vec(),
+        ciphertext,
+    })
+}
+
+impl KdfParams {
+    fn generate() -> Self {
+        let mut salt = vec![0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            salt,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<Zeroizing<[u8; 32]>, KeystoreError> {
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|err| KeystoreError::Kdf(err.to_string()))?;
+        let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+
+        let mut key = Zeroizing::new([0; 32]);
+        argon2
+            .hash_password_into(passphrase.as_bytes(), &self.salt, key.as_mut())
+            .map_err(|err| KeystoreError::Kdf(err.to_string()))?;
+        Ok(key)
+    }
+}
+
+/// Reads the passphrase from [`PASSPHRASE_ENV`], or prompts for it. A new passphrase is
+/// asked for twice.
+pub fn read_passphrase(new: bool) -> Result<Zeroizing<String>, KeystoreError> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        if new && passphrase.is_empty() {
+            return Err(KeystoreError::EmptyPassphrase);
+        }
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    let prompt = if new {
+        "New keystore passphrase: "
+    } else {
+        "Keystore passphrase: "
+    };
+    let passphrase = Zeroizing::new(rpassword::prompt_password(prompt)?);
+    if new {
+        if passphrase.is_empty() {
+            return Err(KeystoreError::EmptyPassphrase);
+        }
+        let confirmation = Zeroizing::new(rpassword::prompt_password("Repeat passphrase: ")?);
+        if passphrase != confirmation {
+            return Err(KeystoreError::PassphraseMismatch);
+        }
+    }
+    Ok(passphrase)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeystoreError {
+    #[error("Wrong keystore passphrase")]
+    WrongPassphrase,
+
+    #[error("Passphrase must not be empty")]
+    EmptyPassphrase,
+
+    #[error("Passphrases do not match")]
+    PassphraseMismatch,
+
+    #[error("Stored key {0} is corrupt")]
+    Corrupt(String),
+
+    #[error("Unable to derive keystore key: {0}")]
+    Kdf(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use server::crypto::encryption::Encryption;
+
+    #[test]
+    fn keys_are_sealed_with_the_passphrase() {
+        let key = Encryption::generate_key().to_vec();
+        let mut keystore = Keystore::default();
+        keystore.insert_plain("default".into(), key.clone());
+
+        assert!(keystore.has_plain_keys());
+        let keyring = keystore.unlock("correct horse").unwrap();
+        keystore.seal(&keyring).unwrap();
+        assert!(!keystore.has_plain_keys());
+        let stored = serde_json::to_string(&keystore).unwrap();
+        assert!(!stored.contains(&format!("{:?}", key)[1..20]));
+
+        let keystore = serde_json::from_str::<Keystore>(&stored).unwrap();
+        assert_eq!(
+            keystore.key_ids().collect::<Vec<_>>(),
+            vec![("default", KeyId::of(&key))]
+        );
+        assert!(matches!(
+            keystore.unlock("battery staple"),
+            Err(KeystoreError::WrongPassphrase)
+        ));
+        let keyring = keystore.unlock("correct horse").unwrap();
+        assert_eq!(keyring.get("default").unwrap(), key.as_slice());
+    }
+
+    #[test]
+    fn passphrases_can_be_changed() {
+        let mut keystore = Keystore::default();
+        let mut keyring = keystore.unlock("first").unwrap();
+        keyring.insert("default".into(), Encryption::generate_key().to_vec());
+        keyring.set_identity(Zeroizing::new(Encryption::generate_key().to_vec()));
+        keyring.change_passphrase("second").unwrap();
+        keystore.seal(&keyring).unwrap();
+
+        assert!(keystore.unlock("first").is_err());
+        let keyring = keystore.unlock("second").unwrap();
+        assert!(keyring.get("default").is_some());
+        assert!(keyring.identity().is_some());
+    }
+}
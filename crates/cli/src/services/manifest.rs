@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use server::{
+    api::types::mfs::{InvalidMfsPath, MfsPath},
+    crypto::{
+        encryption::{EncryptWriter, Encryption, EncryptionError},
+        hex,
+    },
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::Metadata,
+    io,
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::UNIX_EPOCH,
+};
+use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
+
+/// Folder of the mutable file system holding each file's manifest, named by the file's cid.
+/// Each node writes only its own tree, so the manifests of files added elsewhere are read
+/// from the roots the adding peers gossiped.
+pub const MANIFEST_DIR: &str = "/.manifests";
+
+/// What a file was before it was encrypted, stored encrypted next to it so any machine that
+/// can decrypt the file can tell what it is.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub size: u64,
+    pub mime: String,
+    /// Seconds since the unix epoch.
+    pub mtime: Option<u64>,
+    /// Digest of the plaintext, written as `sha256:<hex>`.
+    pub content_hash: String,
+}
+
+impl Manifest {
+    pub fn new(path: &Path, metadata: &Metadata, digest: Digested) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Self {
+            mime: mime_guess::from_path(&name)
+                .first_or_octet_stream()
+                .to_string(),
+            name,
+            size: digest.len,
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                .map(|mtime| mtime.as_secs()),
+            content_hash: digest.content_hash,
+        }
+    }
+
+    pub fn path(hash: &str) -> Result<MfsPath, InvalidMfsPath> {
+        format!("{}/{}", MANIFEST_DIR, hash).parse()
+    }
+
+    /// Encrypts the manifest the way its file was, with `encryption_key` after `prefix`.
+    pub async fn seal(
+        &self,
+        encryption_key: &[u8],
+        prefix: Vec<u8>,
+    ) -> Result<Vec<u8>, EncryptionError> {
+        let mut writer = EncryptWriter::new(encryption_key, prefix);
+        let manifest = serde_json::to_vec(self).map_err(io::Error::from)?;
+        writer.write_all(&manifest).await?;
+        writer.shutdown().await?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Decrypts a manifest sealed with `encryption_key`, without the prefix it was sealed
+    /// after.
+    pub fn open(encryption_key: &[u8], payload: &[u8]) -> Result<Self, EncryptionError> {
+        let manifest = Encryption::decrypt(encryption_key, payload)?;
+        serde_json::from_slice(&manifest)
+            .map_err(|err| EncryptionError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+
+    /// The name to restore the file under, without any directories a manifest could smuggle
+    /// in.
+    pub fn file_name(&self) -> Option<&str> {
+        Path::new(&self.name).file_name()?.to_str()
+    }
+
+    /// Checks what a file decrypted to against the digest taken when it was added.
+    pub fn verify(&self, digest: &Digested) -> Result<(), DigestMismatch> {
+        if digest.len != self.size || digest.content_hash != self.content_hash {
+            return Err(DigestMismatch {
+                expected: format!("{} bytes, {}", self.size, self.content_hash),
+                found: format!("{} bytes, {}", digest.len, digest.content_hash),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("File does not match its manifest, expected {expected} but decrypted {found}")]
+pub struct DigestMismatch {
+    expected: String,
+    found: String,
+}
+
+/// Length and digest of what a [`DigestReader`] read.
+pub struct Digested {
+    pub len: u64,
+    pub content_hash: String,
+}
+
+/// Reads through to `inner`, hashing what it reads for a manifest or to check against one.
+pub struct DigestReader<R> {
+    inner: R,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<R: AsyncRead + Unpin> DigestReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            len: 0,
+        }
+    }
+
+    pub fn finish(self) -> Digested {
+        Digested {
+            len: self.len,
+            content_hash: format!("sha256:{}", hex::encode(&self.hasher.finalize())),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DigestReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        let read = &buf.filled()[filled..];
+        this.hasher.update(read);
+        this.len += read.len() as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn manifests_describe_and_seal_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        tokio::fs::write(&path, b"{\"ok\":true}").await.unwrap();
+
+        let mut reader = DigestReader::new(tokio::fs::File::open(&path).await.unwrap());
+        let mut contents = vec![];
+        reader.read_to_end(&mut contents).await.unwrap();
+        let metadata = tokio::fs::metadata(&path).await.unwrap();
+        let manifest = Manifest::new(&path, &metadata, reader.finish());
+
+        assert_eq!(manifest.name, "report.json");
+        assert_eq!(manifest.size, 11);
+        assert_eq!(manifest.mime, "application/json");
+        assert!(manifest.mtime.is_some());
+        assert_eq!(
+            manifest.content_hash,
+            format!("sha256:{}", hex::encode(&Sha256::digest(&contents)))
+        );
+
+        let key = Encryption::generate_key().to_vec();
+        let sealed = manifest.seal(&key, vec![]).await.unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"report"));
+        assert_eq!(Manifest::open(&key, &sealed).unwrap(), manifest);
+
+        let mut reader = DigestReader::new(contents.as_slice());
+        tokio::io::copy(&mut reader, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        assert!(manifest.verify(&reader.finish()).is_ok());
+        let mut reader = DigestReader::new(b"{\"ok\":null}".as_slice());
+        tokio::io::copy(&mut reader, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        assert!(manifest.verify(&reader.finish()).is_err());
+
+        let smuggled = Manifest {
+            name: "../../.bashrc".into(),
+            ..manifest
+        };
+        assert_eq!(smuggled.file_name(), Some(".bashrc"));
+    }
+}
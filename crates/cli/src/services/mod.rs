@@ -1 +1,2 @@
-pub(crate) mod encryption;
+pub(crate) mod keystore;
+pub(crate) mod manifest;
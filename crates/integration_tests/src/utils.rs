@@ -39,10 +39,7 @@ pub trait Runner {
 
         reader.lines().filter_map(|l| {
             if let Ok(l) = l {
-                match serde_json::from_str::<Log>(&l) {
-                    Ok(log) => Some(log),
-                    Err(_) => None,
-                }
+                serde_json::from_str::<Log>(&l).ok()
             } else {
                 None
             }
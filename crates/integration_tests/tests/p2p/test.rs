@@ -1,33 +1,47 @@
 #[cfg(test)]
 mod tests {
-    use futures::FutureExt;
     use integration_tests::utils::{Log, Runner};
     use jsonrpsee::{core::client::Client, ws_client::WsClientBuilder};
+    use libp2p::PeerId;
 
+    use cid::Cid;
     use rand::Rng;
     use server::{
-        api::{ipfs::IpfsClient, types::ipfs::PinAction},
+        api::{
+            ipfs::IpfsClient,
+            mfs::MfsClient,
+            transfer,
+            types::ipfs::{CidVersion, IpfsPinResponse, PinAction, PinState, ReplicationFactor},
+        },
+        backend::{car, memory::MemoryNetwork, unixfs, Backend},
+        crypto::encryption::{DecryptReader, EncryptWriter, Encryption},
+        gateway::DEFAULT_MAX_FILE_SIZE,
         network::{GossipCallBackFn, NetworkBuilder, NetworkClient},
-        rpc::{ipfs::GossipMessage, Module},
-        server::{builder::ServerBuilder, Server, ServerConfig},
-        state::State,
+        placement::Placement,
+        rpc::{ipfs::GossipHandler, retry::RetryPolicy, Module},
+        server::{builder::ServerBuilder, Server, ServerConfig, MAX_RPC_BODY_SIZE},
+        state::{State, StateClient},
     };
     use std::{
+        collections::HashMap,
         sync::{Arc, Mutex},
         time::Duration,
     };
-    use tracing::{info, instrument, Instrument, Span};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tracing::{instrument, Instrument, Span};
     use tracing_subscriber::{reload::Layer, EnvFilter};
 
     struct ServerRunnerBuilder {
         name: String,
         server_config: ServerConfig,
+        backend: Backend,
         log_buffer: Arc<Mutex<Vec<u8>>>,
     }
 
     struct ServerRunner {
         log_buffer: Arc<Mutex<Vec<u8>>>,
         name: String,
+        server_url: String,
         server_client: Client,
         network_client: NetworkClient,
     }
@@ -63,21 +77,32 @@ mod tests {
             is_boot_node: bool,
             boot_node_addr: impl Into<String>,
             topic: impl Into<String>,
+            memory_network: &MemoryNetwork,
         ) -> Self {
             let server_config = ServerConfig {
                 port: port.into(),
                 network_port: network_port.into(),
                 ip: "0.0.0.0".into(),
-                modules: vec![Module::Util, Module::Ipfs],
+                modules: vec![Module::Util, Module::Ipfs, Module::Mfs],
                 is_boot_node,
                 boot_node_addr: boot_node_addr.into(),
                 topic: topic.into(),
                 ipfs_base_url: "".into(),
                 push_gateway_url: "".into(),
+                gc_on_expiry: false,
+                max_storage: None,
+                pin_queue_file: None,
+                retry_policy: RetryPolicy::default(),
+                normalize_cids: false,
+                gateway_ip: "127.0.0.1".into(),
+                gateway_port: None,
+                gateway_keys: HashMap::new(),
+                gateway_max_file_size: DEFAULT_MAX_FILE_SIZE,
             };
 
             Self {
                 server_config,
+                backend: Backend::Memory(memory_network.backend()),
                 log_buffer,
                 name: name.into(),
             }
@@ -89,22 +114,28 @@ mod tests {
             let server_port = self.server_config.port.clone();
 
             let (_, handle) = Layer::new(EnvFilter::default());
-            let network = NetworkBuilder::new()
+            let mut network_builder = NetworkBuilder::new()
                 .with_port(&self.server_config.network_port)
                 .with_is_boot_node(self.server_config.is_boot_node)
                 .with_boot_addr(&self.server_config.boot_node_addr)
-                .with_topic(&self.server_config.topic)
-                .build()
-                .unwrap();
-            let state = State::new();
+                .with_topic(&self.server_config.topic);
+            if let Some(block_provider) = self.backend.block_provider() {
+                network_builder = network_builder.with_block_provider(block_provider);
+            }
+            let network = network_builder.build().unwrap();
+            let state_client = State::new().start();
 
-            let gossip_callback_fns =
-                Self::build_network_gossip_callback_fns(&self.server_config.modules);
+            let gossip_callback_fns = Self::build_network_gossip_callback_fns(
+                &self.server_config.modules,
+                &self.backend,
+                &state_client,
+                network.peer_id(),
+            );
 
             let network_client = network.start(gossip_callback_fns).await.unwrap();
-            let state_client = state.start();
 
             let server = ServerBuilder::new(self.server_config)
+                .with_ipfs_backend(self.backend)
                 .build(handle, network_client.clone(), state_client.clone())
                 .await
                 .unwrap();
@@ -140,38 +171,29 @@ mod tests {
             ServerRunner {
                 log_buffer: self.log_buffer,
                 name: self.name,
+                server_url,
                 server_client,
                 network_client,
             }
         }
 
-        fn build_network_gossip_callback_fns(modules: &[Module]) -> Vec<GossipCallBackFn> {
+        fn build_network_gossip_callback_fns(
+            modules: &[Module],
+            backend: &Backend,
+            state_client: &StateClient,
+            local_peer_id: PeerId,
+        ) -> Vec<GossipCallBackFn> {
             modules
                 .iter()
                 .filter_map(|m| {
                     if let Module::Ipfs = m {
-                        let callback_fn: GossipCallBackFn = Box::new({
-                            move |msg: &[u8]| {
-                                async move {
-                                    if let Ok(GossipMessage::AddFile { hash: _ }) =
-                                        serde_json::from_slice::<GossipMessage>(msg)
-                                    {
-                                        info!("Processing add file gossip message");
-                                    } else if let Ok(GossipMessage::AddPin { hash: _ }) =
-                                        serde_json::from_slice::<GossipMessage>(msg)
-                                    {
-                                        info!("Processing add pin gossip message");
-                                    } else if let Ok(GossipMessage::RmPin { hash: _ }) =
-                                        serde_json::from_slice::<GossipMessage>(msg)
-                                    {
-                                        info!("Processing rm pin gossip message");
-                                    }
-                                }
-                                .boxed()
-                            }
-                        });
-
-                        Some(callback_fn)
+                        let handler = GossipHandler::new(
+                            backend.clone(),
+                            state_client.clone(),
+                            local_peer_id,
+                            false,
+                        );
+                        Some(handler.into_callback_fn())
                     } else {
                         None
                     }
@@ -184,6 +206,39 @@ mod tests {
         fn network_client(&self) -> &NetworkClient {
             &self.network_client
         }
+
+        /// Connects a client that waits on and allows requests as large as the server does,
+        /// for moving the chunks of large files and cars.
+        async fn transfer_client(&self) -> Client {
+            WsClientBuilder::default()
+                .request_timeout(Duration::from_secs(60))
+                .max_request_size(MAX_RPC_BODY_SIZE)
+                .max_response_size(MAX_RPC_BODY_SIZE)
+                .build(&self.server_url)
+                .await
+                .unwrap()
+        }
+
+        /// Waits for this node to report `hash` as pinned by its own pin queue.
+        async fn wait_until_pinned(&self, hash: &str) {
+            let peer_id = self.network_client.get_peer_id().await.unwrap().to_string();
+            tokio::time::timeout(Duration::from_secs(2), async {
+                loop {
+                    // a request that times out is polled again like a pin still in progress
+                    if let Ok(response) = self.server_client.pin_status(hash.parse().unwrap()).await
+                    {
+                        if response.peers.iter().any(|peer| {
+                            peer.peer_id == peer_id && matches!(peer.status.state, PinState::Pinned)
+                        }) {
+                            break;
+                        }
+                    }
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap_or_else(|_| panic!("Timedout waiting for {} to pin {}", self.name, hash));
+        }
     }
 
     impl Runner for ServerRunner {
@@ -205,6 +260,7 @@ mod tests {
         log_buffer: Arc<Mutex<Vec<u8>>>,
         topic: impl Into<String> + std::marker::Copy,
     ) -> NodeTopology {
+        let memory_network = MemoryNetwork::default();
         let mut rng = rand::thread_rng();
         let port_range = 49152..=65535;
         let bootnode_port = format!("{}", rng.gen_range(port_range.clone()));
@@ -218,6 +274,7 @@ mod tests {
             true,
             "",
             topic,
+            &memory_network,
         )
         .await
         .start()
@@ -239,6 +296,7 @@ mod tests {
             false,
             &boot_node_addr,
             topic,
+            &memory_network,
         )
         .await
         .start()
@@ -258,6 +316,7 @@ mod tests {
                 false,
                 &boot_node_addr,
                 topic,
+                &memory_network,
             )
             .await
             .start()
@@ -373,7 +432,11 @@ mod tests {
             .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
             .await;
 
-        node_1.server_client.add(data).await.unwrap();
+        node_1
+            .server_client
+            .add(data, None, None, None)
+            .await
+            .unwrap();
 
         node_1
             .assert_info_log_entry(&format!(
@@ -415,11 +478,15 @@ mod tests {
             .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
             .await;
 
-        let response = node_1.server_client.add(data).await.unwrap();
+        let response = node_1
+            .server_client
+            .add(data, None, None, None)
+            .await
+            .unwrap();
         let hash = response.hash;
         node_1
             .server_client
-            .pin(PinAction::add, Some(hash))
+            .pin(PinAction::add, Some(hash.parse().unwrap()), None, None)
             .await
             .unwrap();
 
@@ -463,11 +530,15 @@ mod tests {
             .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
             .await;
 
-        let response = node_1.server_client.add(data).await.unwrap();
+        let response = node_1
+            .server_client
+            .add(data, None, None, None)
+            .await
+            .unwrap();
         let hash = response.hash;
         node_1
             .server_client
-            .pin(PinAction::rm, Some(hash))
+            .pin(PinAction::rm, Some(hash.parse().unwrap()), None, None)
             .await
             .unwrap();
 
@@ -488,4 +559,469 @@ mod tests {
             .assert_info_log_entry("Processing rm pin gossip message")
             .await;
     }
+
+    #[test_macro::test]
+    async fn replicate_added_file_to_peers(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+        let data = b"replicated file".to_vec();
+
+        let node_topology = setup_test_topolgy(1, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+
+        let (node_1, node_2) = match &nodes[..] {
+            [first, second, ..] => (first, second),
+            _ => panic!("Not enough peers"),
+        };
+
+        node_1
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+        node_2
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+
+        let response = node_1
+            .server_client
+            .add(data.clone(), None, None, None)
+            .await
+            .unwrap();
+        let hash = response.hash;
+
+        node_2
+            .assert_info_log_entry(&format!("Successfully pinned {} from pin queue", hash))
+            .await;
+
+        let Ok(IpfsPinResponse::Ls(pins)) = node_2
+            .server_client
+            .pin(PinAction::ls, None, None, None)
+            .await
+        else {
+            panic!("Unexpected pin ls response");
+        };
+        assert!(pins.keys.get(&hash).is_some());
+
+        let contents = node_2
+            .server_client
+            .cat(hash.parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(contents.as_bytes(), data);
+    }
+
+    #[test_macro::test]
+    async fn only_allocated_peers_pin_replicated_files(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+
+        let node_topology = setup_test_topolgy(2, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+
+        let (node_1, others) = match &nodes[..] {
+            [first, rest @ ..] if rest.len() == 2 => (first, rest),
+            _ => panic!("Not enough peers"),
+        };
+
+        let mut peer_ids = vec![node_1.network_client().get_peer_id().await.unwrap()];
+        for node in others {
+            let peer_id = node.network_client().get_peer_id().await.unwrap();
+            node_1
+                .assert_info_log_contains(&format!(
+                    "A remote peer {} subscribed to a topic: {}",
+                    peer_id, topic
+                ))
+                .await;
+            peer_ids.push(peer_id);
+        }
+
+        // content allocated away from the node adding it, which hands it off
+        let replication = ReplicationFactor::new(1, 1);
+        let (data, hash, allocation) = (0..)
+            .map(|i| {
+                let data = format!("replicated file {}", i).into_bytes();
+                let (hash, _) = unixfs::build_file(&data, CidVersion::V0).unwrap();
+                let allocation = Placement::allocate(&hash.to_string(), &peer_ids, &replication);
+                (data, hash.to_string(), allocation)
+            })
+            .find(|(_, _, allocation)| !allocation.contains(&peer_ids[0]))
+            .unwrap();
+        let (allocated, unallocated) = if allocation.contains(&peer_ids[1]) {
+            (&others[0], &others[1])
+        } else {
+            (&others[1], &others[0])
+        };
+
+        let response = node_1
+            .server_client
+            .add(data, Some(replication), None, None)
+            .await
+            .unwrap();
+        assert_eq!(response.hash, hash);
+
+        allocated
+            .assert_info_log_entry(&format!("Successfully pinned {} from pin queue", hash))
+            .await;
+        unallocated
+            .assert_info_log_entry(&format!(
+                "Skipping pin of {} which is not allocated to this node",
+                hash
+            ))
+            .await;
+        node_1
+            .assert_info_log_entry(&format!(
+                "Released {} now held by 1 of its allocated peers",
+                hash
+            ))
+            .await;
+
+        let Ok(IpfsPinResponse::Ls(pins)) = node_1
+            .server_client
+            .pin(PinAction::ls, None, None, None)
+            .await
+        else {
+            panic!("Unexpected pin ls response");
+        };
+        assert!(pins.keys.get(&hash).is_none());
+    }
+
+    #[test_macro::test]
+    async fn request_block_from_peer(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+        let data = b"exchanged block".to_vec();
+
+        let node_topology = setup_test_topolgy(1, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+
+        let (node_1, node_2) = match &nodes[..] {
+            [first, second, ..] => (first, second),
+            _ => panic!("Not enough peers"),
+        };
+
+        node_2.assert_info_log_entry("Bootstrap successful!").await;
+
+        let hash = node_1
+            .server_client
+            .add(data.clone(), None, None, None)
+            .await
+            .unwrap()
+            .hash;
+        let cid = Cid::try_from(hash.as_str()).unwrap();
+        let node_1_peer_id = node_1.network_client().get_peer_id().await.unwrap();
+
+        let block = node_2
+            .network_client()
+            .request_block(node_1_peer_id, cid)
+            .await
+            .unwrap()
+            .expect("Peer did not have the block");
+        let (_, blocks) = unixfs::build_file(&data, CidVersion::V0).unwrap();
+        assert_eq!(block, blocks[0].data);
+
+        node_1
+            .assert_info_log_contains(&format!("Block {} requested by", hash))
+            .await;
+    }
+
+    #[test_macro::test]
+    async fn gossip_name_publish_to_peers(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+
+        let node_topology = setup_test_topolgy(1, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+
+        let (node_1, node_2) = match &nodes[..] {
+            [first, second, ..] => (first, second),
+            _ => panic!("Not enough peers"),
+        };
+
+        node_1
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+        node_2
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+
+        let first = node_1
+            .server_client
+            .add(b"build 1".to_vec(), None, None, None)
+            .await
+            .unwrap()
+            .hash;
+        let second = node_1
+            .server_client
+            .add(b"build 2".to_vec(), None, None, None)
+            .await
+            .unwrap()
+            .hash;
+
+        // Only the name should keep the first build pinned on node_2.
+        node_2.wait_until_pinned(&first).await;
+        node_1
+            .server_client
+            .pin(PinAction::rm, Some(first.parse().unwrap()), None, None)
+            .await
+            .unwrap();
+        node_2
+            .assert_info_log_entry(&format!(
+                "Successfully removed {} pin from gossip message",
+                first
+            ))
+            .await;
+
+        let name = node_1
+            .server_client
+            .name_publish(first.parse().unwrap(), Some("latest".into()))
+            .await
+            .unwrap()
+            .name;
+        node_2
+            .assert_info_log_entry(&format!("Name {} now points at {}", name, first))
+            .await;
+        node_2.wait_until_pinned(&first).await;
+
+        node_1
+            .server_client
+            .name_publish(second.parse().unwrap(), Some("latest".into()))
+            .await
+            .unwrap();
+        node_2
+            .assert_info_log_entry(&format!(
+                "Unpinned {} which {} no longer points at",
+                first, name
+            ))
+            .await;
+
+        let resolved = node_2.server_client.name_resolve(name).await.unwrap();
+        assert_eq!(resolved.path, format!("/ipfs/{}", second));
+    }
+
+    #[test_macro::test]
+    async fn gossip_mfs_root_to_peers(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+
+        let node_topology = setup_test_topolgy(1, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+
+        let (node_1, node_2) = match &nodes[..] {
+            [first, second, ..] => (first, second),
+            _ => panic!("Not enough peers"),
+        };
+
+        node_1
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+        node_2
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+
+        let peer_id = node_1.network_client().get_peer_id().await.unwrap();
+        let first = node_1
+            .server_client
+            .mkdir("/docs".parse().unwrap(), None)
+            .await
+            .unwrap()
+            .root;
+        node_2
+            .assert_info_log_entry(&format!("Mfs root of {} is now {}", peer_id, first))
+            .await;
+        node_2
+            .assert_info_log_entry(&format!("Successfully pinned {} from pin queue", first))
+            .await;
+
+        let hash = node_1
+            .server_client
+            .add(b"notes".to_vec(), None, None, None)
+            .await
+            .unwrap()
+            .hash;
+        let second = node_1
+            .server_client
+            .cp(
+                format!("/ipfs/{}", hash).parse().unwrap(),
+                "/docs/notes.txt".parse().unwrap(),
+            )
+            .await
+            .unwrap()
+            .root;
+        node_2
+            .assert_info_log_entry(&format!("Mfs root of {} is now {}", peer_id, second))
+            .await;
+        node_2
+            .assert_info_log_entry(&format!(
+                "Unpinned {} which /mfs/{} no longer points at",
+                first, peer_id
+            ))
+            .await;
+
+        let entries = MfsClient::ls(&node_1.server_client, Some("/docs".parse().unwrap()))
+            .await
+            .unwrap()
+            .entries
+            .unwrap();
+        assert_eq!(entries[0].name, "notes.txt");
+        assert_eq!(entries[0].hash, hash);
+
+        // node_2 reads node_1's files through the root node_1 gossiped
+        let roots = MfsClient::roots(&node_2.server_client).await.unwrap().roots;
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0].peer_id, peer_id.to_string());
+        assert_eq!(roots[0].root, second);
+        let docs = IpfsClient::ls(&node_2.server_client, second)
+            .await
+            .unwrap()
+            .links;
+        assert_eq!(docs[0].name, "docs");
+        assert!(MfsClient::roots(&node_1.server_client)
+            .await
+            .unwrap()
+            .roots
+            .is_empty());
+    }
+
+    #[test_macro::test]
+    async fn gossip_imported_car_roots_to_peers(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+
+        let node_topology = setup_test_topolgy(1, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+
+        let (node_1, node_2) = match &nodes[..] {
+            [first, second, ..] => (first, second),
+            _ => panic!("Not enough peers"),
+        };
+
+        node_1
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+        node_2
+            .assert_info_log_entry(&format!("Subscribed to topic: {}", topic))
+            .await;
+
+        // content from outside the cluster, as if carried over from an air-gapped one
+        let (root, blocks) = unixfs::build_file(b"offline content", CidVersion::V1).unwrap();
+        let car = car::write_car(&[root], &blocks);
+
+        let upload = transfer::upload(&node_1.server_client, &car[..])
+            .await
+            .unwrap();
+        let response = node_1
+            .server_client
+            .dag_import(upload, None, None)
+            .await
+            .unwrap();
+        assert_eq!(response.roots, vec![root.to_string()]);
+
+        node_2
+            .assert_info_log_entry(&format!("Successfully pinned {} from pin queue", root))
+            .await;
+
+        let download = node_2
+            .server_client
+            .dag_export(root.to_string().parse().unwrap())
+            .await
+            .unwrap();
+        let mut exported = Vec::new();
+        transfer::download(&node_2.server_client, download)
+            .read_to_end(&mut exported)
+            .await
+            .unwrap();
+        assert_eq!(exported, car.to_vec());
+    }
+
+    #[test_macro::test]
+    async fn export_and_import_cars_above_the_rpc_limit(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+
+        let node_topology = setup_test_topolgy(0, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+        let node = &nodes[0];
+        let client = node.transfer_client().await;
+
+        // over the rpc body limit, so moved in several chunks, and split into many blocks
+        let data = (0..12 * 1024 * 1024)
+            .map(|i: u32| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+        let (root, blocks) = unixfs::build_file(&data, CidVersion::V1).unwrap();
+        assert!(blocks.len() > 1);
+        let car = car::write_car(&[root], &blocks);
+
+        assert!(car.len() > MAX_RPC_BODY_SIZE as usize);
+
+        let upload = transfer::upload(&client, &car[..]).await.unwrap();
+        let response = client.dag_import(upload, None, None).await.unwrap();
+        assert_eq!(response.roots, vec![root.to_string()]);
+
+        let download = client
+            .dag_export(root.to_string().parse().unwrap())
+            .await
+            .unwrap();
+        let mut exported = Vec::new();
+        transfer::download(&client, download)
+            .read_to_end(&mut exported)
+            .await
+            .unwrap();
+        // the export walks the dag from its root, so holds the same blocks in another order
+        let exported = car::read_car(&exported).unwrap();
+        assert_eq!(exported.roots, vec![root]);
+        let mut exported = exported
+            .blocks
+            .iter()
+            .map(|block| (block.cid, block.data.clone()))
+            .collect::<Vec<_>>();
+        let mut imported = blocks
+            .iter()
+            .map(|block| (block.cid, block.data.clone()))
+            .collect::<Vec<_>>();
+        exported.sort_by_key(|(cid, _)| cid.to_bytes());
+        imported.sort_by_key(|(cid, _)| cid.to_bytes());
+        assert!(
+            exported == imported,
+            "exported blocks differ from the imported ones"
+        );
+    }
+
+    #[test_macro::test]
+    async fn round_trip_encrypted_files_above_the_rpc_limit(log_buffer: Arc<Mutex<Vec<u8>>>) {
+        let topic = "gossip_topic";
+
+        let node_topology = setup_test_topolgy(0, log_buffer, topic).await;
+        let (_, nodes) = node_topology.into_nodes();
+        let node = &nodes[0];
+        let client = node.transfer_client().await;
+
+        let key = Encryption::generate_key();
+        let mut rng = rand::thread_rng();
+        let data = (0..MAX_RPC_BODY_SIZE + 1024 * 1024)
+            .map(|_| rng.gen::<u8>())
+            .collect::<Vec<u8>>();
+
+        // encrypted and uploaded as it is read, the way the cli adds files
+        let (pipe, uploaded) = tokio::io::duplex(64 * 1024);
+        let encrypt = async {
+            let mut writer = EncryptWriter::new(&key, pipe);
+            tokio::io::copy(&mut &data[..], &mut writer).await?;
+            writer.shutdown().await
+        };
+        let upload = transfer::upload(&client, transfer::encode_json_array(uploaded));
+        let (_, upload) = tokio::try_join!(encrypt, upload).unwrap();
+        let hash = client
+            .add_upload(upload, None, None, None)
+            .await
+            .unwrap()
+            .hash;
+
+        let stored = IpfsClient::stat(&client, hash.parse().unwrap())
+            .await
+            .unwrap();
+        assert!(stored.cumulative_size > MAX_RPC_BODY_SIZE as u64);
+
+        let stored = transfer::decode_json_array(transfer::cat(&client, hash.parse().unwrap()));
+        let mut decrypted = Vec::new();
+        DecryptReader::new(&key, stored)
+            .read_to_end(&mut decrypted)
+            .await
+            .unwrap();
+        assert!(
+            decrypted == data,
+            "decrypted file differs from the added one"
+        );
+    }
 }
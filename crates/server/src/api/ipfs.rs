@@ -1,4 +1,9 @@
-use super::types::ipfs::{IpfsAddResponse, IpfsIdResponse, IpfsPinResponse, PinAction};
+use super::types::ipfs::{
+    Chunk, CidVersion, IpfsAddResponse, IpfsCid, IpfsDagImportResponse, IpfsDownload,
+    IpfsIdResponse, IpfsKey, IpfsKeyListResponse, IpfsLsResponse, IpfsNamePublishResponse,
+    IpfsNameResolveResponse, IpfsPinResponse, IpfsPinStatusResponse, IpfsRefsResponse,
+    IpfsStatResponse, PinAction, ReplicationFactor, TransferId,
+};
 use jsonrpsee::{core::RpcResult, proc_macros::rpc};
 
 #[rpc(client, server, namespace = "ipfs")]
@@ -7,11 +12,97 @@ pub trait Ipfs {
     async fn id(&self) -> RpcResult<IpfsIdResponse>;
 
     #[method(name = "pin")]
-    async fn pin(&self, pin_action: PinAction, hash: Option<String>) -> RpcResult<IpfsPinResponse>;
+    async fn pin(
+        &self,
+        pin_action: PinAction,
+        hash: Option<IpfsCid>,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+    ) -> RpcResult<IpfsPinResponse>;
 
     #[method(name = "add")]
-    async fn add(&self, data: Vec<u8>) -> RpcResult<IpfsAddResponse>;
+    async fn add(
+        &self,
+        data: Vec<u8>,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+        cid_version: Option<CidVersion>,
+    ) -> RpcResult<IpfsAddResponse>;
+
+    /// Adds the file uploaded as `transfer`, like `add`.
+    #[method(name = "addUpload")]
+    async fn add_upload(
+        &self,
+        transfer: TransferId,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+        cid_version: Option<CidVersion>,
+    ) -> RpcResult<IpfsAddResponse>;
+
+    #[method(name = "pinStatus")]
+    async fn pin_status(&self, hash: IpfsCid) -> RpcResult<IpfsPinStatusResponse>;
 
     #[method(name = "cat")]
-    async fn cat(&self, hash: String) -> RpcResult<String>;
+    async fn cat(&self, hash: IpfsCid) -> RpcResult<String>;
+
+    /// Reads up to `length` bytes of `hash` from `offset`, at most `MAX_CHUNK_SIZE`.
+    #[method(name = "catRange")]
+    async fn cat_range(&self, hash: IpfsCid, offset: u64, length: u64) -> RpcResult<Chunk>;
+
+    /// Size and block count of the whole dag below `hash`.
+    #[method(name = "stat")]
+    async fn stat(&self, hash: IpfsCid) -> RpcResult<IpfsStatResponse>;
+
+    /// Links of `hash`, the entries of a unixfs directory or the chunks of a file.
+    #[method(name = "ls")]
+    async fn ls(&self, hash: IpfsCid) -> RpcResult<IpfsLsResponse>;
+
+    #[method(name = "refs")]
+    async fn refs(&self, hash: IpfsCid) -> RpcResult<IpfsRefsResponse>;
+
+    /// Exports the raw bytes of the single block `hash`.
+    #[method(name = "blockGet")]
+    async fn block_get(&self, hash: IpfsCid) -> RpcResult<Vec<u8>>;
+
+    /// Appends `chunk` to the upload `transfer`, or starts a new upload without one. Uploads
+    /// left idle for ten minutes are dropped.
+    #[method(name = "upload")]
+    async fn upload(&self, transfer: Option<TransferId>, chunk: Chunk) -> RpcResult<TransferId>;
+
+    /// Reads up to `MAX_CHUNK_SIZE` bytes of the download `transfer` from `offset`. The
+    /// download is dropped once read to the end.
+    #[method(name = "download")]
+    async fn download(&self, transfer: TransferId, offset: u64) -> RpcResult<Chunk>;
+
+    /// Exports the dag rooted at `hash` as a car file, staged for `download`.
+    #[method(name = "dagExport")]
+    async fn dag_export(&self, hash: IpfsCid) -> RpcResult<IpfsDownload>;
+
+    /// Imports the car file uploaded as `transfer` and pins its roots across the cluster,
+    /// like `pin`.
+    #[method(name = "dagImport")]
+    async fn dag_import(
+        &self,
+        transfer: TransferId,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+    ) -> RpcResult<IpfsDagImportResponse>;
+
+    #[method(name = "keyGen")]
+    async fn key_gen(&self, name: String) -> RpcResult<IpfsKey>;
+
+    #[method(name = "keyList")]
+    async fn key_list(&self) -> RpcResult<IpfsKeyListResponse>;
+
+    /// Points the ipns name of `key`, `self` by default, at `hash`. The key is created
+    /// if it does not exist yet.
+    #[method(name = "namePublish")]
+    async fn name_publish(
+        &self,
+        hash: IpfsCid,
+        key: Option<String>,
+    ) -> RpcResult<IpfsNamePublishResponse>;
+
+    #[method(name = "nameResolve")]
+    async fn name_resolve(&self, name: String) -> RpcResult<IpfsNameResolveResponse>;
 }
@@ -0,0 +1,43 @@
+use super::types::mfs::{
+    MfsChangeResponse, MfsLsResponse, MfsPath, MfsRootsResponse, MfsSource, MfsStatResponse,
+};
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+
+/// The node's mutable file system. Every change returns the new root, which is also
+/// gossiped so peers replicate the tree.
+#[rpc(client, server, namespace = "mfs")]
+pub trait Mfs {
+    #[method(name = "ls")]
+    async fn ls(&self, path: Option<MfsPath>) -> RpcResult<MfsLsResponse>;
+
+    #[method(name = "mkdir")]
+    async fn mkdir(&self, path: MfsPath, parents: Option<bool>) -> RpcResult<MfsChangeResponse>;
+
+    /// Copies `from`, a path of the mutable file system or `/ipfs/<cid>`, to `to`.
+    #[method(name = "cp")]
+    async fn cp(&self, from: MfsSource, to: MfsPath) -> RpcResult<MfsChangeResponse>;
+
+    #[method(name = "mv")]
+    async fn mv(&self, from: MfsPath, to: MfsPath) -> RpcResult<MfsChangeResponse>;
+
+    #[method(name = "rm")]
+    async fn rm(&self, path: MfsPath, recursive: Option<bool>) -> RpcResult<MfsChangeResponse>;
+
+    #[method(name = "stat")]
+    async fn stat(&self, path: Option<MfsPath>) -> RpcResult<MfsStatResponse>;
+
+    #[method(name = "write")]
+    async fn write(
+        &self,
+        path: MfsPath,
+        data: Vec<u8>,
+        create: Option<bool>,
+    ) -> RpcResult<MfsChangeResponse>;
+
+    #[method(name = "read")]
+    async fn read(&self, path: MfsPath) -> RpcResult<String>;
+
+    /// Roots of the peers' trees, which this node pins, so their files can be read by cid.
+    #[method(name = "roots")]
+    async fn roots(&self) -> RpcResult<MfsRootsResponse>;
+}
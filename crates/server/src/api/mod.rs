@@ -1,4 +1,6 @@
 pub mod ipfs;
 pub mod metrics;
+pub mod mfs;
+pub mod transfer;
 pub mod types;
 pub mod util;
@@ -0,0 +1,234 @@
+//! Client side of moving files in chunks, for files and cars too large to travel in a
+//! single rpc request.
+
+use bytes::Bytes;
+use futures::{future, stream, StreamExt, TryStreamExt};
+use std::io::Write;
+use tokio::io::{self, AsyncRead, AsyncReadExt};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use super::{
+    ipfs::IpfsClient,
+    types::ipfs::{Chunk, IpfsCid, IpfsDownload, TransferId, MAX_CHUNK_SIZE},
+};
+
+/// Uploads everything `reader` yields, in chunks, and returns the upload to pass on to
+/// the method that consumes it.
+pub async fn upload<C, R>(client: &C, mut reader: R) -> io::Result<TransferId>
+where
+    C: IpfsClient + Sync,
+    R: AsyncRead + Unpin,
+{
+    let mut transfer = None;
+    loop {
+        let mut chunk = Vec::with_capacity(MAX_CHUNK_SIZE);
+        (&mut reader)
+            .take(MAX_CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)
+            .await?;
+        let last = chunk.len() < MAX_CHUNK_SIZE;
+        let uploaded = client
+            .upload(transfer, Chunk(chunk))
+            .await
+            .map_err(io::Error::other)?;
+        if last {
+            return Ok(uploaded);
+        }
+        transfer = Some(uploaded);
+    }
+}
+
+/// Reads a file staged for download, fetching a chunk at a time.
+pub fn download<C>(client: &C, download: IpfsDownload) -> impl AsyncRead + Unpin + Send + '_
+where
+    C: IpfsClient + Sync,
+{
+    let IpfsDownload { transfer, size } = download;
+    let chunks = stream::try_unfold(0, move |offset| {
+        let transfer = transfer.clone();
+        async move {
+            if offset >= size {
+                return Ok(None);
+            }
+            let chunk = client
+                .download(transfer, offset)
+                .await
+                .map_err(io::Error::other)?;
+            if chunk.0.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Download ended after {} of {} bytes", offset, size),
+                ));
+            }
+            let next = offset + chunk.0.len() as u64;
+            Ok(Some((Bytes::from(chunk.0), next)))
+        }
+    });
+    StreamReader::new(Box::pin(chunks))
+}
+
+/// Reads `hash` from the cluster, fetching a chunk at a time.
+pub fn cat<C>(client: &C, hash: IpfsCid) -> impl AsyncRead + Unpin + Send + '_
+where
+    C: IpfsClient + Sync,
+{
+    let chunks = stream::try_unfold(Some(0), move |offset| async move {
+        let Some(offset) = offset else {
+            return Ok::<_, io::Error>(None);
+        };
+        let chunk = client
+            .cat_range(hash, offset, MAX_CHUNK_SIZE as u64)
+            .await
+            .map_err(io::Error::other)?;
+        // a short chunk is the end of the file
+        let next = (chunk.0.len() == MAX_CHUNK_SIZE).then_some(offset + MAX_CHUNK_SIZE as u64);
+        Ok(Some((Bytes::from(chunk.0), next)))
+    });
+    StreamReader::new(Box::pin(chunks))
+}
+
+/// Encodes what `reader` yields as the text of a json array of its bytes, `[1,2,3]`, the
+/// way encrypted files are stored.
+pub fn encode_json_array<R>(reader: R) -> impl AsyncRead + Unpin + Send
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let mut first = true;
+    let elements = ReaderStream::new(reader).map_ok(move |bytes| {
+        let mut text = Vec::with_capacity(bytes.len() * 4);
+        for byte in bytes {
+            if !first {
+                text.push(b',');
+            }
+            first = false;
+            write!(text, "{}", byte).expect("writing to a vec cannot fail");
+        }
+        Bytes::from(text)
+    });
+    let open = stream::once(future::ready(Ok(Bytes::from_static(b"["))));
+    let close = stream::once(future::ready(Ok(Bytes::from_static(b"]"))));
+    StreamReader::new(open.chain(elements).chain(close))
+}
+
+/// Decodes the text of a json array of bytes as `encode_json_array` writes it, failing
+/// with [`io::ErrorKind::InvalidData`] on anything else.
+pub fn decode_json_array<R>(reader: R) -> impl AsyncRead + Unpin + Send
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let text = ReaderStream::new(reader);
+    let bytes = stream::try_unfold(
+        (text, JsonArrayDecoder::Start),
+        |(mut text, mut decoder)| async move {
+            match text.try_next().await? {
+                Some(chunk) => {
+                    let bytes = decoder.decode(&chunk)?;
+                    Ok(Some((Bytes::from(bytes), (text, decoder))))
+                }
+                None if decoder == JsonArrayDecoder::End => Ok(None),
+                None => Err(invalid_json_array("the array is cut short")),
+            }
+        },
+    );
+    StreamReader::new(Box::pin(bytes))
+}
+
+/// Where a json array of bytes has been parsed up to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonArrayDecoder {
+    Start,
+    /// After the opening bracket.
+    Open,
+    /// Inside a number.
+    Number(u8),
+    /// After a number and the whitespace following it.
+    Element,
+    /// After a comma.
+    Comma,
+    End,
+}
+
+impl JsonArrayDecoder {
+    fn decode(&mut self, text: &[u8]) -> io::Result<Vec<u8>> {
+        use JsonArrayDecoder::*;
+
+        let mut bytes = Vec::with_capacity(text.len() / 2);
+        for &c in text {
+            *self = match (*self, c) {
+                (Number(n), b' ' | b'\t' | b'\n' | b'\r') => {
+                    bytes.push(n);
+                    Element
+                }
+                (state, b' ' | b'\t' | b'\n' | b'\r') => state,
+                (Start, b'[') => Open,
+                (Open | Comma, b'0'..=b'9') => Number(c - b'0'),
+                (Number(n), b'0'..=b'9') => Number(
+                    n.checked_mul(10)
+                        .and_then(|n| n.checked_add(c - b'0'))
+                        .ok_or_else(|| invalid_json_array("an element is not a byte"))?,
+                ),
+                (Number(n), b',') => {
+                    bytes.push(n);
+                    Comma
+                }
+                (Element, b',') => Comma,
+                (Number(n), b']') => {
+                    bytes.push(n);
+                    End
+                }
+                (Open | Element, b']') => End,
+                (state, c) => {
+                    return Err(invalid_json_array(&format!(
+                        "unexpected {:?} in state {:?}",
+                        c as char, state
+                    )))
+                }
+            };
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn invalid_json_array(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid json array of bytes: {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn read(mut reader: impl AsyncRead + Unpin) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn json_arrays_are_encoded_like_serde_and_decoded_back() {
+        let large = (0..100_000).map(|i| (i % 256) as u8).collect::<Vec<u8>>();
+        for bytes in [vec![], vec![0], vec![255, 0, 7], large] {
+            let text = read(encode_json_array(&bytes[..])).await.unwrap();
+            assert!(text == serde_json::to_vec(&bytes).unwrap());
+
+            let decoded = read(decode_json_array(&text[..])).await.unwrap();
+            assert!(decoded == bytes);
+        }
+
+        let spaced = read(decode_json_array(&b" [ 1 , 2,3 ] "[..]))
+            .await
+            .unwrap();
+        assert_eq!(spaced, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn malformed_json_arrays_are_rejected() {
+        for text in ["", "[1,2", "[1,,2]", "[256]", "[1 2]", "[1,]", "{}", "[1]x"] {
+            let err = read(decode_json_array(text.as_bytes())).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", text);
+        }
+    }
+}
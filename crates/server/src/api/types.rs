@@ -8,6 +8,9 @@ pub struct Pong {
 
 pub mod ipfs {
     use super::*;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use cid::{Cid, Version};
+    use std::{fmt, str::FromStr};
 
     #[allow(non_camel_case_types)]
     #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -66,6 +69,272 @@ pub mod ipfs {
         pub pins: Vec<String>,
     }
 
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum CidVersion {
+        #[default]
+        V0,
+        V1,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum PinState {
+        Queued,
+        Pinning,
+        Pinned,
+        Failed,
+        Refused,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct PinStatus {
+        pub state: PinState,
+        pub retries: u32,
+        pub error: Option<String>,
+    }
+
+    impl PinStatus {
+        pub fn new(state: PinState) -> Self {
+            Self {
+                state,
+                retries: 0,
+                error: None,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct PeerPinStatus {
+        pub peer_id: String,
+        pub status: PinStatus,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsPinStatusResponse {
+        pub hash: String,
+        pub peers: Vec<PeerPinStatus>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsRepoStatResponse {
+        #[serde(alias = "RepoSize")]
+        pub repo_size: u64,
+        #[serde(alias = "StorageMax")]
+        pub storage_max: u64,
+        #[serde(alias = "NumObjects")]
+        pub num_objects: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsBlockPutResponse {
+        #[serde(alias = "Key")]
+        pub key: String,
+        #[serde(alias = "Size")]
+        pub size: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsObjectStatResponse {
+        #[serde(alias = "Hash")]
+        pub hash: String,
+        #[serde(alias = "CumulativeSize")]
+        pub cumulative_size: u64,
+    }
+
+    /// Kubo renamed these fields when it added multiple roots to `dag/stat`, so accept both.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsDagStatResponse {
+        #[serde(alias = "TotalSize", alias = "Size")]
+        pub total_size: u64,
+        #[serde(alias = "UniqueBlocks", alias = "NumBlocks", default)]
+        pub num_blocks: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsStatResponse {
+        pub hash: String,
+        /// Size of every unique block of the dag.
+        pub cumulative_size: u64,
+        pub num_blocks: u64,
+    }
+
+    /// Unixfs node types as kubo numbers them.
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+    #[serde(try_from = "u8", into = "u8")]
+    pub enum IpfsLinkType {
+        Raw,
+        Directory,
+        File,
+        Metadata,
+        Symlink,
+        HamtShard,
+    }
+
+    impl TryFrom<u8> for IpfsLinkType {
+        type Error = String;
+
+        fn try_from(value: u8) -> Result<Self, Self::Error> {
+            match value {
+                0 => Ok(Self::Raw),
+                1 => Ok(Self::Directory),
+                2 => Ok(Self::File),
+                3 => Ok(Self::Metadata),
+                4 => Ok(Self::Symlink),
+                5 => Ok(Self::HamtShard),
+                value => Err(format!("Unknown unixfs type {}", value)),
+            }
+        }
+    }
+
+    impl From<IpfsLinkType> for u8 {
+        fn from(value: IpfsLinkType) -> Self {
+            match value {
+                IpfsLinkType::Raw => 0,
+                IpfsLinkType::Directory => 1,
+                IpfsLinkType::File => 2,
+                IpfsLinkType::Metadata => 3,
+                IpfsLinkType::Symlink => 4,
+                IpfsLinkType::HamtShard => 5,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsLink {
+        /// Empty for the links between the chunks of a file.
+        #[serde(alias = "Name")]
+        pub name: String,
+        #[serde(alias = "Hash")]
+        pub hash: String,
+        /// File size of the linked node, zero for directories.
+        #[serde(alias = "Size")]
+        pub size: u64,
+        #[serde(rename = "type", alias = "Type")]
+        pub link_type: IpfsLinkType,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsLsResponse {
+        #[serde(alias = "Hash")]
+        pub hash: String,
+        #[serde(alias = "Links")]
+        pub links: Vec<IpfsLink>,
+    }
+
+    /// Kubo lists every argument it was given.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsLsObjectsResponse {
+        #[serde(alias = "Objects")]
+        pub objects: Vec<IpfsLsResponse>,
+    }
+
+    /// One line of kubo's streamed `refs` output.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsRefResponse {
+        #[serde(alias = "Ref")]
+        pub r#ref: String,
+        #[serde(alias = "Err")]
+        pub err: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsRefsResponse {
+        pub hash: String,
+        /// Every block below `hash`, once each, depth first.
+        pub refs: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsDagImportResponse {
+        /// Roots of the imported car, which are pinned.
+        pub roots: Vec<String>,
+    }
+
+    /// Largest chunk a file is moved in, which stays well inside the rpc body limit once
+    /// encoded as base64.
+    pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+    /// Names a file staged on the server while it is uploaded or downloaded in chunks.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+    #[serde(transparent)]
+    pub struct TransferId(String);
+
+    impl TransferId {
+        pub fn random() -> Self {
+            Self(crate::crypto::hex::encode(&rand::random::<[u8; 16]>()))
+        }
+    }
+
+    impl fmt::Display for TransferId {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    /// Part of a file, sent as base64 rather than as an array of numbers.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct Chunk(pub Vec<u8>);
+
+    impl Serialize for Chunk {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&STANDARD.encode(&self.0))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Chunk {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let chunk = String::deserialize(deserializer)?;
+            STANDARD
+                .decode(chunk)
+                .map(Self)
+                .map_err(serde::de::Error::custom)
+        }
+    }
+
+    /// A file staged for download in chunks.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsDownload {
+        pub transfer: TransferId,
+        pub size: u64,
+    }
+
+    /// One line of kubo's streamed `dag/import` output.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsDagImportLine {
+        #[serde(alias = "Root")]
+        pub root: Option<IpfsDagImportRoot>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsDagImportRoot {
+        #[serde(alias = "Cid")]
+        pub cid: IpfsCidLink,
+        #[serde(alias = "PinErrorMsg", default)]
+        pub pin_error_msg: String,
+    }
+
+    /// A cid in dag-json form, `{"/": "<cid>"}`.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsCidLink {
+        #[serde(rename = "/")]
+        pub cid: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ReplicationFactor {
+        pub min: usize,
+        pub max: usize,
+    }
+
+    impl ReplicationFactor {
+        pub fn new(min: usize, max: usize) -> Self {
+            Self { min, max }
+        }
+
+        pub fn is_valid(&self) -> bool {
+            self.min >= 1 && self.min <= self.max
+        }
+    }
+
     #[derive(Serialize, Deserialize, Clone, Debug)]
     pub struct IpfsAddResponse {
         #[serde(alias = "Hash")]
@@ -73,4 +342,441 @@ pub mod ipfs {
         #[serde(alias = "Name")]
         pub name: String,
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsKey {
+        #[serde(alias = "Name")]
+        pub name: String,
+        #[serde(alias = "Id")]
+        pub id: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsKeyListResponse {
+        #[serde(alias = "Keys")]
+        pub keys: Vec<IpfsKey>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsNamePublishResponse {
+        #[serde(alias = "Name")]
+        pub name: String,
+        #[serde(alias = "Value")]
+        pub value: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct IpfsNameResolveResponse {
+        #[serde(alias = "Path")]
+        pub path: String,
+    }
+
+    /// Key and ipns names end up in ipfs query strings, so only allow the characters key
+    /// names, peer ids and dnslink domains are made of.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= 255
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    }
+
+    const DAG_PB: u64 = 0x70;
+    const DAG_CBOR: u64 = 0x71;
+    const LIBP2P_KEY: u64 = 0x72;
+    const RAW: u64 = 0x55;
+    const DAG_JSON: u64 = 0x0129;
+    const SUPPORTED_CODECS: [u64; 5] = [DAG_PB, DAG_CBOR, LIBP2P_KEY, RAW, DAG_JSON];
+
+    const IDENTITY: u64 = 0x00;
+    const SHA2_256: u64 = 0x12;
+    const SHA2_512: u64 = 0x13;
+    const SHA3_256: u64 = 0x16;
+    const BLAKE2B_256: u64 = 0xb220;
+    const SUPPORTED_MULTIHASHES: [u64; 5] = [IDENTITY, SHA2_256, SHA2_512, SHA3_256, BLAKE2B_256];
+
+    /// A cid checked to be well formed and to use a codec and multihash ipfs understands.
+    /// Sent over rpc and gossip as its string form, so hashes never reach the ipfs node
+    /// unvalidated.
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[serde(try_from = "String", into = "String")]
+    pub struct IpfsCid(Cid);
+
+    impl IpfsCid {
+        pub fn cid(&self) -> &Cid {
+            &self.0
+        }
+
+        /// Converts to cidv1, which is displayed in base32.
+        pub fn to_v1(self) -> Self {
+            match self.0.version() {
+                Version::V0 => Self(Cid::new_v1(self.0.codec(), *self.0.hash())),
+                Version::V1 => self,
+            }
+        }
+
+        /// Returns the cidv1 form when `to_v1` is set, otherwise the cid unchanged.
+        pub fn normalize(self, to_v1: bool) -> Self {
+            if to_v1 {
+                self.to_v1()
+            } else {
+                self
+            }
+        }
+    }
+
+    impl TryFrom<Cid> for IpfsCid {
+        type Error = InvalidCid;
+
+        fn try_from(cid: Cid) -> Result<Self, Self::Error> {
+            if !SUPPORTED_CODECS.contains(&cid.codec()) {
+                return Err(InvalidCid::UnsupportedCodec {
+                    cid: cid.to_string(),
+                    codec: cid.codec(),
+                });
+            }
+            if !SUPPORTED_MULTIHASHES.contains(&cid.hash().code()) {
+                return Err(InvalidCid::UnsupportedMultihash {
+                    cid: cid.to_string(),
+                    code: cid.hash().code(),
+                });
+            }
+            Ok(Self(cid))
+        }
+    }
+
+    impl FromStr for IpfsCid {
+        type Err = InvalidCid;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let cid = Cid::try_from(s).map_err(|err| InvalidCid::Malformed {
+                cid: s.to_string(),
+                reason: err.to_string(),
+            })?;
+            cid.try_into()
+        }
+    }
+
+    impl TryFrom<String> for IpfsCid {
+        type Error = InvalidCid;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            s.parse()
+        }
+    }
+
+    impl From<IpfsCid> for String {
+        fn from(cid: IpfsCid) -> Self {
+            cid.to_string()
+        }
+    }
+
+    impl fmt::Display for IpfsCid {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    #[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+    pub enum InvalidCid {
+        #[error("Invalid cid {cid}: {reason}")]
+        Malformed { cid: String, reason: String },
+
+        #[error("Unsupported codec 0x{codec:x} in cid {cid}")]
+        UnsupportedCodec { cid: String, codec: u64 },
+
+        #[error("Unsupported multihash 0x{code:x} in cid {cid}")]
+        UnsupportedMultihash { cid: String, code: u64 },
+    }
+}
+
+pub mod mfs {
+    use super::{ipfs::IpfsCid, *};
+    use std::{fmt, str::FromStr};
+
+    /// An absolute path in the mutable file system, kept free of the characters that would
+    /// change the meaning of an ipfs query string.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+    #[serde(try_from = "String", into = "String")]
+    pub struct MfsPath(String);
+
+    impl MfsPath {
+        pub fn root() -> Self {
+            Self("/".to_string())
+        }
+
+        pub fn is_root(&self) -> bool {
+            self.0 == "/"
+        }
+
+        pub fn components(&self) -> impl Iterator<Item = &str> {
+            self.0.split('/').filter(|c| !c.is_empty())
+        }
+
+        /// Splits off the last component, or returns `None` for the root.
+        pub fn split_last(&self) -> Option<(MfsPath, &str)> {
+            let (parent, name) = self.0.rsplit_once('/')?;
+            if name.is_empty() {
+                return None;
+            }
+            let parent = if parent.is_empty() { "/" } else { parent };
+            Some((Self(parent.to_string()), name))
+        }
+
+        /// Whether `self` is `other` or lies below it.
+        pub fn starts_with(&self, other: &MfsPath) -> bool {
+            let mut components = self.components();
+            other.components().all(|c| components.next() == Some(c))
+        }
+    }
+
+    impl FromStr for MfsPath {
+        type Err = InvalidMfsPath;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = |reason: &str| InvalidMfsPath {
+                path: s.to_string(),
+                reason: reason.to_string(),
+            };
+            if !s.starts_with('/') {
+                return Err(invalid("paths must be absolute"));
+            }
+            if s.chars()
+                .any(|c| c.is_control() || matches!(c, '&' | '?' | '#' | '%' | '+' | '\\'))
+            {
+                return Err(invalid(
+                    "paths cannot contain control characters or &?#%+\\",
+                ));
+            }
+
+            let components = s
+                .split('/')
+                .filter(|c| !c.is_empty())
+                .collect::<Vec<&str>>();
+            if components.iter().any(|c| *c == "." || *c == "..") {
+                return Err(invalid("paths cannot contain . or .. components"));
+            }
+            if components.iter().any(|c| c.len() > 255) {
+                return Err(invalid("path components are limited to 255 bytes"));
+            }
+
+            Ok(Self(format!("/{}", components.join("/"))))
+        }
+    }
+
+    impl TryFrom<String> for MfsPath {
+        type Error = InvalidMfsPath;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            s.parse()
+        }
+    }
+
+    impl From<MfsPath> for String {
+        fn from(path: MfsPath) -> Self {
+            path.0
+        }
+    }
+
+    impl fmt::Display for MfsPath {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    #[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+    #[error("Invalid path {path}: {reason}")]
+    pub struct InvalidMfsPath {
+        pub path: String,
+        pub reason: String,
+    }
+
+    /// What to copy into the mutable file system: content already in ipfs, written as
+    /// `/ipfs/<cid>`, or another path of the mutable file system.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    #[serde(try_from = "String", into = "String")]
+    pub enum MfsSource {
+        Ipfs(IpfsCid),
+        Mfs(MfsPath),
+    }
+
+    impl FromStr for MfsSource {
+        type Err = InvalidMfsSource;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s.strip_prefix("/ipfs/") {
+                Some(cid) => Ok(Self::Ipfs(cid.trim_end_matches('/').parse()?)),
+                None => Ok(Self::Mfs(s.parse()?)),
+            }
+        }
+    }
+
+    impl TryFrom<String> for MfsSource {
+        type Error = InvalidMfsSource;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            s.parse()
+        }
+    }
+
+    impl From<MfsSource> for String {
+        fn from(source: MfsSource) -> Self {
+            source.to_string()
+        }
+    }
+
+    impl fmt::Display for MfsSource {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Ipfs(cid) => write!(f, "/ipfs/{}", cid),
+                Self::Mfs(path) => path.fmt(f),
+            }
+        }
+    }
+
+    #[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+    pub enum InvalidMfsSource {
+        #[error(transparent)]
+        Cid(#[from] super::ipfs::InvalidCid),
+
+        #[error(transparent)]
+        Path(#[from] InvalidMfsPath),
+    }
+
+    /// Kubo reports entry types as the unixfs type number.
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+    #[serde(try_from = "u8", into = "u8")]
+    pub enum MfsEntryType {
+        File,
+        Directory,
+    }
+
+    impl TryFrom<u8> for MfsEntryType {
+        type Error = String;
+
+        fn try_from(value: u8) -> Result<Self, Self::Error> {
+            match value {
+                0 => Ok(Self::File),
+                1 => Ok(Self::Directory),
+                value => Err(format!("Unknown mfs entry type {}", value)),
+            }
+        }
+    }
+
+    impl From<MfsEntryType> for u8 {
+        fn from(value: MfsEntryType) -> Self {
+            match value {
+                MfsEntryType::File => 0,
+                MfsEntryType::Directory => 1,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MfsEntry {
+        #[serde(alias = "Name")]
+        pub name: String,
+        #[serde(rename = "type", alias = "Type")]
+        pub entry_type: MfsEntryType,
+        #[serde(alias = "Size")]
+        pub size: u64,
+        #[serde(alias = "Hash")]
+        pub hash: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MfsLsResponse {
+        /// Kubo sends null rather than an empty list for empty directories.
+        #[serde(alias = "Entries")]
+        pub entries: Option<Vec<MfsEntry>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MfsStatResponse {
+        #[serde(alias = "Hash")]
+        pub hash: String,
+        #[serde(alias = "Size")]
+        pub size: u64,
+        #[serde(alias = "CumulativeSize")]
+        pub cumulative_size: u64,
+        #[serde(alias = "Blocks")]
+        pub blocks: u64,
+        /// `file` or `directory`.
+        #[serde(rename = "type", alias = "Type")]
+        pub entry_type: String,
+    }
+
+    /// The root of the node's mutable file system after a change, which is gossiped so
+    /// peers can replicate the tree.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MfsChangeResponse {
+        pub root: IpfsCid,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MfsPeerRoot {
+        pub peer_id: String,
+        pub root: IpfsCid,
+    }
+
+    /// The roots peers last gossiped for their mutable file systems.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct MfsRootsResponse {
+        pub roots: Vec<MfsPeerRoot>,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ipfs::*, mfs::*};
+
+    #[test]
+    fn parses_and_normalizes_cids() {
+        let v0 = "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o"
+            .parse::<IpfsCid>()
+            .unwrap();
+        assert_eq!(
+            v0.to_v1().to_string(),
+            "bafybeicg2rebjoofv4kbyovkw7af3rpiitvnl6i7ckcywaq6xjcxnc2mby"
+        );
+        assert_eq!(v0.normalize(false), v0);
+
+        assert!(matches!(
+            "foo&recursive=false".parse::<IpfsCid>(),
+            Err(InvalidCid::Malformed { .. })
+        ));
+        // dag-pb cid hashed with murmur3
+        let cid = cid::Cid::new_v1(
+            0x70,
+            cid::multihash::Multihash::wrap(0x22, &[0; 4]).unwrap(),
+        );
+        assert!(matches!(
+            IpfsCid::try_from(cid),
+            Err(InvalidCid::UnsupportedMultihash { code: 0x22, .. })
+        ));
+    }
+
+    #[test]
+    fn parses_mfs_paths_and_sources() {
+        let path = "/docs//notes/".parse::<MfsPath>().unwrap();
+        assert_eq!(path.to_string(), "/docs/notes");
+        let (parent, name) = path.split_last().unwrap();
+        assert_eq!((parent.to_string().as_str(), name), ("/docs", "notes"));
+        assert!(path.starts_with(&parent));
+        assert!(MfsPath::root().split_last().is_none());
+
+        assert!("docs".parse::<MfsPath>().is_err());
+        assert!("/docs/../etc".parse::<MfsPath>().is_err());
+        assert!("/a&arg=/b".parse::<MfsPath>().is_err());
+
+        assert!(matches!(
+            "/ipfs/QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o".parse::<MfsSource>(),
+            Ok(MfsSource::Ipfs(_))
+        ));
+        assert!(matches!(
+            "/docs".parse::<MfsSource>(),
+            Ok(MfsSource::Mfs(_))
+        ));
+        assert!("/ipfs/foo".parse::<MfsSource>().is_err());
+    }
 }
@@ -0,0 +1,247 @@
+//! CARv1 archives: a dag-cbor header naming the root cids, followed by every block of
+//! the dags prefixed with its cid, as written by kubo's `dag/export`.
+
+use bytes::Bytes;
+use cid::Cid;
+use std::io::Cursor;
+
+use super::{
+    unixfs::{read_varint, write_varint, Block},
+    BackendError,
+};
+
+const CBOR_UINT: u8 = 0;
+const CBOR_BYTES: u8 = 2;
+const CBOR_TEXT: u8 = 3;
+const CBOR_ARRAY: u8 = 4;
+const CBOR_MAP: u8 = 5;
+const CBOR_TAG: u8 = 6;
+/// Cbor tag dag-cbor uses for cids.
+const CID_TAG: u64 = 42;
+
+pub struct Car {
+    pub roots: Vec<Cid>,
+    pub blocks: Vec<Block>,
+}
+
+pub fn write_car(roots: &[Cid], blocks: &[Block]) -> Bytes {
+    let mut header = Vec::new();
+    // dag-cbor orders map keys by length, so roots comes before version
+    write_cbor_head(&mut header, CBOR_MAP, 2);
+    write_cbor_text(&mut header, "roots");
+    write_cbor_head(&mut header, CBOR_ARRAY, roots.len() as u64);
+    for root in roots {
+        write_cbor_head(&mut header, CBOR_TAG, CID_TAG);
+        // cids in dag-cbor carry the identity multibase prefix
+        let mut cid = vec![0];
+        cid.extend_from_slice(&root.to_bytes());
+        write_cbor_head(&mut header, CBOR_BYTES, cid.len() as u64);
+        header.extend_from_slice(&cid);
+    }
+    write_cbor_text(&mut header, "version");
+    write_cbor_head(&mut header, CBOR_UINT, 1);
+
+    let mut car = Vec::new();
+    write_varint(&mut car, header.len() as u64);
+    car.extend_from_slice(&header);
+    for block in blocks {
+        let cid = block.cid.to_bytes();
+        write_varint(&mut car, (cid.len() + block.data.len()) as u64);
+        car.extend_from_slice(&cid);
+        car.extend_from_slice(&block.data);
+    }
+
+    Bytes::from(car)
+}
+
+/// Parses a car without checking its blocks against their cids.
+pub fn read_car(mut car: &[u8]) -> Result<Car, BackendError> {
+    let header = take(&mut car)?;
+    let roots = read_header(header)?;
+
+    let mut blocks = Vec::new();
+    while !car.is_empty() {
+        let section = take(&mut car)?;
+        let mut reader = Cursor::new(section);
+        let cid = Cid::read_bytes(&mut reader)?;
+        let data = &section[reader.position() as usize..];
+        blocks.push(Block {
+            cid,
+            data: Bytes::copy_from_slice(data),
+        });
+    }
+
+    Ok(Car { roots, blocks })
+}
+
+/// Splits off the next length prefixed section.
+fn take<'a>(car: &mut &'a [u8]) -> Result<&'a [u8], BackendError> {
+    let len = read_varint(car)? as usize;
+    if len > car.len() {
+        return Err(invalid("Truncated car section"));
+    }
+    let (section, rest) = car.split_at(len);
+    *car = rest;
+    Ok(section)
+}
+
+fn read_header(mut header: &[u8]) -> Result<Vec<Cid>, BackendError> {
+    let (major, entries) = read_cbor_head(&mut header)?;
+    if major != CBOR_MAP {
+        return Err(invalid("Car header is not a map"));
+    }
+
+    let mut roots = None;
+    let mut version = None;
+    for _ in 0..entries {
+        let key = read_cbor_text(&mut header)?;
+        match key {
+            "roots" => {
+                let (major, len) = read_cbor_head(&mut header)?;
+                if major != CBOR_ARRAY {
+                    return Err(invalid("Car roots are not an array"));
+                }
+                let mut cids = Vec::new();
+                for _ in 0..len {
+                    cids.push(read_cbor_cid(&mut header)?);
+                }
+                roots = Some(cids);
+            }
+            "version" => match read_cbor_head(&mut header)? {
+                (CBOR_UINT, value) => version = Some(value),
+                _ => return Err(invalid("Car version is not a number")),
+            },
+            _ => return Err(invalid("Unexpected car header field")),
+        }
+    }
+
+    match version {
+        Some(1) => roots.ok_or_else(|| invalid("Car header is missing its roots")),
+        Some(version) => Err(BackendError::InvalidBlock(format!(
+            "Unsupported car version {}",
+            version
+        ))),
+        None => Err(invalid("Car header is missing its version")),
+    }
+}
+
+fn read_cbor_cid(buf: &mut &[u8]) -> Result<Cid, BackendError> {
+    if read_cbor_head(buf)? != (CBOR_TAG, CID_TAG) {
+        return Err(invalid("Car root is not a cid"));
+    }
+    match read_cbor_head(buf)? {
+        (CBOR_BYTES, len) if len as usize <= buf.len() && len > 0 => {
+            let (cid, rest) = buf.split_at(len as usize);
+            *buf = rest;
+            Ok(Cid::try_from(&cid[1..])?)
+        }
+        _ => Err(invalid("Car root is not a cid")),
+    }
+}
+
+fn read_cbor_text<'a>(buf: &mut &'a [u8]) -> Result<&'a str, BackendError> {
+    match read_cbor_head(buf)? {
+        (CBOR_TEXT, len) if len as usize <= buf.len() => {
+            let (text, rest) = buf.split_at(len as usize);
+            *buf = rest;
+            std::str::from_utf8(text).map_err(|_| invalid("Car header key is not utf-8"))
+        }
+        _ => Err(invalid("Car header key is not a string")),
+    }
+}
+
+/// Reads a cbor major type and its argument.
+fn read_cbor_head(buf: &mut &[u8]) -> Result<(u8, u64), BackendError> {
+    let (&initial, rest) = buf
+        .split_first()
+        .ok_or_else(|| invalid("Truncated car header"))?;
+    *buf = rest;
+
+    let major = initial >> 5;
+    let len = match initial & 0x1f {
+        info @ 0..=23 => return Ok((major, u64::from(info))),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(invalid("Unsupported cbor in car header")),
+    };
+    if len > buf.len() {
+        return Err(invalid("Truncated car header"));
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    let value = bytes
+        .iter()
+        .fold(0u64, |value, byte| (value << 8) | u64::from(*byte));
+    Ok((major, value))
+}
+
+fn write_cbor_head(buf: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => buf.push(major | value as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn write_cbor_text(buf: &mut Vec<u8>, text: &str) {
+    write_cbor_head(buf, CBOR_TEXT, text.len() as u64);
+    buf.extend_from_slice(text.as_bytes());
+}
+
+fn invalid(reason: &str) -> BackendError {
+    BackendError::InvalidBlock(reason.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{api::types::ipfs::CidVersion, backend::unixfs::build_file};
+
+    #[test]
+    fn cars_round_trip() {
+        let (root, blocks) = build_file(&vec![3u8; 300 * 1024], CidVersion::V1).unwrap();
+        let car = write_car(&[root], &blocks);
+
+        let parsed = read_car(&car).unwrap();
+        assert_eq!(parsed.roots, vec![root]);
+        assert_eq!(parsed.blocks.len(), blocks.len());
+        for (parsed, block) in parsed.blocks.iter().zip(&blocks) {
+            assert_eq!(parsed.cid, block.cid);
+            assert_eq!(parsed.data, block.data);
+        }
+
+        assert!(read_car(&car[..car.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn reads_kubo_car_header() {
+        // header of `ipfs dag export QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn`
+        let root = Cid::try_from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+        let mut header = vec![0xa2, 0x65];
+        header.extend_from_slice(b"roots");
+        header.extend_from_slice(&[0x81, 0xd8, 0x2a, 0x58, 0x23, 0x00]);
+        header.extend_from_slice(&root.to_bytes());
+        header.push(0x67);
+        header.extend_from_slice(b"version");
+        header.push(0x01);
+
+        let mut car = vec![header.len() as u8];
+        car.extend_from_slice(&header);
+        assert_eq!(&write_car(&[root], &[])[..], &car[..]);
+        assert_eq!(read_car(&car).unwrap().roots, vec![root]);
+    }
+}
@@ -0,0 +1,99 @@
+//! Dag inspection, export and import for backends that hold their blocks themselves,
+//! answering the way kubo's `dag/stat`, `ls`, `refs`, `dag/export` and `dag/import` do.
+
+use bytes::Bytes;
+use cid::Cid;
+use std::collections::HashSet;
+
+use super::{
+    car::{self, Car},
+    mfs::DagStore,
+    unixfs::{self, Block},
+    BackendError,
+};
+use crate::api::types::ipfs::{
+    IpfsLink, IpfsLinkType, IpfsLsResponse, IpfsRefsResponse, IpfsStatResponse,
+};
+
+pub async fn stat<S: DagStore>(store: &S, cid: &Cid) -> Result<IpfsStatResponse, BackendError> {
+    let blocks = unique_blocks(store, cid).await?;
+
+    Ok(IpfsStatResponse {
+        hash: cid.to_string(),
+        cumulative_size: blocks.iter().map(|block| block.data.len() as u64).sum(),
+        num_blocks: blocks.len() as u64,
+    })
+}
+
+pub async fn ls<S: DagStore>(store: &S, cid: &Cid) -> Result<IpfsLsResponse, BackendError> {
+    let block = store.get_node(cid).await?;
+    let mut links = Vec::new();
+    if cid.codec() == unixfs::DAG_PB {
+        for link in unixfs::decode_node(&block)?.links {
+            let info = unixfs::node_info(&link.cid, &store.get_node(&link.cid).await?)?;
+            links.push(IpfsLink {
+                name: link.name,
+                hash: link.cid.to_string(),
+                size: info.filesize,
+                link_type: match info.is_directory {
+                    true => IpfsLinkType::Directory,
+                    false => IpfsLinkType::File,
+                },
+            });
+        }
+    }
+
+    Ok(IpfsLsResponse {
+        hash: cid.to_string(),
+        links,
+    })
+}
+
+pub async fn refs<S: DagStore>(store: &S, cid: &Cid) -> Result<IpfsRefsResponse, BackendError> {
+    let refs = unique_blocks(store, cid)
+        .await?
+        .into_iter()
+        .skip(1)
+        .map(|block| block.cid.to_string())
+        .collect();
+
+    Ok(IpfsRefsResponse {
+        hash: cid.to_string(),
+        refs,
+    })
+}
+
+/// Writes the dag rooted at `cid` to a car.
+pub async fn export<S: DagStore>(store: &S, cid: &Cid) -> Result<Bytes, BackendError> {
+    let blocks = unique_blocks(store, cid).await?;
+    Ok(car::write_car(&[*cid], &blocks))
+}
+
+/// Stores every block of `car` after checking it against its cid, and returns the roots
+/// for the caller to pin.
+pub async fn import<S: DagStore>(store: &S, car: &[u8]) -> Result<Vec<Cid>, BackendError> {
+    let Car { roots, blocks } = car::read_car(car)?;
+    for block in blocks {
+        unixfs::verify_block(&block.cid, &block.data)?;
+        store.put_node(block).await?;
+    }
+
+    Ok(roots)
+}
+
+/// Returns `cid` and every block below it, once each, depth first.
+async fn unique_blocks<S: DagStore>(store: &S, cid: &Cid) -> Result<Vec<Block>, BackendError> {
+    let mut seen = HashSet::new();
+    let mut blocks = Vec::new();
+    let mut stack = vec![*cid];
+    while let Some(cid) = stack.pop() {
+        if !seen.insert(cid) {
+            continue;
+        }
+        let data = store.get_node(&cid).await?;
+        stack.extend(unixfs::links(&cid, &data)?.into_iter().rev());
+        blocks.push(Block { cid, data });
+    }
+
+    Ok(blocks)
+}
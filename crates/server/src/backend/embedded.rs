@@ -0,0 +1,538 @@
+use bytes::Bytes;
+use cid::Cid;
+use futures::FutureExt;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+use tokio::{fs, sync::Mutex};
+use tracing::{info, warn};
+
+use super::{
+    dag, fetch_block,
+    mfs::{DagStore, MfsTree},
+    unixfs::{self, Block},
+    BackendError, IpfsBackend,
+};
+use crate::{
+    api::types::{
+        ipfs::{
+            CidVersion, IpfsAddResponse, IpfsDagImportResponse, IpfsIdResponse, IpfsKey,
+            IpfsKeyListResponse, IpfsLsResponse, IpfsNamePublishResponse, IpfsNameResolveResponse,
+            IpfsPinAddResponse, IpfsPinLsResponse, IpfsPinRmResponse, IpfsRefsResponse,
+            IpfsRepoStatResponse, IpfsStatResponse,
+        },
+        mfs::{MfsLsResponse, MfsPath, MfsSource, MfsStatResponse},
+    },
+    network::{BlockProviderFn, NetworkClient},
+};
+
+/// Stores blocks on disk under `repo` and fetches missing blocks from cluster peers over
+/// the swarm's block exchange, so the server does not need a kubo daemon.
+#[derive(Clone)]
+pub struct EmbeddedBackend {
+    repo: PathBuf,
+    pins: Arc<Mutex<HashSet<Cid>>>,
+    names: Arc<Mutex<Names>>,
+    mfs_root: Arc<Mutex<Option<Cid>>>,
+    network_client: Arc<OnceLock<NetworkClient>>,
+}
+
+/// Ipns keys and the records published with them. Records are only known locally, the
+/// rest of the cluster learns about them through gossip.
+#[derive(Serialize, Deserialize, Default)]
+struct Names {
+    keys: HashMap<String, String>,
+    records: HashMap<String, String>,
+}
+
+impl EmbeddedBackend {
+    /// Opens the repo at `repo`, creating it if it does not exist.
+    pub async fn open(repo: impl Into<PathBuf>) -> Result<Self, BackendError> {
+        let repo = repo.into();
+        fs::create_dir_all(repo.join("blocks")).await?;
+
+        let pins = match fs::read(repo.join("pins.json")).await {
+            Ok(contents) => serde_json::from_slice::<Vec<String>>(&contents)?
+                .iter()
+                .map(|pin| Cid::try_from(pin.as_str()))
+                .collect::<Result<HashSet<Cid>, cid::Error>>()?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let names = match fs::read(repo.join("names.json")).await {
+            Ok(contents) => serde_json::from_slice::<Names>(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Names::default(),
+            Err(err) => return Err(err.into()),
+        };
+        let mfs_root = match fs::read_to_string(repo.join("mfs_root")).await {
+            Ok(root) => Some(Cid::try_from(root.trim())?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        info!("Opened embedded ipfs repo at {}", repo.display());
+
+        Ok(Self {
+            repo,
+            pins: Arc::new(Mutex::new(pins)),
+            names: Arc::new(Mutex::new(names)),
+            mfs_root: Arc::new(Mutex::new(mfs_root)),
+            network_client: Arc::new(OnceLock::new()),
+        })
+    }
+
+    /// Lets the backend fetch blocks from peers. The network is started after the
+    /// backend because it serves blocks from it, see `block_provider`.
+    pub fn set_network_client(&self, network_client: NetworkClient) {
+        if self.network_client.set(network_client).is_err() {
+            warn!("Embedded ipfs network client is already set");
+        }
+    }
+
+    /// Serves blocks held in this repo to peers.
+    pub fn block_provider(&self) -> BlockProviderFn {
+        let backend = self.clone();
+        Arc::new(move |cid| {
+            let backend = backend.clone();
+            async move { backend.read_block(&cid).await }.boxed()
+        })
+    }
+
+    fn block_path(&self, cid: &Cid) -> PathBuf {
+        self.repo.join("blocks").join(cid.to_string())
+    }
+
+    async fn read_block(&self, cid: &Cid) -> Option<Bytes> {
+        fs::read(self.block_path(cid)).await.ok().map(Bytes::from)
+    }
+
+    async fn write_block(&self, cid: &Cid, block: &[u8]) -> Result<(), BackendError> {
+        let path = self.block_path(cid);
+        // write then rename so a crash never leaves a partial block behind
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, block).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    /// Returns a block from the repo, fetching it from cluster peers if needed.
+    async fn get_block(&self, cid: &Cid) -> Result<Bytes, BackendError> {
+        if let Some(block) = self.read_block(cid).await {
+            return Ok(block);
+        }
+
+        let block = self.fetch_block(cid).await?;
+        self.write_block(cid, &block).await?;
+        Ok(block)
+    }
+
+    async fn fetch_block(&self, cid: &Cid) -> Result<Bytes, BackendError> {
+        match self.network_client.get() {
+            Some(network_client) => fetch_block(network_client, cid).await,
+            None => Err(BackendError::NotFound(format!("{} not found", cid))),
+        }
+    }
+
+    /// Walks the dag rooted at `cid` depth first, calling `visit` with each block.
+    async fn walk(
+        &self,
+        cid: &Cid,
+        mut visit: impl FnMut(&Cid, &Bytes) -> Result<(), BackendError> + Send,
+    ) -> Result<(), BackendError> {
+        let mut stack = vec![*cid];
+        while let Some(cid) = stack.pop() {
+            let block = self.get_block(&cid).await?;
+            visit(&cid, &block)?;
+            stack.extend(unixfs::links(&cid, &block)?.into_iter().rev());
+        }
+        Ok(())
+    }
+
+    async fn persist_names(&self, names: &Names) -> Result<(), BackendError> {
+        fs::write(self.repo.join("names.json"), serde_json::to_vec(names)?).await?;
+        Ok(())
+    }
+
+    async fn self_id(&self) -> Result<String, BackendError> {
+        let network_client = self
+            .network_client
+            .get()
+            .ok_or(BackendError::EmptyResponse)?;
+        Ok(network_client.get_peer_id().await?.to_string())
+    }
+
+    /// Saves the root of a changed mfs tree so it survives restarts.
+    async fn set_mfs_root(
+        &self,
+        root: &mut Option<Cid>,
+        tree: MfsTree<'_, Self>,
+    ) -> Result<(), BackendError> {
+        fs::write(self.repo.join("mfs_root"), tree.root().to_string()).await?;
+        *root = Some(tree.root());
+        Ok(())
+    }
+
+    async fn persist_pins(&self, pins: &HashSet<Cid>) -> Result<(), BackendError> {
+        let pins = pins
+            .iter()
+            .map(|pin| pin.to_string())
+            .collect::<Vec<String>>();
+        fs::write(self.repo.join("pins.json"), serde_json::to_vec(&pins)?).await?;
+        Ok(())
+    }
+}
+
+impl IpfsBackend for EmbeddedBackend {
+    async fn id(&self) -> Result<IpfsIdResponse, BackendError> {
+        let network_client = self
+            .network_client
+            .get()
+            .ok_or(BackendError::EmptyResponse)?;
+
+        Ok(IpfsIdResponse {
+            id: network_client.get_peer_id().await?.to_string(),
+        })
+    }
+
+    async fn add(
+        &self,
+        data: Vec<u8>,
+        cid_version: CidVersion,
+    ) -> Result<IpfsAddResponse, BackendError> {
+        let (root, blocks) = unixfs::build_file(&data, cid_version)?;
+        for block in blocks {
+            self.write_block(&block.cid, &block.data).await?;
+        }
+
+        let mut pins = self.pins.lock().await;
+        pins.insert(root);
+        self.persist_pins(&pins).await?;
+
+        Ok(IpfsAddResponse {
+            hash: root.to_string(),
+            name: root.to_string(),
+        })
+    }
+
+    async fn cat(&self, hash: &str) -> Result<Bytes, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let mut contents = Vec::new();
+        self.walk(&cid, |cid, block| {
+            contents.extend_from_slice(&unixfs::file_data(cid, block)?);
+            Ok(())
+        })
+        .await?;
+
+        Ok(Bytes::from(contents))
+    }
+
+    async fn pin_add(&self, hash: &str) -> Result<IpfsPinAddResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        self.walk(&cid, |_, _| Ok(())).await?;
+
+        let mut pins = self.pins.lock().await;
+        pins.insert(cid);
+        self.persist_pins(&pins).await?;
+
+        Ok(IpfsPinAddResponse {
+            pins: vec![cid.to_string()],
+        })
+    }
+
+    async fn pin_rm(&self, hash: &str) -> Result<IpfsPinRmResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let mut pins = self.pins.lock().await;
+        if !pins.remove(&cid) {
+            return Err(BackendError::NotFound(format!("{} is not pinned", hash)));
+        }
+        self.persist_pins(&pins).await?;
+
+        Ok(IpfsPinRmResponse {
+            pins: vec![cid.to_string()],
+        })
+    }
+
+    async fn pin_ls(&self) -> Result<IpfsPinLsResponse, BackendError> {
+        let keys = self
+            .pins
+            .lock()
+            .await
+            .iter()
+            .map(|cid| (cid.to_string(), json!({ "Type": "recursive", "Name": "" })))
+            .collect::<Map<String, Value>>();
+
+        Ok(IpfsPinLsResponse {
+            keys: Value::Object(keys),
+        })
+    }
+
+    async fn repo_stat(&self) -> Result<IpfsRepoStatResponse, BackendError> {
+        let blocks = stored_blocks(&self.repo.join("blocks")).await?;
+
+        Ok(IpfsRepoStatResponse {
+            repo_size: blocks.iter().map(|(_, size)| size).sum(),
+            storage_max: u64::MAX,
+            num_objects: blocks.len() as u64,
+        })
+    }
+
+    async fn stat(&self, hash: &str) -> Result<u64, BackendError> {
+        let mut size = 0;
+        let mut stack = vec![Cid::try_from(hash)?];
+        while let Some(cid) = stack.pop() {
+            let block = self
+                .read_block(&cid)
+                .await
+                .ok_or_else(|| BackendError::NotFound(format!("Block {} is not local", cid)))?;
+            size += block.len() as u64;
+            stack.extend(unixfs::links(&cid, &block)?);
+        }
+
+        Ok(size)
+    }
+
+    async fn dag_stat(&self, hash: &str) -> Result<IpfsStatResponse, BackendError> {
+        dag::stat(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn ls(&self, hash: &str) -> Result<IpfsLsResponse, BackendError> {
+        dag::ls(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn refs(&self, hash: &str) -> Result<IpfsRefsResponse, BackendError> {
+        dag::refs(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn block_get(&self, hash: &str) -> Result<Bytes, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        self.get_block(&cid).await
+    }
+
+    async fn dag_export(&self, hash: &str) -> Result<Bytes, BackendError> {
+        dag::export(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn dag_import(&self, car: Bytes) -> Result<IpfsDagImportResponse, BackendError> {
+        let mut roots = Vec::new();
+        for root in dag::import(self, &car).await? {
+            roots.extend(self.pin_add(&root.to_string()).await?.pins);
+        }
+
+        Ok(IpfsDagImportResponse { roots })
+    }
+
+    async fn repo_gc(&self) -> Result<usize, BackendError> {
+        let mut roots = self.pins.lock().await.clone();
+        // like kubo, the mfs tree is kept without being pinned
+        roots.extend(*self.mfs_root.lock().await);
+        let mut reachable = HashSet::new();
+        for pin in roots {
+            self.walk(&pin, |cid, _| {
+                reachable.insert(*cid);
+                Ok(())
+            })
+            .await?;
+        }
+
+        let mut removed = 0;
+        for (cid, _) in stored_blocks(&self.repo.join("blocks")).await? {
+            if !reachable.contains(&cid) {
+                fs::remove_file(self.block_path(&cid)).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    async fn key_gen(&self, name: &str) -> Result<IpfsKey, BackendError> {
+        let id = PeerId::random().to_string();
+        let mut names = self.names.lock().await;
+        names.keys.insert(name.to_string(), id.clone());
+        self.persist_names(&names).await?;
+
+        Ok(IpfsKey {
+            name: name.to_string(),
+            id,
+        })
+    }
+
+    async fn key_list(&self) -> Result<IpfsKeyListResponse, BackendError> {
+        let self_key = IpfsKey {
+            name: "self".to_string(),
+            id: self.self_id().await?,
+        };
+        let keys = std::iter::once(self_key)
+            .chain(
+                self.names
+                    .lock()
+                    .await
+                    .keys
+                    .iter()
+                    .map(|(name, id)| IpfsKey {
+                        name: name.clone(),
+                        id: id.clone(),
+                    }),
+            )
+            .collect::<Vec<IpfsKey>>();
+
+        Ok(IpfsKeyListResponse { keys })
+    }
+
+    async fn name_publish(
+        &self,
+        hash: &str,
+        key: &str,
+    ) -> Result<IpfsNamePublishResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let mut names = self.names.lock().await;
+        let id = match key {
+            "self" => self.self_id().await?,
+            _ => names
+                .keys
+                .get(key)
+                .cloned()
+                .ok_or_else(|| BackendError::NotFound(format!("no key named {}", key)))?,
+        };
+        let value = format!("/ipfs/{}", cid);
+        names.records.insert(id.clone(), value.clone());
+        self.persist_names(&names).await?;
+
+        Ok(IpfsNamePublishResponse { name: id, value })
+    }
+
+    async fn name_resolve(&self, name: &str) -> Result<IpfsNameResolveResponse, BackendError> {
+        let path = self
+            .names
+            .lock()
+            .await
+            .records
+            .get(name)
+            .cloned()
+            .ok_or_else(|| BackendError::NotFound(format!("could not resolve name {}", name)))?;
+
+        Ok(IpfsNameResolveResponse { path })
+    }
+
+    async fn files_ls(&self, path: &MfsPath) -> Result<MfsLsResponse, BackendError> {
+        let root = *self.mfs_root.lock().await;
+        MfsTree::open(self, root).await?.ls(path).await
+    }
+
+    async fn files_mkdir(&self, path: &MfsPath, parents: bool) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.mkdir(path, parents).await?;
+        self.set_mfs_root(&mut root, tree).await
+    }
+
+    async fn files_cp(&self, from: &MfsSource, to: &MfsPath) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        let cid = match from {
+            MfsSource::Ipfs(cid) => *cid.cid(),
+            MfsSource::Mfs(path) => tree.resolve(path).await?,
+        };
+        tree.cp(cid, to).await?;
+        self.set_mfs_root(&mut root, tree).await
+    }
+
+    async fn files_mv(&self, from: &MfsPath, to: &MfsPath) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.mv(from, to).await?;
+        self.set_mfs_root(&mut root, tree).await
+    }
+
+    async fn files_rm(&self, path: &MfsPath, recursive: bool) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.rm(path, recursive).await?;
+        self.set_mfs_root(&mut root, tree).await
+    }
+
+    async fn files_stat(&self, path: &MfsPath) -> Result<MfsStatResponse, BackendError> {
+        let root = *self.mfs_root.lock().await;
+        MfsTree::open(self, root).await?.stat(path).await
+    }
+
+    async fn files_write(
+        &self,
+        path: &MfsPath,
+        data: Vec<u8>,
+        create: bool,
+    ) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.write(path, &data, create).await?;
+        self.set_mfs_root(&mut root, tree).await
+    }
+
+    async fn files_read(&self, path: &MfsPath) -> Result<Bytes, BackendError> {
+        let root = *self.mfs_root.lock().await;
+        let cid = MfsTree::open(self, root).await?.file(path).await?;
+        self.cat(&cid.to_string()).await
+    }
+}
+
+impl DagStore for EmbeddedBackend {
+    async fn get_node(&self, cid: &Cid) -> Result<Bytes, BackendError> {
+        self.get_block(cid).await
+    }
+
+    async fn put_node(&self, block: Block) -> Result<(), BackendError> {
+        self.write_block(&block.cid, &block.data).await
+    }
+}
+
+/// Lists the cid and size of every block in `dir`.
+async fn stored_blocks(dir: &Path) -> Result<Vec<(Cid, u64)>, BackendError> {
+    let mut blocks = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(cid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Cid::try_from(name).ok())
+        else {
+            continue;
+        };
+        blocks.push((cid, entry.metadata().await?.len()));
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn blocks_and_pins_survive_reopening_the_repo() {
+        let repo = tempfile::tempdir().unwrap();
+        let data = vec![7u8; 300 * 1024];
+
+        let backend = EmbeddedBackend::open(repo.path()).await.unwrap();
+        let hash = backend
+            .add(data.clone(), CidVersion::V1)
+            .await
+            .unwrap()
+            .hash;
+        let unpinned = backend
+            .add(b"unpinned".to_vec(), CidVersion::V0)
+            .await
+            .unwrap()
+            .hash;
+        backend.pin_rm(&unpinned).await.unwrap();
+
+        let backend = EmbeddedBackend::open(repo.path()).await.unwrap();
+        assert_eq!(backend.cat(&hash).await.unwrap(), Bytes::from(data));
+        assert_eq!(backend.repo_gc().await.unwrap(), 1);
+        assert!(backend.pin_ls().await.unwrap().keys.get(&hash).is_some());
+        assert!(backend.cat(&unpinned).await.is_err());
+    }
+}
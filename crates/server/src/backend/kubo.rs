@@ -0,0 +1,600 @@
+use bytes::Bytes;
+use cid::Cid;
+use futures::{future::BoxFuture, stream, FutureExt, TryStreamExt};
+use reqwest::{
+    multipart::{Form, Part},
+    Body, Client,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+use tokio::{fs::File, io::AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+use super::{fetch_block, unixfs, BackendError, IpfsBackend};
+use crate::{
+    api::types::{
+        ipfs::{
+            CidVersion, IpfsAddResponse, IpfsBlockPutResponse, IpfsDagImportLine,
+            IpfsDagImportResponse, IpfsDagStatResponse, IpfsIdResponse, IpfsKey,
+            IpfsKeyListResponse, IpfsLsObjectsResponse, IpfsLsResponse, IpfsNamePublishResponse,
+            IpfsNameResolveResponse, IpfsObjectStatResponse, IpfsPinAddResponse, IpfsPinLsResponse,
+            IpfsPinRmResponse, IpfsRefResponse, IpfsRefsResponse, IpfsRepoStatResponse,
+            IpfsStatResponse,
+        },
+        mfs::{MfsLsResponse, MfsPath, MfsSource, MfsStatResponse},
+    },
+    network::{BlockProviderFn, NetworkClient},
+    rpc::{fetch_with_retry, retry::RetryPolicy, send_with_retry, Call},
+    state::StateClient,
+};
+
+/// Talks to a kubo node over its http rpc api.
+#[derive(Clone)]
+pub struct KuboBackend<C> {
+    base_url: String,
+    client: C,
+    retry_policy: RetryPolicy,
+    state_client: StateClient,
+    network_client: Arc<OnceLock<NetworkClient>>,
+}
+
+impl<C> KuboBackend<C>
+where
+    C: HttpClient + Clone + std::marker::Send + std::marker::Sync + 'static,
+{
+    pub fn new(
+        base_url: impl Into<String>,
+        client: C,
+        retry_policy: RetryPolicy,
+        state_client: StateClient,
+    ) -> Self {
+        Self {
+            base_url: base_url.into(),
+            client,
+            retry_policy,
+            state_client,
+            network_client: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Lets pins fetch blocks kubo is missing from cluster peers instead of relying on
+    /// kubo finding them through the public dht.
+    pub fn set_network_client(&self, network_client: NetworkClient) {
+        if self.network_client.set(network_client).is_err() {
+            warn!("Kubo network client is already set");
+        }
+    }
+
+    /// Serves blocks held by the local kubo node to peers.
+    pub fn block_provider(&self) -> BlockProviderFn {
+        let backend = self.clone();
+        Arc::new(move |cid| {
+            let backend = backend.clone();
+            async move { backend.local_block(&cid).await.ok().flatten() }.boxed()
+        })
+    }
+
+    /// Returns a block if the local kubo node has it, without asking the ipfs network.
+    async fn local_block(&self, cid: &Cid) -> Result<Option<Bytes>, BackendError> {
+        let url = self.url(&format!("block/get?arg={}&offline=true", cid));
+        let response = self.client.post(url).await?;
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+
+        Ok(Some(response.bytes().await?))
+    }
+
+    /// Pins `cid` if the local kubo node has all of it, without asking the ipfs network.
+    /// A failure is not retried, it only means blocks are missing.
+    async fn local_pin(&self, cid: &Cid) -> Result<Option<IpfsPinAddResponse>, BackendError> {
+        let url = self.url(&format!("pin/add?arg={}&offline=true", cid));
+        let response = self.client.post(url).await?;
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_slice(&response.bytes().await?)?))
+    }
+
+    async fn block_put(&self, cid: &Cid, block: Bytes) -> Result<(), BackendError> {
+        let codec = match cid.codec() {
+            unixfs::DAG_PB => "dag-pb",
+            unixfs::RAW => "raw",
+            codec => {
+                return Err(BackendError::InvalidBlock(format!(
+                    "Unsupported codec 0x{:x} for {}",
+                    codec, cid
+                )))
+            }
+        };
+        let url = self.url(&format!("block/put?cid-codec={}&mhtype=sha2-256", codec));
+
+        let request = || {
+            let part = Part::stream(Body::from(block.clone()));
+            let form = Form::new().part("data", part);
+            self.client.post_multipart(url.clone(), form).boxed()
+        };
+
+        self.call_with_retry::<IpfsBlockPutResponse>(request)
+            .await?
+            .ok_or(BackendError::EmptyResponse)?;
+        Ok(())
+    }
+
+    /// Copies every block of the dag rooted at `cid` that kubo is missing from cluster
+    /// peers into kubo.
+    async fn import_dag(
+        &self,
+        network_client: &NetworkClient,
+        cid: &Cid,
+    ) -> Result<usize, BackendError> {
+        let mut imported = 0;
+        let mut stack = vec![*cid];
+        while let Some(cid) = stack.pop() {
+            let block = match self.local_block(&cid).await? {
+                Some(block) => block,
+                None => {
+                    let block = fetch_block(network_client, &cid).await?;
+                    self.block_put(&cid, block.clone()).await?;
+                    imported += 1;
+                    block
+                }
+            };
+            stack.extend(unixfs::links(&cid, &block)?);
+        }
+
+        Ok(imported)
+    }
+
+    async fn import_from_peers(&self, cid: &Cid) {
+        if let Some(network_client) = self.network_client.get() {
+            match self.import_dag(network_client, cid).await {
+                Ok(imported) => info!("Imported {} blocks of {} from peers", imported, cid),
+                // kubo can still find the content itself unless the cluster is air-gapped
+                Err(err) => warn!("Unable to import {} from peers: {}", cid, err),
+            }
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v0/{}", self.base_url, path)
+    }
+
+    async fn post<D>(&self, path: &str) -> Result<Option<D>, BackendError>
+    where
+        D: DeserializeOwned,
+    {
+        let url = self.url(path);
+        let request = || self.client.post(url.clone()).boxed();
+        self.call_with_retry(request).await
+    }
+
+    /// Posts to `path` and returns the raw response body, for endpoints that stream data
+    /// or one json object per line.
+    async fn post_bytes(&self, path: &str) -> Result<Bytes, BackendError> {
+        let url = self.url(path);
+        let request = || self.client.post(url.clone()).boxed();
+        fetch_with_retry::<BackendError>(&self.retry_policy, &self.state_client, request).await
+    }
+
+    /// Adds the file `body` returns, calling it again for each retry.
+    async fn add_body(
+        &self,
+        body: impl Fn() -> Body,
+        cid_version: CidVersion,
+    ) -> Result<IpfsAddResponse, BackendError> {
+        let cid_version = match cid_version {
+            CidVersion::V0 => 0,
+            CidVersion::V1 => 1,
+        };
+        let url = self.url(&format!("add?cid-version={}", cid_version));
+
+        let request = || {
+            let form = Form::new().part("file", Part::stream(body()));
+            self.client.post_multipart(url.clone(), form).boxed()
+        };
+
+        self.call_with_retry(request)
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    /// Posts the car `body` returns to `dag/import`, calling it again for each retry.
+    async fn dag_import_body(
+        &self,
+        body: impl Fn() -> Body,
+    ) -> Result<IpfsDagImportResponse, BackendError> {
+        let url = self.url("dag/import?pin-roots=true");
+        let request = || {
+            let form = Form::new().part("file", Part::stream(body()));
+            self.client.post_multipart(url.clone(), form).boxed()
+        };
+        let body =
+            fetch_with_retry::<BackendError>(&self.retry_policy, &self.state_client, request)
+                .await?;
+        let body = String::from_utf8_lossy(&body);
+
+        // streamed as one json object per line
+        let mut roots = Vec::new();
+        for line in body.lines().filter(|l| !l.trim().is_empty()) {
+            let Some(root) = serde_json::from_str::<IpfsDagImportLine>(line)?.root else {
+                continue;
+            };
+            if !root.pin_error_msg.is_empty() {
+                return Err(BackendError::NotFound(format!(
+                    "Unable to pin {}: {}",
+                    root.cid.cid, root.pin_error_msg
+                )));
+            }
+            roots.push(root.cid.cid);
+        }
+
+        Ok(IpfsDagImportResponse { roots })
+    }
+
+    async fn call_with_retry<'a, D>(
+        &self,
+        request: impl Fn() -> BoxFuture<'a, Result<reqwest::Response, reqwest::Error>>,
+    ) -> Result<Option<D>, BackendError>
+    where
+        D: DeserializeOwned,
+    {
+        <Self as Call>::call_with_retry::<D, BackendError>(
+            &self.retry_policy,
+            &self.state_client,
+            request,
+        )
+        .await
+    }
+}
+
+impl<C> Call for KuboBackend<C> {}
+
+/// Streams the file at `path` as a request body. It is only opened once the request is
+/// sent, so each retry reads it from the start.
+fn file_body(path: &Path) -> Body {
+    let file = stream::once(File::open(path.to_path_buf()))
+        .map_ok(ReaderStream::new)
+        .try_flatten();
+    Body::wrap_stream(file)
+}
+
+impl<C> IpfsBackend for KuboBackend<C>
+where
+    C: HttpClient + Clone + std::marker::Send + std::marker::Sync + 'static,
+{
+    async fn id(&self) -> Result<IpfsIdResponse, BackendError> {
+        self.post("id").await?.ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn add(
+        &self,
+        data: Vec<u8>,
+        cid_version: CidVersion,
+    ) -> Result<IpfsAddResponse, BackendError> {
+        let bytes = Bytes::from(data);
+        self.add_body(|| Body::from(bytes.clone()), cid_version)
+            .await
+    }
+
+    async fn add_file(
+        &self,
+        path: &Path,
+        cid_version: CidVersion,
+    ) -> Result<IpfsAddResponse, BackendError> {
+        self.add_body(|| file_body(path), cid_version).await
+    }
+
+    async fn cat(&self, hash: &str) -> Result<Bytes, BackendError> {
+        self.post_bytes(&format!("cat?arg={}", Cid::try_from(hash)?))
+            .await
+    }
+
+    async fn cat_range(&self, hash: &str, offset: u64, length: u64) -> Result<Bytes, BackendError> {
+        self.post_bytes(&format!(
+            "cat?arg={}&offset={}&length={}",
+            Cid::try_from(hash)?,
+            offset,
+            length
+        ))
+        .await
+    }
+
+    async fn pin_add(&self, hash: &str) -> Result<IpfsPinAddResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        // content kubo already holds, as the node it was added on does, is pinned without
+        // checking every block of it against peers
+        if let Some(response) = self.local_pin(&cid).await? {
+            return Ok(response);
+        }
+        self.import_from_peers(&cid).await;
+
+        self.post(&format!("pin/add?arg={}", cid))
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn pin_rm(&self, hash: &str) -> Result<IpfsPinRmResponse, BackendError> {
+        self.post(&format!("pin/rm?arg={}", Cid::try_from(hash)?))
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn pin_ls(&self) -> Result<IpfsPinLsResponse, BackendError> {
+        self.post("pin/ls")
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn repo_stat(&self) -> Result<IpfsRepoStatResponse, BackendError> {
+        self.post("repo/stat")
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn stat(&self, hash: &str) -> Result<u64, BackendError> {
+        // parsed so only a well formed cid is ever put in the query string
+        let hash = Cid::try_from(hash)?;
+        match self
+            .post::<IpfsObjectStatResponse>(&format!("object/stat?arg={}&offline=true", hash))
+            .await
+        {
+            Ok(Some(object_stat)) => Ok(object_stat.cumulative_size),
+            // object/stat only understands dag-pb, so fall back to walking the dag
+            _ => Ok(self
+                .post::<IpfsDagStatResponse>(&format!(
+                    "dag/stat?arg={}&progress=false&offline=true",
+                    hash
+                ))
+                .await?
+                .ok_or(BackendError::EmptyResponse)?
+                .total_size),
+        }
+    }
+
+    async fn dag_stat(&self, hash: &str) -> Result<IpfsStatResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let dag_stat = self
+            .post::<IpfsDagStatResponse>(&format!("dag/stat?arg={}&progress=false", cid))
+            .await?
+            .ok_or(BackendError::EmptyResponse)?;
+
+        Ok(IpfsStatResponse {
+            hash: cid.to_string(),
+            cumulative_size: dag_stat.total_size,
+            num_blocks: dag_stat.num_blocks,
+        })
+    }
+
+    async fn ls(&self, hash: &str) -> Result<IpfsLsResponse, BackendError> {
+        self.post::<IpfsLsObjectsResponse>(&format!("ls?arg={}", Cid::try_from(hash)?))
+            .await?
+            .ok_or(BackendError::EmptyResponse)?
+            .objects
+            .into_iter()
+            .next()
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn refs(&self, hash: &str) -> Result<IpfsRefsResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let body = self
+            .post_bytes(&format!("refs?arg={}&recursive=true&unique=true", cid))
+            .await?;
+        let body = String::from_utf8_lossy(&body);
+
+        // streamed as one json object per line
+        let mut refs = Vec::new();
+        for line in body.lines().filter(|l| !l.trim().is_empty()) {
+            let r = serde_json::from_str::<IpfsRefResponse>(line)?;
+            if !r.err.is_empty() {
+                return Err(BackendError::NotFound(r.err));
+            }
+            refs.push(r.r#ref);
+        }
+
+        Ok(IpfsRefsResponse {
+            hash: cid.to_string(),
+            refs,
+        })
+    }
+
+    async fn block_get(&self, hash: &str) -> Result<Bytes, BackendError> {
+        self.post_bytes(&format!("block/get?arg={}", Cid::try_from(hash)?))
+            .await
+    }
+
+    async fn dag_export(&self, hash: &str) -> Result<Bytes, BackendError> {
+        self.post_bytes(&format!("dag/export?arg={}", Cid::try_from(hash)?))
+            .await
+    }
+
+    async fn dag_import(&self, car: Bytes) -> Result<IpfsDagImportResponse, BackendError> {
+        self.dag_import_body(|| Body::from(car.clone())).await
+    }
+
+    async fn dag_export_file(&self, hash: &str, path: &Path) -> Result<u64, BackendError> {
+        let url = self.url(&format!("dag/export?arg={}", Cid::try_from(hash)?));
+        let request = || self.client.post(url.clone()).boxed();
+        let response =
+            send_with_retry::<BackendError>(&self.retry_policy, &self.state_client, request)
+                .await?;
+
+        let mut file = File::create(path).await?;
+        let mut body = response.bytes_stream();
+        let mut size = 0;
+        while let Some(chunk) = body.try_next().await? {
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+
+        Ok(size)
+    }
+
+    async fn dag_import_file(&self, path: &Path) -> Result<IpfsDagImportResponse, BackendError> {
+        self.dag_import_body(|| file_body(path)).await
+    }
+
+    async fn repo_gc(&self) -> Result<usize, BackendError> {
+        let body = self.post_bytes("repo/gc").await?;
+        let body = String::from_utf8_lossy(&body);
+
+        Ok(body.lines().filter(|l| !l.trim().is_empty()).count())
+    }
+
+    async fn key_gen(&self, name: &str) -> Result<IpfsKey, BackendError> {
+        self.post(&format!("key/gen?arg={}&type=ed25519", name))
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn key_list(&self) -> Result<IpfsKeyListResponse, BackendError> {
+        self.post("key/list")
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn name_publish(
+        &self,
+        hash: &str,
+        key: &str,
+    ) -> Result<IpfsNamePublishResponse, BackendError> {
+        // allow-offline lets air-gapped clusters publish without reaching the public dht
+        self.post(&format!(
+            "name/publish?arg=/ipfs/{}&key={}&allow-offline=true",
+            Cid::try_from(hash)?,
+            key
+        ))
+        .await?
+        .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn name_resolve(&self, name: &str) -> Result<IpfsNameResolveResponse, BackendError> {
+        self.post(&format!("name/resolve?arg=/ipns/{}", name))
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn files_ls(&self, path: &MfsPath) -> Result<MfsLsResponse, BackendError> {
+        self.post(&format!("files/ls?arg={}&long=true", path))
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn files_mkdir(&self, path: &MfsPath, parents: bool) -> Result<(), BackendError> {
+        self.post::<serde_json::Value>(&format!("files/mkdir?arg={}&parents={}", path, parents))
+            .await?;
+        Ok(())
+    }
+
+    async fn files_cp(&self, from: &MfsSource, to: &MfsPath) -> Result<(), BackendError> {
+        if let MfsSource::Ipfs(cid) = from {
+            self.import_from_peers(cid.cid()).await;
+        }
+
+        self.post::<serde_json::Value>(&format!("files/cp?arg={}&arg={}", from, to))
+            .await?;
+        Ok(())
+    }
+
+    async fn files_mv(&self, from: &MfsPath, to: &MfsPath) -> Result<(), BackendError> {
+        self.post::<serde_json::Value>(&format!("files/mv?arg={}&arg={}", from, to))
+            .await?;
+        Ok(())
+    }
+
+    async fn files_rm(&self, path: &MfsPath, recursive: bool) -> Result<(), BackendError> {
+        self.post::<serde_json::Value>(&format!("files/rm?arg={}&recursive={}", path, recursive))
+            .await?;
+        Ok(())
+    }
+
+    async fn files_stat(&self, path: &MfsPath) -> Result<MfsStatResponse, BackendError> {
+        self.post(&format!("files/stat?arg={}", path))
+            .await?
+            .ok_or(BackendError::EmptyResponse)
+    }
+
+    async fn files_write(
+        &self,
+        path: &MfsPath,
+        data: Vec<u8>,
+        create: bool,
+    ) -> Result<(), BackendError> {
+        let url = self.url(&format!(
+            "files/write?arg={}&create={}&truncate=true",
+            path, create
+        ));
+        let bytes = Bytes::from(data);
+
+        let request = || {
+            let part = Part::stream(Body::from(bytes.clone()));
+            let form = Form::new().part("file", part);
+            self.client.post_multipart(url.clone(), form).boxed()
+        };
+
+        self.call_with_retry::<serde_json::Value>(request).await?;
+        Ok(())
+    }
+
+    async fn files_read(&self, path: &MfsPath) -> Result<Bytes, BackendError> {
+        self.post_bytes(&format!("files/read?arg={}", path)).await
+    }
+}
+
+#[derive(Clone)]
+pub struct ReqwestClient {
+    client: Client,
+}
+
+impl ReqwestClient {
+    pub fn new() -> Self {
+        Self {
+            client: Client::new(),
+        }
+    }
+}
+
+impl Default for ReqwestClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait HttpClient {
+    fn post(
+        &self,
+        url: String,
+    ) -> impl std::future::Future<Output = Result<reqwest::Response, reqwest::Error>> + std::marker::Send;
+
+    fn post_multipart(
+        &self,
+        url: String,
+        form: Form,
+    ) -> impl std::future::Future<Output = Result<reqwest::Response, reqwest::Error>> + std::marker::Send;
+}
+
+impl HttpClient for ReqwestClient {
+    async fn post(&self, url: String) -> Result<reqwest::Response, reqwest::Error> {
+        self.client.post(url).send().await
+    }
+
+    async fn post_multipart(
+        &self,
+        url: String,
+        form: Form,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        self.client
+            .post(url)
+            .multipart(form)
+            .header("Content-Type", "application/octet-stream")
+            .send()
+            .await
+    }
+}
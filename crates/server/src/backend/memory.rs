@@ -0,0 +1,563 @@
+use bytes::Bytes;
+use cid::Cid;
+use futures::{future, FutureExt};
+use libp2p::PeerId;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{
+    dag,
+    mfs::{DagStore, MfsTree},
+    unixfs::{self, Block},
+    BackendError, IpfsBackend,
+};
+use crate::{
+    api::types::{
+        ipfs::{
+            CidVersion, IpfsAddResponse, IpfsDagImportResponse, IpfsIdResponse, IpfsKey,
+            IpfsKeyListResponse, IpfsLsResponse, IpfsNamePublishResponse, IpfsNameResolveResponse,
+            IpfsPinAddResponse, IpfsPinLsResponse, IpfsPinRmResponse, IpfsRefsResponse,
+            IpfsRepoStatResponse, IpfsStatResponse,
+        },
+        mfs::{MfsLsResponse, MfsPath, MfsSource, MfsStatResponse},
+    },
+    network::BlockProviderFn,
+};
+
+/// Keeps content in memory, for tests and running the server without an ipfs node.
+/// Backends joined to the same `MemoryNetwork` fetch blocks they are missing from each
+/// other, the way ipfs nodes would.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    id: String,
+    store: Arc<Mutex<MemoryStore>>,
+    network: MemoryNetwork,
+    /// Held for the whole of an mfs change so concurrent changes do not lose each other.
+    mfs_root: Arc<tokio::sync::Mutex<Option<Cid>>>,
+}
+
+#[derive(Default)]
+struct MemoryStore {
+    blocks: HashMap<Cid, Bytes>,
+    pins: HashSet<Cid>,
+    /// Ipns key names mapped to their ids.
+    keys: HashMap<String, String>,
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    stores: Arc<Mutex<Vec<Arc<Mutex<MemoryStore>>>>>,
+    /// Ipns records published by any backend, by key id.
+    names: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MemoryNetwork {
+    /// Creates a backend whose blocks can be fetched by every other backend of this network.
+    pub fn backend(&self) -> MemoryBackend {
+        let backend = MemoryBackend {
+            id: PeerId::random().to_string(),
+            store: Arc::new(Mutex::new(MemoryStore::default())),
+            network: self.clone(),
+            mfs_root: Arc::new(tokio::sync::Mutex::new(None)),
+        };
+        lock(&self.stores).push(backend.store.clone());
+        backend
+    }
+
+    fn find_block(&self, cid: &Cid) -> Option<Bytes> {
+        let stores = lock(&self.stores).clone();
+        stores
+            .iter()
+            .find_map(|store| lock(store).blocks.get(cid).cloned())
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryNetwork::default().backend()
+    }
+
+    /// Serves blocks held in this backend to peers.
+    pub fn block_provider(&self) -> BlockProviderFn {
+        let store = self.store.clone();
+        Arc::new(move |cid| future::ready(lock(&store).blocks.get(&cid).cloned()).boxed())
+    }
+
+    fn store(&self) -> MutexGuard<'_, MemoryStore> {
+        lock(&self.store)
+    }
+
+    /// Returns a block from the local store, fetching it from the network if needed.
+    fn get_block(&self, cid: &Cid) -> Result<Bytes, BackendError> {
+        if let Some(block) = self.store().blocks.get(cid) {
+            return Ok(block.clone());
+        }
+
+        let block = self
+            .network
+            .find_block(cid)
+            .ok_or_else(|| BackendError::NotFound(format!("{} not found", cid)))?;
+        self.store().blocks.insert(*cid, block.clone());
+        Ok(block)
+    }
+
+    fn key_id(&self, key: &str) -> Option<String> {
+        match key {
+            "self" => Some(self.id.clone()),
+            _ => self.store().keys.get(key).cloned(),
+        }
+    }
+
+    /// Walks the dag rooted at `cid` depth first, calling `visit` with each block.
+    fn walk(
+        &self,
+        cid: &Cid,
+        visit: &mut impl FnMut(&Cid, &Bytes) -> Result<(), BackendError>,
+    ) -> Result<(), BackendError> {
+        let block = self.get_block(cid)?;
+        visit(cid, &block)?;
+        for link in unixfs::links(cid, &block)? {
+            self.walk(&link, visit)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IpfsBackend for MemoryBackend {
+    async fn id(&self) -> Result<IpfsIdResponse, BackendError> {
+        Ok(IpfsIdResponse {
+            id: self.id.clone(),
+        })
+    }
+
+    async fn add(
+        &self,
+        data: Vec<u8>,
+        cid_version: CidVersion,
+    ) -> Result<IpfsAddResponse, BackendError> {
+        let (root, blocks) = unixfs::build_file(&data, cid_version)?;
+
+        let mut store = self.store();
+        blocks.into_iter().for_each(|block| {
+            store.blocks.insert(block.cid, block.data);
+        });
+        store.pins.insert(root);
+
+        Ok(IpfsAddResponse {
+            hash: root.to_string(),
+            name: root.to_string(),
+        })
+    }
+
+    async fn cat(&self, hash: &str) -> Result<Bytes, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let mut contents = Vec::new();
+        self.walk(&cid, &mut |cid, block| {
+            contents.extend_from_slice(&unixfs::file_data(cid, block)?);
+            Ok(())
+        })?;
+
+        Ok(Bytes::from(contents))
+    }
+
+    async fn pin_add(&self, hash: &str) -> Result<IpfsPinAddResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        self.walk(&cid, &mut |_, _| Ok(()))?;
+        self.store().pins.insert(cid);
+
+        Ok(IpfsPinAddResponse {
+            pins: vec![cid.to_string()],
+        })
+    }
+
+    async fn pin_rm(&self, hash: &str) -> Result<IpfsPinRmResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        if !self.store().pins.remove(&cid) {
+            return Err(BackendError::NotFound(format!("{} is not pinned", hash)));
+        }
+
+        Ok(IpfsPinRmResponse {
+            pins: vec![cid.to_string()],
+        })
+    }
+
+    async fn pin_ls(&self) -> Result<IpfsPinLsResponse, BackendError> {
+        let keys = self
+            .store()
+            .pins
+            .iter()
+            .map(|cid| (cid.to_string(), json!({ "Type": "recursive", "Name": "" })))
+            .collect::<Map<String, Value>>();
+
+        Ok(IpfsPinLsResponse {
+            keys: Value::Object(keys),
+        })
+    }
+
+    async fn repo_stat(&self) -> Result<IpfsRepoStatResponse, BackendError> {
+        let store = self.store();
+
+        Ok(IpfsRepoStatResponse {
+            repo_size: store.blocks.values().map(|b| b.len() as u64).sum(),
+            storage_max: u64::MAX,
+            num_objects: store.blocks.len() as u64,
+        })
+    }
+
+    async fn stat(&self, hash: &str) -> Result<u64, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let mut size = 0;
+        self.walk(&cid, &mut |_, block| {
+            size += block.len() as u64;
+            Ok(())
+        })?;
+
+        Ok(size)
+    }
+
+    async fn dag_stat(&self, hash: &str) -> Result<IpfsStatResponse, BackendError> {
+        dag::stat(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn ls(&self, hash: &str) -> Result<IpfsLsResponse, BackendError> {
+        dag::ls(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn refs(&self, hash: &str) -> Result<IpfsRefsResponse, BackendError> {
+        dag::refs(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn block_get(&self, hash: &str) -> Result<Bytes, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        self.get_block(&cid)
+    }
+
+    async fn dag_export(&self, hash: &str) -> Result<Bytes, BackendError> {
+        dag::export(self, &Cid::try_from(hash)?).await
+    }
+
+    async fn dag_import(&self, car: Bytes) -> Result<IpfsDagImportResponse, BackendError> {
+        let mut roots = Vec::new();
+        for root in dag::import(self, &car).await? {
+            roots.extend(self.pin_add(&root.to_string()).await?.pins);
+        }
+
+        Ok(IpfsDagImportResponse { roots })
+    }
+
+    async fn repo_gc(&self) -> Result<usize, BackendError> {
+        let mut roots = self.store().pins.clone();
+        // like kubo, the mfs tree is kept without being pinned
+        roots.extend(*self.mfs_root.lock().await);
+        let mut reachable = HashSet::new();
+        for pin in roots {
+            self.walk(&pin, &mut |cid, _| {
+                reachable.insert(*cid);
+                Ok(())
+            })?;
+        }
+
+        let mut store = self.store();
+        let before = store.blocks.len();
+        store.blocks.retain(|cid, _| reachable.contains(cid));
+
+        Ok(before - store.blocks.len())
+    }
+
+    async fn key_gen(&self, name: &str) -> Result<IpfsKey, BackendError> {
+        let id = PeerId::random().to_string();
+        self.store().keys.insert(name.to_string(), id.clone());
+
+        Ok(IpfsKey {
+            name: name.to_string(),
+            id,
+        })
+    }
+
+    async fn key_list(&self) -> Result<IpfsKeyListResponse, BackendError> {
+        let keys = std::iter::once(("self".to_string(), self.id.clone()))
+            .chain(self.store().keys.clone())
+            .map(|(name, id)| IpfsKey { name, id })
+            .collect::<Vec<IpfsKey>>();
+
+        Ok(IpfsKeyListResponse { keys })
+    }
+
+    async fn name_publish(
+        &self,
+        hash: &str,
+        key: &str,
+    ) -> Result<IpfsNamePublishResponse, BackendError> {
+        let cid = Cid::try_from(hash)?;
+        let id = self
+            .key_id(key)
+            .ok_or_else(|| BackendError::NotFound(format!("no key named {}", key)))?;
+        let value = format!("/ipfs/{}", cid);
+        lock(&self.network.names).insert(id.clone(), value.clone());
+
+        Ok(IpfsNamePublishResponse { name: id, value })
+    }
+
+    async fn name_resolve(&self, name: &str) -> Result<IpfsNameResolveResponse, BackendError> {
+        let path = lock(&self.network.names)
+            .get(name)
+            .cloned()
+            .ok_or_else(|| BackendError::NotFound(format!("could not resolve name {}", name)))?;
+
+        Ok(IpfsNameResolveResponse { path })
+    }
+
+    async fn files_ls(&self, path: &MfsPath) -> Result<MfsLsResponse, BackendError> {
+        let root = *self.mfs_root.lock().await;
+        MfsTree::open(self, root).await?.ls(path).await
+    }
+
+    async fn files_mkdir(&self, path: &MfsPath, parents: bool) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.mkdir(path, parents).await?;
+        *root = Some(tree.root());
+        Ok(())
+    }
+
+    async fn files_cp(&self, from: &MfsSource, to: &MfsPath) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        let cid = match from {
+            MfsSource::Ipfs(cid) => *cid.cid(),
+            MfsSource::Mfs(path) => tree.resolve(path).await?,
+        };
+        tree.cp(cid, to).await?;
+        *root = Some(tree.root());
+        Ok(())
+    }
+
+    async fn files_mv(&self, from: &MfsPath, to: &MfsPath) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.mv(from, to).await?;
+        *root = Some(tree.root());
+        Ok(())
+    }
+
+    async fn files_rm(&self, path: &MfsPath, recursive: bool) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.rm(path, recursive).await?;
+        *root = Some(tree.root());
+        Ok(())
+    }
+
+    async fn files_stat(&self, path: &MfsPath) -> Result<MfsStatResponse, BackendError> {
+        let root = *self.mfs_root.lock().await;
+        MfsTree::open(self, root).await?.stat(path).await
+    }
+
+    async fn files_write(
+        &self,
+        path: &MfsPath,
+        data: Vec<u8>,
+        create: bool,
+    ) -> Result<(), BackendError> {
+        let mut root = self.mfs_root.lock().await;
+        let mut tree = MfsTree::open(self, *root).await?;
+        tree.write(path, &data, create).await?;
+        *root = Some(tree.root());
+        Ok(())
+    }
+
+    async fn files_read(&self, path: &MfsPath) -> Result<Bytes, BackendError> {
+        let root = *self.mfs_root.lock().await;
+        let cid = MfsTree::open(self, root).await?.file(path).await?;
+        self.cat(&cid.to_string()).await
+    }
+}
+
+impl DagStore for MemoryBackend {
+    async fn get_node(&self, cid: &Cid) -> Result<Bytes, BackendError> {
+        self.get_block(cid)
+    }
+
+    async fn put_node(&self, block: Block) -> Result<(), BackendError> {
+        self.store().blocks.insert(block.cid, block.data);
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::types::ipfs::IpfsLinkType;
+    use futures::executor::block_on;
+
+    #[test]
+    fn unpinned_content_is_garbage_collected() {
+        let backend = MemoryBackend::new();
+
+        block_on(async {
+            let hash = backend
+                .add(b"hello".to_vec(), CidVersion::V0)
+                .await
+                .unwrap()
+                .hash;
+            assert_eq!(backend.cat(&hash).await.unwrap(), Bytes::from("hello"));
+            assert_eq!(backend.repo_gc().await.unwrap(), 0);
+
+            backend.pin_rm(&hash).await.unwrap();
+            assert_eq!(backend.repo_gc().await.unwrap(), 1);
+            assert!(backend.cat(&hash).await.is_err());
+        });
+    }
+
+    #[test]
+    fn dags_can_be_inspected() {
+        let backend = MemoryBackend::new();
+
+        block_on(async {
+            let hash = backend
+                .add(vec![7u8; 300 * 1024], CidVersion::V0)
+                .await
+                .unwrap()
+                .hash;
+            let stat = backend.dag_stat(&hash).await.unwrap();
+            let refs = backend.refs(&hash).await.unwrap().refs;
+            let chunks = backend.ls(&hash).await.unwrap().links;
+            assert_eq!(stat.num_blocks, 3);
+            assert_eq!(refs.len(), 2);
+            assert_eq!(
+                chunks.iter().map(|l| l.hash.clone()).collect::<Vec<_>>(),
+                refs
+            );
+            assert!(chunks.iter().all(|l| l.link_type == IpfsLinkType::File));
+
+            let mut size = 0;
+            for cid in refs.iter().chain([&hash]) {
+                let block = backend.block_get(cid).await.unwrap();
+                unixfs::verify_block(&Cid::try_from(cid.as_str()).unwrap(), &block).unwrap();
+                size += block.len() as u64;
+            }
+            assert_eq!(stat.cumulative_size, size);
+
+            backend
+                .files_mkdir(&"/docs".parse().unwrap(), false)
+                .await
+                .unwrap();
+            let root = backend.files_stat(&MfsPath::root()).await.unwrap().hash;
+            let links = backend.ls(&root).await.unwrap().links;
+            assert_eq!(links[0].name, "docs");
+            assert_eq!(links[0].link_type, IpfsLinkType::Directory);
+        });
+    }
+
+    #[test]
+    fn cars_move_dags_between_separate_backends() {
+        let source = MemoryBackend::new();
+        let destination = MemoryBackend::new();
+
+        block_on(async {
+            let hash = source
+                .add(vec![5u8; 300 * 1024], CidVersion::V1)
+                .await
+                .unwrap()
+                .hash;
+            assert!(destination.cat(&hash).await.is_err());
+
+            let car = source.dag_export(&hash).await.unwrap();
+            let response = destination.dag_import(car.clone()).await.unwrap();
+            assert_eq!(response.roots, vec![hash.clone()]);
+            assert_eq!(destination.cat(&hash).await.unwrap().len(), 300 * 1024);
+            assert_eq!(destination.repo_gc().await.unwrap(), 0);
+
+            // a block that does not match its cid is refused
+            let mut corrupt = car.to_vec();
+            *corrupt.last_mut().unwrap() ^= 1;
+            assert!(MemoryBackend::new()
+                .dag_import(Bytes::from(corrupt))
+                .await
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn content_is_fetched_from_other_backends_on_the_network() {
+        let network = MemoryNetwork::default();
+        let node_1 = network.backend();
+        let node_2 = network.backend();
+
+        block_on(async {
+            let hash = node_1
+                .add(b"hello".to_vec(), CidVersion::V1)
+                .await
+                .unwrap()
+                .hash;
+            node_2.pin_add(&hash).await.unwrap();
+            node_1.pin_rm(&hash).await.unwrap();
+            node_1.repo_gc().await.unwrap();
+
+            assert_eq!(node_2.cat(&hash).await.unwrap(), Bytes::from("hello"));
+            assert!(node_2.pin_ls().await.unwrap().keys.get(&hash).is_some());
+            assert!(node_1.pin_ls().await.unwrap().keys.get(&hash).is_none());
+        });
+    }
+
+    #[test]
+    fn mfs_changes_are_kept_in_the_root() {
+        let backend = MemoryBackend::new();
+        let path = |path: &str| path.parse::<MfsPath>().unwrap();
+
+        block_on(async {
+            let empty = backend.files_stat(&MfsPath::root()).await.unwrap().hash;
+            backend
+                .files_mkdir(&path("/docs/notes"), true)
+                .await
+                .unwrap();
+            backend
+                .files_write(&path("/docs/notes/a.txt"), b"hello".to_vec(), true)
+                .await
+                .unwrap();
+            backend
+                .files_cp(&"/docs/notes/a.txt".parse().unwrap(), &path("/b.txt"))
+                .await
+                .unwrap();
+            backend
+                .files_mv(&path("/b.txt"), &path("/docs/b.txt"))
+                .await
+                .unwrap();
+            // only the directories the changes replaced are collected
+            backend.repo_gc().await.unwrap();
+
+            let entries = backend
+                .files_ls(&path("/docs"))
+                .await
+                .unwrap()
+                .entries
+                .unwrap();
+            let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+            assert_eq!(names, ["b.txt", "notes"]);
+            assert_eq!(
+                backend.files_read(&path("/docs/b.txt")).await.unwrap(),
+                Bytes::from("hello")
+            );
+            assert!(backend.files_rm(&path("/docs"), false).await.is_err());
+
+            backend.files_rm(&path("/docs"), true).await.unwrap();
+            assert_eq!(
+                backend.files_stat(&MfsPath::root()).await.unwrap().hash,
+                empty
+            );
+        });
+    }
+}
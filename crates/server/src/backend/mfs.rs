@@ -0,0 +1,288 @@
+//! A mutable file system built from unixfs directories, for backends without an ipfs node
+//! to keep one for them. Every change rewrites the directories between the changed entry
+//! and the root, so the root cid identifies the whole tree, as it does in kubo.
+
+use bytes::Bytes;
+use cid::Cid;
+use std::future::Future;
+
+use super::{
+    unixfs::{self, Block, PbLink, PbNode},
+    BackendError,
+};
+use crate::api::types::{
+    ipfs::CidVersion,
+    mfs::{MfsEntry, MfsEntryType, MfsLsResponse, MfsPath, MfsStatResponse},
+};
+
+/// Where the blocks of the tree are kept.
+pub trait DagStore: Sync {
+    fn get_node(&self, cid: &Cid) -> impl Future<Output = Result<Bytes, BackendError>> + Send;
+
+    fn put_node(&self, block: Block) -> impl Future<Output = Result<(), BackendError>> + Send;
+}
+
+pub struct MfsTree<'a, S> {
+    store: &'a S,
+    root: Cid,
+}
+
+impl<'a, S> MfsTree<'a, S>
+where
+    S: DagStore,
+{
+    /// Opens the tree at `root`, or a new empty tree when there is none yet.
+    pub async fn open(store: &'a S, root: Option<Cid>) -> Result<Self, BackendError> {
+        let root = match root {
+            Some(root) => root,
+            None => {
+                let block = unixfs::build_directory(&[])?;
+                let cid = block.cid;
+                store.put_node(block).await?;
+                cid
+            }
+        };
+
+        Ok(Self { store, root })
+    }
+
+    pub fn root(&self) -> Cid {
+        self.root
+    }
+
+    pub async fn ls(&self, path: &MfsPath) -> Result<MfsLsResponse, BackendError> {
+        let cid = self.resolve(path).await?;
+        let block = self.store.get_node(&cid).await?;
+        if !unixfs::node_info(&cid, &block)?.is_directory {
+            let name = path.split_last().map(|(_, name)| name).unwrap_or_default();
+            let entry = self.entry(name.to_string(), cid).await?;
+            return Ok(MfsLsResponse {
+                entries: Some(vec![entry]),
+            });
+        }
+
+        let mut entries = Vec::new();
+        for link in unixfs::decode_node(&block)?.links {
+            entries.push(self.entry(link.name, link.cid).await?);
+        }
+
+        Ok(MfsLsResponse {
+            entries: Some(entries),
+        })
+    }
+
+    pub async fn stat(&self, path: &MfsPath) -> Result<MfsStatResponse, BackendError> {
+        let cid = self.resolve(path).await?;
+        let info = unixfs::node_info(&cid, &self.store.get_node(&cid).await?)?;
+
+        Ok(MfsStatResponse {
+            hash: cid.to_string(),
+            size: info.filesize,
+            cumulative_size: info.cumulative_size,
+            blocks: info.links as u64,
+            entry_type: if info.is_directory {
+                "directory"
+            } else {
+                "file"
+            }
+            .to_string(),
+        })
+    }
+
+    /// Returns the cid of the file at `path`.
+    pub async fn file(&self, path: &MfsPath) -> Result<Cid, BackendError> {
+        let cid = self.resolve(path).await?;
+        if unixfs::node_info(&cid, &self.store.get_node(&cid).await?)?.is_directory {
+            return Err(BackendError::Mfs(format!("{} is a directory", path)));
+        }
+        Ok(cid)
+    }
+
+    pub async fn mkdir(&mut self, path: &MfsPath, parents: bool) -> Result<(), BackendError> {
+        if self.lookup(path).await?.is_some() {
+            return match parents {
+                true => Ok(()),
+                false => Err(BackendError::Mfs(format!("{} already exists", path))),
+            };
+        }
+
+        let block = unixfs::build_directory(&[])?;
+        let cid = block.cid;
+        self.store.put_node(block).await?;
+        self.link(path, Some(cid), parents).await
+    }
+
+    /// Adds the dag rooted at `cid` to the tree at `to`.
+    pub async fn cp(&mut self, cid: Cid, to: &MfsPath) -> Result<(), BackendError> {
+        if self.lookup(to).await?.is_some() {
+            return Err(BackendError::Mfs(format!("{} already exists", to)));
+        }
+        self.link(to, Some(cid), false).await
+    }
+
+    pub async fn mv(&mut self, from: &MfsPath, to: &MfsPath) -> Result<(), BackendError> {
+        if to.starts_with(from) {
+            return Err(BackendError::Mfs(format!(
+                "Cannot move {} into itself",
+                from
+            )));
+        }
+
+        let cid = self.resolve(from).await?;
+        self.cp(cid, to).await?;
+        self.link(from, None, false).await
+    }
+
+    pub async fn rm(&mut self, path: &MfsPath, recursive: bool) -> Result<(), BackendError> {
+        let cid = self.resolve(path).await?;
+        if !recursive && unixfs::node_info(&cid, &self.store.get_node(&cid).await?)?.is_directory {
+            return Err(BackendError::Mfs(format!(
+                "{} is a directory, remove it recursively",
+                path
+            )));
+        }
+        self.link(path, None, false).await
+    }
+
+    /// Replaces the contents of the file at `path`, creating it if `create` is set.
+    pub async fn write(
+        &mut self,
+        path: &MfsPath,
+        data: &[u8],
+        create: bool,
+    ) -> Result<(), BackendError> {
+        let existing = self.lookup(path).await?;
+        if existing.is_none() && !create {
+            return Err(not_found(path));
+        }
+        if let Some(cid) = existing {
+            if unixfs::node_info(&cid, &self.store.get_node(&cid).await?)?.is_directory {
+                return Err(BackendError::Mfs(format!("{} is a directory", path)));
+            }
+        }
+
+        // kubo's mfs defaults to cidv0 files
+        let (root, blocks) = unixfs::build_file(data, CidVersion::V0)?;
+        for block in blocks {
+            self.store.put_node(block).await?;
+        }
+        self.link(path, Some(root), false).await
+    }
+
+    pub async fn resolve(&self, path: &MfsPath) -> Result<Cid, BackendError> {
+        self.lookup(path).await?.ok_or_else(|| not_found(path))
+    }
+
+    async fn lookup(&self, path: &MfsPath) -> Result<Option<Cid>, BackendError> {
+        let mut cid = self.root;
+        for name in path.components() {
+            let directory = self.directory(&cid, path).await?;
+            match directory.links.into_iter().find(|link| link.name == name) {
+                Some(link) => cid = link.cid,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(cid))
+    }
+
+    async fn directory(&self, cid: &Cid, path: &MfsPath) -> Result<PbNode, BackendError> {
+        let block = self.store.get_node(cid).await?;
+        if !unixfs::node_info(cid, &block)?.is_directory {
+            return Err(BackendError::Mfs(format!(
+                "A parent of {} is not a directory",
+                path
+            )));
+        }
+        unixfs::decode_node(&block)
+    }
+
+    async fn entry(&self, name: String, cid: Cid) -> Result<MfsEntry, BackendError> {
+        let info = unixfs::node_info(&cid, &self.store.get_node(&cid).await?)?;
+
+        Ok(MfsEntry {
+            name,
+            entry_type: match info.is_directory {
+                true => MfsEntryType::Directory,
+                false => MfsEntryType::File,
+            },
+            size: info.filesize,
+            hash: cid.to_string(),
+        })
+    }
+
+    /// Points `path` at `cid`, or removes it when `cid` is `None`, then rewrites every
+    /// directory up to the root.
+    async fn link(
+        &mut self,
+        path: &MfsPath,
+        cid: Option<Cid>,
+        parents: bool,
+    ) -> Result<(), BackendError> {
+        let (parent, name) = path
+            .split_last()
+            .ok_or_else(|| BackendError::Mfs("Cannot replace the root directory".into()))?;
+        let link = match cid {
+            Some(cid) => {
+                let block = self.store.get_node(&cid).await?;
+                Some(PbLink {
+                    cid,
+                    name: name.to_string(),
+                    tsize: unixfs::node_info(&cid, &block)?.cumulative_size,
+                })
+            }
+            None => None,
+        };
+
+        let mut ancestors = Vec::new();
+        let mut directory = self.directory(&self.root, path).await?;
+        for component in parent.components() {
+            let child = match directory.links.iter().find(|link| link.name == component) {
+                Some(link) => self.directory(&link.cid, path).await?,
+                None if parents => PbNode::default(),
+                None => return Err(not_found(&parent)),
+            };
+            ancestors.push((component, directory));
+            directory = child;
+        }
+
+        set_link(&mut directory.links, name, link);
+        let mut links = directory.links;
+        while let Some((name, mut parent)) = ancestors.pop() {
+            let child = self.put_directory(links, name).await?;
+            set_link(&mut parent.links, name, Some(child));
+            links = parent.links;
+        }
+        self.root = self.put_directory(links, "").await?.cid;
+
+        Ok(())
+    }
+
+    /// Stores a directory and returns the link its parent should hold to it.
+    async fn put_directory(&self, links: Vec<PbLink>, name: &str) -> Result<PbLink, BackendError> {
+        let block = unixfs::build_directory(&links)?;
+        let link = PbLink {
+            cid: block.cid,
+            name: name.to_string(),
+            tsize: block.data.len() as u64 + links.iter().map(|l| l.tsize).sum::<u64>(),
+        };
+        self.store.put_node(block).await?;
+        Ok(link)
+    }
+}
+
+/// Replaces or removes the link called `name`, keeping links sorted by name as unixfs
+/// directories require.
+fn set_link(links: &mut Vec<PbLink>, name: &str, link: Option<PbLink>) {
+    match (links.binary_search_by(|l| l.name.as_str().cmp(name)), link) {
+        (Ok(i), Some(link)) => links[i] = link,
+        (Ok(i), None) => {
+            links.remove(i);
+        }
+        (Err(i), Some(link)) => links.insert(i, link),
+        (Err(_), None) => {}
+    }
+}
+
+fn not_found(path: &MfsPath) -> BackendError {
+    BackendError::NotFound(format!("{}: file does not exist", path))
+}
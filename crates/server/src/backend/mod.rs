@@ -0,0 +1,450 @@
+pub mod car;
+pub mod dag;
+#[cfg(feature = "embedded-ipfs")]
+pub mod embedded;
+pub mod kubo;
+pub mod memory;
+pub mod mfs;
+pub mod unixfs;
+
+use bytes::Bytes;
+use cid::Cid;
+use std::{future::Future, path::Path};
+use tracing::warn;
+
+use crate::{
+    api::types::{
+        ipfs::{
+            CidVersion, IpfsAddResponse, IpfsDagImportResponse, IpfsIdResponse, IpfsKey,
+            IpfsKeyListResponse, IpfsLsResponse, IpfsNamePublishResponse, IpfsNameResolveResponse,
+            IpfsPinAddResponse, IpfsPinLsResponse, IpfsPinRmResponse, IpfsRefsResponse,
+            IpfsRepoStatResponse, IpfsStatResponse,
+        },
+        mfs::{MfsLsResponse, MfsPath, MfsSource, MfsStatResponse},
+    },
+    network::{BlockProviderFn, NetworkClient},
+};
+#[cfg(feature = "embedded-ipfs")]
+use embedded::EmbeddedBackend;
+use kubo::{KuboBackend, ReqwestClient};
+use memory::MemoryBackend;
+
+/// Typed operations against an ipfs node, so callers do not depend on how a particular
+/// node is reached.
+pub trait IpfsBackend: Clone + Send + Sync + 'static {
+    fn id(&self) -> impl Future<Output = Result<IpfsIdResponse, BackendError>> + Send;
+
+    fn add(
+        &self,
+        data: Vec<u8>,
+        cid_version: CidVersion,
+    ) -> impl Future<Output = Result<IpfsAddResponse, BackendError>> + Send;
+
+    fn cat(&self, hash: &str) -> impl Future<Output = Result<Bytes, BackendError>> + Send;
+
+    /// Adds the file at `path` like `add`. Backends that can stream it override reading it
+    /// into memory first.
+    fn add_file(
+        &self,
+        path: &Path,
+        cid_version: CidVersion,
+    ) -> impl Future<Output = Result<IpfsAddResponse, BackendError>> + Send {
+        async move {
+            let data = tokio::fs::read(path).await?;
+            self.add(data, cid_version).await
+        }
+    }
+
+    /// Returns up to `length` bytes of `hash` from `offset`. Backends that can read part of
+    /// a file override reading all of it.
+    fn cat_range(
+        &self,
+        hash: &str,
+        offset: u64,
+        length: u64,
+    ) -> impl Future<Output = Result<Bytes, BackendError>> + Send {
+        async move {
+            let data = self.cat(hash).await?;
+            let start = data.len().min(offset.try_into().unwrap_or(usize::MAX));
+            let end = data
+                .len()
+                .min(start.saturating_add(length.try_into().unwrap_or(usize::MAX)));
+            Ok(data.slice(start..end))
+        }
+    }
+
+    fn pin_add(
+        &self,
+        hash: &str,
+    ) -> impl Future<Output = Result<IpfsPinAddResponse, BackendError>> + Send;
+
+    fn pin_rm(
+        &self,
+        hash: &str,
+    ) -> impl Future<Output = Result<IpfsPinRmResponse, BackendError>> + Send;
+
+    fn pin_ls(&self) -> impl Future<Output = Result<IpfsPinLsResponse, BackendError>> + Send;
+
+    fn repo_stat(&self) -> impl Future<Output = Result<IpfsRepoStatResponse, BackendError>> + Send;
+
+    /// Returns the cumulative size in bytes of the dag rooted at `hash` from the blocks held
+    /// locally, failing rather than fetching any that are missing.
+    fn stat(&self, hash: &str) -> impl Future<Output = Result<u64, BackendError>> + Send;
+
+    /// Walks the dag rooted at `hash`, counting its unique blocks and their size.
+    fn dag_stat(
+        &self,
+        hash: &str,
+    ) -> impl Future<Output = Result<IpfsStatResponse, BackendError>> + Send;
+
+    /// Lists the links of the node `hash`.
+    fn ls(&self, hash: &str) -> impl Future<Output = Result<IpfsLsResponse, BackendError>> + Send;
+
+    /// Lists every block below `hash`.
+    fn refs(
+        &self,
+        hash: &str,
+    ) -> impl Future<Output = Result<IpfsRefsResponse, BackendError>> + Send;
+
+    /// Returns the raw block `hash`.
+    fn block_get(&self, hash: &str) -> impl Future<Output = Result<Bytes, BackendError>> + Send;
+
+    /// Writes the dag rooted at `hash` to a car.
+    fn dag_export(&self, hash: &str) -> impl Future<Output = Result<Bytes, BackendError>> + Send;
+
+    /// Stores the blocks of a car and pins its roots.
+    fn dag_import(
+        &self,
+        car: Bytes,
+    ) -> impl Future<Output = Result<IpfsDagImportResponse, BackendError>> + Send;
+
+    /// Writes the dag rooted at `hash` to a car at `path` and returns its size. Backends
+    /// that can stream it override reading it into memory first.
+    fn dag_export_file(
+        &self,
+        hash: &str,
+        path: &Path,
+    ) -> impl Future<Output = Result<u64, BackendError>> + Send {
+        async move {
+            let car = self.dag_export(hash).await?;
+            tokio::fs::write(path, &car).await?;
+            Ok(car.len() as u64)
+        }
+    }
+
+    /// Imports the car at `path` like `dag_import`. Backends that can stream it override
+    /// reading it into memory first.
+    fn dag_import_file(
+        &self,
+        path: &Path,
+    ) -> impl Future<Output = Result<IpfsDagImportResponse, BackendError>> + Send {
+        async move {
+            let car = tokio::fs::read(path).await?;
+            self.dag_import(car.into()).await
+        }
+    }
+
+    /// Removes unpinned blocks and returns how many were removed.
+    fn repo_gc(&self) -> impl Future<Output = Result<usize, BackendError>> + Send;
+
+    /// Creates the ipns key `name`.
+    fn key_gen(&self, name: &str) -> impl Future<Output = Result<IpfsKey, BackendError>> + Send;
+
+    fn key_list(&self) -> impl Future<Output = Result<IpfsKeyListResponse, BackendError>> + Send;
+
+    /// Points the ipns name of `key` at `hash`.
+    fn name_publish(
+        &self,
+        hash: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<IpfsNamePublishResponse, BackendError>> + Send;
+
+    fn name_resolve(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<IpfsNameResolveResponse, BackendError>> + Send;
+
+    fn files_ls(
+        &self,
+        path: &MfsPath,
+    ) -> impl Future<Output = Result<MfsLsResponse, BackendError>> + Send;
+
+    /// Creates the directory `path`, and any missing parents if `parents` is set.
+    fn files_mkdir(
+        &self,
+        path: &MfsPath,
+        parents: bool,
+    ) -> impl Future<Output = Result<(), BackendError>> + Send;
+
+    fn files_cp(
+        &self,
+        from: &MfsSource,
+        to: &MfsPath,
+    ) -> impl Future<Output = Result<(), BackendError>> + Send;
+
+    fn files_mv(
+        &self,
+        from: &MfsPath,
+        to: &MfsPath,
+    ) -> impl Future<Output = Result<(), BackendError>> + Send;
+
+    fn files_rm(
+        &self,
+        path: &MfsPath,
+        recursive: bool,
+    ) -> impl Future<Output = Result<(), BackendError>> + Send;
+
+    fn files_stat(
+        &self,
+        path: &MfsPath,
+    ) -> impl Future<Output = Result<MfsStatResponse, BackendError>> + Send;
+
+    /// Replaces the contents of the file at `path`, creating it if `create` is set.
+    fn files_write(
+        &self,
+        path: &MfsPath,
+        data: Vec<u8>,
+        create: bool,
+    ) -> impl Future<Output = Result<(), BackendError>> + Send;
+
+    fn files_read(
+        &self,
+        path: &MfsPath,
+    ) -> impl Future<Output = Result<Bytes, BackendError>> + Send;
+}
+
+/// Runs `$call` with `$backend` bound to whichever backend `$self` holds.
+macro_rules! dispatch {
+    ($self:ident, $backend:ident => $call:expr) => {
+        match $self {
+            Backend::Kubo($backend) => $call,
+            Backend::Memory($backend) => $call,
+            #[cfg(feature = "embedded-ipfs")]
+            Backend::Embedded($backend) => $call,
+        }
+    };
+}
+
+/// Backend chosen at runtime.
+#[derive(Clone)]
+pub enum Backend {
+    Kubo(KuboBackend<ReqwestClient>),
+    Memory(MemoryBackend),
+    #[cfg(feature = "embedded-ipfs")]
+    Embedded(EmbeddedBackend),
+}
+
+impl Backend {
+    /// Serves this backend's blocks to peers over the swarm, if it can.
+    pub fn block_provider(&self) -> Option<BlockProviderFn> {
+        dispatch!(self, backend => Some(backend.block_provider()))
+    }
+
+    /// Lets the backend fetch blocks from cluster peers once the network has started.
+    pub fn set_network_client(&self, network_client: NetworkClient) {
+        match self {
+            Backend::Kubo(backend) => backend.set_network_client(network_client),
+            Backend::Memory(_) => {}
+            #[cfg(feature = "embedded-ipfs")]
+            Backend::Embedded(backend) => backend.set_network_client(network_client),
+        }
+    }
+}
+
+impl IpfsBackend for Backend {
+    async fn id(&self) -> Result<IpfsIdResponse, BackendError> {
+        dispatch!(self, backend => backend.id().await)
+    }
+
+    async fn add(
+        &self,
+        data: Vec<u8>,
+        cid_version: CidVersion,
+    ) -> Result<IpfsAddResponse, BackendError> {
+        dispatch!(self, backend => backend.add(data, cid_version).await)
+    }
+
+    async fn cat(&self, hash: &str) -> Result<Bytes, BackendError> {
+        dispatch!(self, backend => backend.cat(hash).await)
+    }
+
+    async fn add_file(
+        &self,
+        path: &Path,
+        cid_version: CidVersion,
+    ) -> Result<IpfsAddResponse, BackendError> {
+        dispatch!(self, backend => backend.add_file(path, cid_version).await)
+    }
+
+    async fn cat_range(&self, hash: &str, offset: u64, length: u64) -> Result<Bytes, BackendError> {
+        dispatch!(self, backend => backend.cat_range(hash, offset, length).await)
+    }
+
+    async fn pin_add(&self, hash: &str) -> Result<IpfsPinAddResponse, BackendError> {
+        dispatch!(self, backend => backend.pin_add(hash).await)
+    }
+
+    async fn pin_rm(&self, hash: &str) -> Result<IpfsPinRmResponse, BackendError> {
+        dispatch!(self, backend => backend.pin_rm(hash).await)
+    }
+
+    async fn pin_ls(&self) -> Result<IpfsPinLsResponse, BackendError> {
+        dispatch!(self, backend => backend.pin_ls().await)
+    }
+
+    async fn repo_stat(&self) -> Result<IpfsRepoStatResponse, BackendError> {
+        dispatch!(self, backend => backend.repo_stat().await)
+    }
+
+    async fn stat(&self, hash: &str) -> Result<u64, BackendError> {
+        dispatch!(self, backend => backend.stat(hash).await)
+    }
+
+    async fn dag_stat(&self, hash: &str) -> Result<IpfsStatResponse, BackendError> {
+        dispatch!(self, backend => backend.dag_stat(hash).await)
+    }
+
+    async fn ls(&self, hash: &str) -> Result<IpfsLsResponse, BackendError> {
+        dispatch!(self, backend => backend.ls(hash).await)
+    }
+
+    async fn refs(&self, hash: &str) -> Result<IpfsRefsResponse, BackendError> {
+        dispatch!(self, backend => backend.refs(hash).await)
+    }
+
+    async fn block_get(&self, hash: &str) -> Result<Bytes, BackendError> {
+        dispatch!(self, backend => backend.block_get(hash).await)
+    }
+
+    async fn dag_export(&self, hash: &str) -> Result<Bytes, BackendError> {
+        dispatch!(self, backend => backend.dag_export(hash).await)
+    }
+
+    async fn dag_import(&self, car: Bytes) -> Result<IpfsDagImportResponse, BackendError> {
+        dispatch!(self, backend => backend.dag_import(car).await)
+    }
+
+    async fn dag_export_file(&self, hash: &str, path: &Path) -> Result<u64, BackendError> {
+        dispatch!(self, backend => backend.dag_export_file(hash, path).await)
+    }
+
+    async fn dag_import_file(&self, path: &Path) -> Result<IpfsDagImportResponse, BackendError> {
+        dispatch!(self, backend => backend.dag_import_file(path).await)
+    }
+
+    async fn repo_gc(&self) -> Result<usize, BackendError> {
+        dispatch!(self, backend => backend.repo_gc().await)
+    }
+
+    async fn key_gen(&self, name: &str) -> Result<IpfsKey, BackendError> {
+        dispatch!(self, backend => backend.key_gen(name).await)
+    }
+
+    async fn key_list(&self) -> Result<IpfsKeyListResponse, BackendError> {
+        dispatch!(self, backend => backend.key_list().await)
+    }
+
+    async fn name_publish(
+        &self,
+        hash: &str,
+        key: &str,
+    ) -> Result<IpfsNamePublishResponse, BackendError> {
+        dispatch!(self, backend => backend.name_publish(hash, key).await)
+    }
+
+    async fn name_resolve(&self, name: &str) -> Result<IpfsNameResolveResponse, BackendError> {
+        dispatch!(self, backend => backend.name_resolve(name).await)
+    }
+
+    async fn files_ls(&self, path: &MfsPath) -> Result<MfsLsResponse, BackendError> {
+        dispatch!(self, backend => backend.files_ls(path).await)
+    }
+
+    async fn files_mkdir(&self, path: &MfsPath, parents: bool) -> Result<(), BackendError> {
+        dispatch!(self, backend => backend.files_mkdir(path, parents).await)
+    }
+
+    async fn files_cp(&self, from: &MfsSource, to: &MfsPath) -> Result<(), BackendError> {
+        dispatch!(self, backend => backend.files_cp(from, to).await)
+    }
+
+    async fn files_mv(&self, from: &MfsPath, to: &MfsPath) -> Result<(), BackendError> {
+        dispatch!(self, backend => backend.files_mv(from, to).await)
+    }
+
+    async fn files_rm(&self, path: &MfsPath, recursive: bool) -> Result<(), BackendError> {
+        dispatch!(self, backend => backend.files_rm(path, recursive).await)
+    }
+
+    async fn files_stat(&self, path: &MfsPath) -> Result<MfsStatResponse, BackendError> {
+        dispatch!(self, backend => backend.files_stat(path).await)
+    }
+
+    async fn files_write(
+        &self,
+        path: &MfsPath,
+        data: Vec<u8>,
+        create: bool,
+    ) -> Result<(), BackendError> {
+        dispatch!(self, backend => backend.files_write(path, data, create).await)
+    }
+
+    async fn files_read(&self, path: &MfsPath) -> Result<Bytes, BackendError> {
+        dispatch!(self, backend => backend.files_read(path).await)
+    }
+}
+
+/// Fetches `cid` from the first connected peer that has it, checking the block against
+/// its hash.
+pub(crate) async fn fetch_block(
+    network_client: &NetworkClient,
+    cid: &Cid,
+) -> Result<Bytes, BackendError> {
+    let local_peer_id = network_client.get_peer_id().await?;
+    let peers = network_client.get_connected_peers().await?;
+    for peer_id in peers.into_iter().filter(|p| *p != local_peer_id) {
+        match network_client.request_block(peer_id, *cid).await {
+            Ok(Some(block)) => match unixfs::verify_block(cid, &block) {
+                Ok(()) => return Ok(block),
+                Err(err) => warn!("Discarding block from {}: {}", peer_id, err),
+            },
+            Ok(None) => {}
+            Err(err) => warn!("Error requesting {} from {}: {}", cid, peer_id, err),
+        }
+    }
+
+    Err(BackendError::NotFound(format!("{} not found", cid)))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BackendError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+
+    #[error("Error deserializing ipfs response")]
+    SerdeDeserialize(#[from] serde_json::Error),
+
+    #[error("Received empty response from ipfs")]
+    EmptyResponse,
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error(transparent)]
+    Cid(#[from] cid::Error),
+
+    #[error(transparent)]
+    Multihash(#[from] cid::multihash::Error),
+
+    #[error("Invalid block: {0}")]
+    InvalidBlock(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Network(#[from] crate::network::NetworkError),
+
+    #[error("{0}")]
+    Mfs(String),
+}
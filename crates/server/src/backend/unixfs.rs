@@ -0,0 +1,427 @@
+//! Just enough dag-pb and unixfs to build the file and directory dags kubo creates with
+//! its default chunker and balanced layout, and to read them back.
+
+use bytes::Bytes;
+use cid::{multihash::Multihash, Cid};
+use sha2::{Digest, Sha256};
+
+use super::BackendError;
+use crate::api::types::ipfs::CidVersion;
+
+pub const DAG_PB: u64 = 0x70;
+pub const RAW: u64 = 0x55;
+const SHA2_256: u64 = 0x12;
+
+const CHUNK_SIZE: usize = 256 * 1024;
+const MAX_LINKS: usize = 174;
+const UNIXFS_DIRECTORY: u64 = 1;
+const UNIXFS_FILE: u64 = 2;
+
+pub struct Block {
+    pub cid: Cid,
+    pub data: Bytes,
+}
+
+pub struct PbLink {
+    pub cid: Cid,
+    /// Entry name for directory links, empty for links between file chunks.
+    pub name: String,
+    pub tsize: u64,
+}
+
+#[derive(Default)]
+pub struct PbNode {
+    pub links: Vec<PbLink>,
+    pub data: Option<Bytes>,
+}
+
+/// A node of the dag under construction.
+struct DagNode {
+    cid: Cid,
+    /// Size of the node's block plus all blocks below it.
+    tsize: u64,
+    filesize: u64,
+}
+
+/// Chunks `data` into a unixfs file dag, returning the root cid and every block of the dag.
+pub fn build_file(data: &[u8], version: CidVersion) -> Result<(Cid, Vec<Block>), BackendError> {
+    let mut blocks = Vec::new();
+
+    let chunks = if data.is_empty() {
+        vec![data]
+    } else {
+        data.chunks(CHUNK_SIZE).collect::<Vec<&[u8]>>()
+    };
+
+    let mut level = chunks
+        .into_iter()
+        .map(|chunk| {
+            let block = match version {
+                CidVersion::V0 => {
+                    let unixfs = encode_unixfs(Some(chunk), chunk.len() as u64, &[]);
+                    dag_pb_block(&encode_node(&[], &unixfs), version)?
+                }
+                CidVersion::V1 => Block {
+                    cid: Cid::new_v1(RAW, sha256(chunk)?),
+                    data: Bytes::copy_from_slice(chunk),
+                },
+            };
+            let node = DagNode {
+                cid: block.cid,
+                tsize: block.data.len() as u64,
+                filesize: chunk.len() as u64,
+            };
+            blocks.push(block);
+            Ok(node)
+        })
+        .collect::<Result<Vec<DagNode>, BackendError>>()?;
+
+    while level.len() > 1 {
+        level = level
+            .chunks(MAX_LINKS)
+            .map(|children| {
+                let links = children
+                    .iter()
+                    .map(|child| PbLink {
+                        cid: child.cid,
+                        name: String::new(),
+                        tsize: child.tsize,
+                    })
+                    .collect::<Vec<PbLink>>();
+                let blocksizes = children.iter().map(|c| c.filesize).collect::<Vec<u64>>();
+                let filesize = blocksizes.iter().sum();
+
+                let unixfs = encode_unixfs(None, filesize, &blocksizes);
+                let block = dag_pb_block(&encode_node(&links, &unixfs), version)?;
+                let node = DagNode {
+                    cid: block.cid,
+                    tsize: block.data.len() as u64 + links.iter().map(|l| l.tsize).sum::<u64>(),
+                    filesize,
+                };
+                blocks.push(block);
+                Ok(node)
+            })
+            .collect::<Result<Vec<DagNode>, BackendError>>()?;
+    }
+
+    Ok((level[0].cid, blocks))
+}
+
+/// Builds a unixfs directory node linking to `links`, which must be sorted by name.
+pub fn build_directory(links: &[PbLink]) -> Result<Block, BackendError> {
+    let mut unixfs = Vec::new();
+    write_varint_field(&mut unixfs, 1, UNIXFS_DIRECTORY);
+    dag_pb_block(&encode_node(links, &unixfs), CidVersion::V0)
+}
+
+/// What a unixfs node holds, as far as listing it is concerned.
+pub struct NodeInfo {
+    pub is_directory: bool,
+    /// Size of the file contents, zero for directories.
+    pub filesize: u64,
+    /// Size of `block` plus every block below it.
+    pub cumulative_size: u64,
+    pub links: usize,
+}
+
+pub fn node_info(cid: &Cid, block: &[u8]) -> Result<NodeInfo, BackendError> {
+    if cid.codec() != DAG_PB {
+        return Ok(NodeInfo {
+            is_directory: false,
+            filesize: block.len() as u64,
+            cumulative_size: block.len() as u64,
+            links: 0,
+        });
+    }
+
+    let node = decode_node(block)?;
+    let mut unixfs_type = UNIXFS_FILE;
+    let mut filesize = 0;
+    if let Some(data) = &node.data {
+        for field in fields(data)? {
+            match field {
+                (1, Field::Varint(value)) => unixfs_type = value,
+                (3, Field::Varint(value)) => filesize = value,
+                _ => {}
+            }
+        }
+    }
+
+    Ok(NodeInfo {
+        is_directory: unixfs_type == UNIXFS_DIRECTORY,
+        filesize,
+        cumulative_size: block.len() as u64 + node.links.iter().map(|l| l.tsize).sum::<u64>(),
+        links: node.links.len(),
+    })
+}
+
+/// Returns the cids linked from `block`.
+pub fn links(cid: &Cid, block: &[u8]) -> Result<Vec<Cid>, BackendError> {
+    match cid.codec() {
+        DAG_PB => Ok(decode_node(block)?
+            .links
+            .into_iter()
+            .map(|link| link.cid)
+            .collect()),
+        _ => Ok(vec![]),
+    }
+}
+
+/// Reads the file contents held directly in `block`, not including its children.
+pub fn file_data(cid: &Cid, block: &Bytes) -> Result<Bytes, BackendError> {
+    match cid.codec() {
+        RAW => Ok(block.clone()),
+        DAG_PB => {
+            let Some(unixfs) = decode_node(block)?.data else {
+                return Ok(Bytes::new());
+            };
+            let data = fields(&unixfs)?
+                .into_iter()
+                .find_map(|field| match field {
+                    (2, Field::Bytes(data)) => Some(Bytes::copy_from_slice(data)),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            Ok(data)
+        }
+        codec => Err(BackendError::InvalidBlock(format!(
+            "Unsupported codec 0x{:x} for {}",
+            codec, cid
+        ))),
+    }
+}
+
+/// Checks that `block` hashes to `cid`, so blocks received from peers can be trusted.
+pub fn verify_block(cid: &Cid, block: &[u8]) -> Result<(), BackendError> {
+    if cid.hash().code() != SHA2_256 {
+        return Err(BackendError::InvalidBlock(format!(
+            "Unsupported hash function 0x{:x} for {}",
+            cid.hash().code(),
+            cid
+        )));
+    }
+    if sha256(block)? != *cid.hash() {
+        return Err(BackendError::InvalidBlock(format!(
+            "Block does not match {}",
+            cid
+        )));
+    }
+    Ok(())
+}
+
+pub fn sha256(data: &[u8]) -> Result<Multihash<64>, BackendError> {
+    let digest = Sha256::digest(data);
+    Ok(Multihash::wrap(SHA2_256, &digest)?)
+}
+
+fn dag_pb_block(data: &[u8], version: CidVersion) -> Result<Block, BackendError> {
+    let hash = sha256(data)?;
+    let cid = match version {
+        CidVersion::V0 => Cid::new_v0(hash)?,
+        CidVersion::V1 => Cid::new_v1(DAG_PB, hash),
+    };
+
+    Ok(Block {
+        cid,
+        data: Bytes::copy_from_slice(data),
+    })
+}
+
+/// Encodes a dag-pb node. Links come before data, as the spec requires.
+pub fn encode_node(links: &[PbLink], data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for link in links {
+        let mut encoded_link = Vec::new();
+        write_bytes(&mut encoded_link, 1, &link.cid.to_bytes());
+        write_bytes(&mut encoded_link, 2, link.name.as_bytes());
+        write_varint_field(&mut encoded_link, 3, link.tsize);
+        write_bytes(&mut buf, 2, &encoded_link);
+    }
+    write_bytes(&mut buf, 1, data);
+    buf
+}
+
+pub fn decode_node(block: &[u8]) -> Result<PbNode, BackendError> {
+    let mut node = PbNode::default();
+
+    for field in fields(block)? {
+        match field {
+            (1, Field::Bytes(data)) => node.data = Some(Bytes::copy_from_slice(data)),
+            (2, Field::Bytes(link)) => {
+                let mut cid = None;
+                let mut name = String::new();
+                let mut tsize = 0;
+                for field in fields(link)? {
+                    match field {
+                        (1, Field::Bytes(hash)) => cid = Some(Cid::try_from(hash)?),
+                        (2, Field::Bytes(link_name)) => {
+                            name = String::from_utf8_lossy(link_name).into_owned()
+                        }
+                        (3, Field::Varint(size)) => tsize = size,
+                        _ => {}
+                    }
+                }
+                let cid = cid.ok_or_else(|| {
+                    BackendError::InvalidBlock("dag-pb link is missing its hash".into())
+                })?;
+                node.links.push(PbLink { cid, name, tsize });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(node)
+}
+
+fn encode_unixfs(data: Option<&[u8]>, filesize: u64, blocksizes: &[u64]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint_field(&mut buf, 1, UNIXFS_FILE);
+    if let Some(data) = data.filter(|d| !d.is_empty()) {
+        write_bytes(&mut buf, 2, data);
+    }
+    write_varint_field(&mut buf, 3, filesize);
+    for size in blocksizes {
+        write_varint_field(&mut buf, 4, *size);
+    }
+    buf
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn fields(mut buf: &[u8]) -> Result<Vec<(u64, Field<'_>)>, BackendError> {
+    let mut fields = Vec::new();
+
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf)?;
+        let field = match key & 0x7 {
+            0 => Field::Varint(read_varint(&mut buf)?),
+            2 => {
+                let len = read_varint(&mut buf)? as usize;
+                if len > buf.len() {
+                    return Err(BackendError::InvalidBlock(
+                        "Truncated protobuf field".into(),
+                    ));
+                }
+                let (value, rest) = buf.split_at(len);
+                buf = rest;
+                Field::Bytes(value)
+            }
+            wire_type => {
+                return Err(BackendError::InvalidBlock(format!(
+                    "Unsupported protobuf wire type {}",
+                    wire_type
+                )))
+            }
+        };
+        fields.push((key >> 3, field));
+    }
+
+    Ok(fields)
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    write_varint(buf, (field << 3) | 2);
+    write_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buf, field << 3);
+    write_varint(buf, value);
+}
+
+pub(super) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub(super) fn read_varint(buf: &mut &[u8]) -> Result<u64, BackendError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| BackendError::InvalidBlock("Truncated varint".into()))?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(BackendError::InvalidBlock("Varint is too long".into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn small_file_matches_kubo_cids() {
+        let (cid, blocks) = build_file(b"hello world\n", CidVersion::V0).unwrap();
+        assert_eq!(
+            cid.to_string(),
+            "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o"
+        );
+        assert_eq!(blocks.len(), 1);
+
+        let (cid, _) = build_file(b"", CidVersion::V0).unwrap();
+        assert_eq!(
+            cid.to_string(),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+    }
+
+    #[test]
+    fn chunked_file_round_trips() {
+        let data = (0..CHUNK_SIZE * 3 + 10)
+            .map(|i| i as u8)
+            .collect::<Vec<u8>>();
+
+        for version in [CidVersion::V0, CidVersion::V1] {
+            let (root, blocks) = build_file(&data, version).unwrap();
+            assert_eq!(blocks.len(), 5);
+
+            let root_block = &blocks.iter().find(|b| b.cid == root).unwrap().data;
+            let contents = links(&root, root_block)
+                .unwrap()
+                .iter()
+                .flat_map(|cid| {
+                    let block = &blocks.iter().find(|b| b.cid == *cid).unwrap().data;
+                    file_data(cid, block).unwrap()
+                })
+                .collect::<Vec<u8>>();
+
+            assert_eq!(contents, data);
+        }
+    }
+
+    #[test]
+    fn directories_match_kubo_cids() {
+        let empty = build_directory(&[]).unwrap();
+        assert_eq!(
+            empty.cid.to_string(),
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        );
+
+        let (file, blocks) = build_file(b"hello world\n", CidVersion::V0).unwrap();
+        let dir = build_directory(&[PbLink {
+            cid: file,
+            name: "hello.txt".into(),
+            tsize: blocks[0].data.len() as u64,
+        }])
+        .unwrap();
+        let node = decode_node(&dir.data).unwrap();
+        assert_eq!(node.links[0].name, "hello.txt");
+
+        let info = node_info(&dir.cid, &dir.data).unwrap();
+        assert!(info.is_directory);
+        assert_eq!(info.links, 1);
+        assert!(!node_info(&file, &blocks[0].data).unwrap().is_directory);
+        assert_eq!(node_info(&file, &blocks[0].data).unwrap().filesize, 12);
+    }
+}
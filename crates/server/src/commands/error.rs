@@ -24,6 +24,12 @@ pub enum CommandError {
         source: crate::network::NetworkError,
     },
 
+    #[error("{source}")]
+    Backend {
+        #[from]
+        source: crate::backend::BackendError,
+    },
+
     #[error("{0}")]
     Arg(String),
 }
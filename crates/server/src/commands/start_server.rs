@@ -1,15 +1,20 @@
+#[cfg(feature = "embedded-ipfs")]
+use crate::backend::embedded::EmbeddedBackend;
 use crate::{
-    network::{GossipCallBackFn, NetworkBuilder},
-    rpc::{
-        ipfs::{IpfsApi, ReqwestClient},
-        Module,
+    backend::{
+        kubo::{KuboBackend, ReqwestClient},
+        memory::MemoryBackend,
+        Backend,
     },
+    crypto::encryption::{KeyId, KEY_SIZE},
+    network::{GossipCallBackFn, NetworkBuilder},
+    rpc::{ipfs::GossipHandler, retry::RetryPolicy, Module},
     server::{builder::ServerBuilder, Server, ServerConfig},
-    state::State,
+    state::{State, StateClient},
 };
-use clap::Parser;
-use futures::future::FutureExt;
-use std::env::var;
+use clap::{Parser, ValueEnum};
+use libp2p::PeerId;
+use std::{collections::HashMap, env::var, fs, path::PathBuf, time::Duration};
 use tracing_subscriber::{reload::Handle, EnvFilter, Registry};
 
 use super::error::CommandError;
@@ -34,35 +39,127 @@ pub struct StartServerCmd {
     #[arg(long, default_value = "")]
     boot_node_addr: String,
 
+    /// Run ipfs garbage collection after expired pins are removed
+    #[arg(long, default_value = "false")]
+    gc_on_expiry: bool,
+
+    /// Refuse gossiped pins that would grow the ipfs repo beyond this size, e.g. 512M or 10G
+    #[arg(long, value_parser = parse_size)]
+    max_storage: Option<u64>,
+
+    /// Persist the pin queue to this file so queued pins survive a restart
+    #[arg(long)]
+    pin_queue_file: Option<PathBuf>,
+
+    /// Number of attempts made for an ipfs request that fails with a transient error
+    #[arg(long, default_value = "3")]
+    retry_attempts: u32,
+
+    /// Delay before the first retry of an ipfs request, doubled on each further attempt
+    #[arg(long, default_value = "200")]
+    retry_base_delay_ms: u64,
+
+    /// Upper bound on the delay between retries of an ipfs request
+    #[arg(long, default_value = "5000")]
+    retry_max_delay_ms: u64,
+
+    /// Where ipfs content is stored. The memory backend keeps everything in process and
+    /// is lost on restart
+    #[arg(long, value_enum, default_value_t = IpfsBackendKind::Kubo)]
+    ipfs_backend: IpfsBackendKind,
+
+    /// Serve ipfs from a block store embedded in this server instead of a kubo node.
+    /// Missing blocks are fetched from cluster peers
+    #[cfg(feature = "embedded-ipfs")]
+    #[arg(long, default_value = "false", conflicts_with = "ipfs_backend")]
+    embedded_ipfs: bool,
+
+    /// Directory the embedded ipfs node stores blocks and pins in
+    #[cfg(feature = "embedded-ipfs")]
+    #[arg(long, default_value = "ipfs-repo")]
+    ipfs_repo: PathBuf,
+
+    /// Convert every cid the server receives or returns to cidv1 in base32
+    #[arg(long, default_value = "false")]
+    normalize_cids: bool,
+
+    /// Serve /ipfs/<cid> over http on this port, e.g. for opening files in a browser
+    #[arg(long)]
+    gateway_port: Option<String>,
+
+    /// Address the gateway listens on. It serves plain http, so it only listens locally
+    /// unless put behind a tls proxy
+    #[arg(long, default_value = "127.0.0.1", requires = "gateway_port")]
+    gateway_ip: String,
+
+    /// Json file mapping secret tokens to the 32 byte keys the gateway decrypts with when a
+    /// request passes `Authorization: Bearer <token>` or a `gateway_token` cookie. Tokens
+    /// grant access to decrypted content, so they should be hard to guess, and a key's
+    /// public id is rejected as its token
+    #[arg(long, requires = "gateway_port")]
+    gateway_keys_file: Option<PathBuf>,
+
+    /// Largest file the gateway decrypts, e.g. 256M. Compressed files are decompressed only
+    /// up to this size, however small they are stored
+    #[arg(long, value_parser = parse_size, default_value = "256M", requires = "gateway_port")]
+    gateway_max_file_size: u64,
+
     #[arg(long, default_value = "false", hide = true)]
     dev: bool,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum IpfsBackendKind {
+    Kubo,
+    Memory,
+}
+
 impl StartServerCmd {
     pub async fn handle(
         self,
         reload_handle: Handle<EnvFilter, Registry>,
     ) -> Result<(), CommandError> {
+        let ipfs_backend = self.ipfs_backend;
+        #[cfg(feature = "embedded-ipfs")]
+        let embedded_repo = self.embedded_ipfs.then(|| self.ipfs_repo.clone());
         let server_config = self.handle_args()?;
 
-        let gossip_callback_fns = Self::build_network_gossip_callback_fns(
-            &server_config.modules,
-            &server_config.ipfs_base_url,
-        );
+        let mut state = State::new();
+        if let Some(pin_queue_file) = &server_config.pin_queue_file {
+            state = state.with_pin_queue_file(pin_queue_file)?;
+        }
+        let state_client = state.start();
 
-        let network = NetworkBuilder::new()
+        #[cfg(feature = "embedded-ipfs")]
+        let backend = match embedded_repo {
+            Some(repo) => Backend::Embedded(EmbeddedBackend::open(repo).await?),
+            None => Self::build_ipfs_backend(ipfs_backend, &server_config, &state_client),
+        };
+        #[cfg(not(feature = "embedded-ipfs"))]
+        let backend = Self::build_ipfs_backend(ipfs_backend, &server_config, &state_client);
+
+        let mut network_builder = NetworkBuilder::new()
             .with_port(&server_config.network_port)
             .with_is_boot_node(server_config.is_boot_node)
             .with_boot_addr(&server_config.boot_node_addr)
-            .with_topic(&server_config.topic)
-            .build()?;
+            .with_topic(&server_config.topic);
+        if let Some(block_provider) = backend.block_provider() {
+            network_builder = network_builder.with_block_provider(block_provider);
+        }
+        let network = network_builder.build()?;
 
-        let state = State::new();
+        let gossip_callback_fns = Self::build_network_gossip_callback_fns(
+            &server_config,
+            &backend,
+            &state_client,
+            network.peer_id(),
+        );
 
         let network_client = network.start(gossip_callback_fns).await?;
-        let state_client = state.start();
+        backend.set_network_client(network_client.clone());
 
         let server = ServerBuilder::new(server_config)
+            .with_ipfs_backend(backend)
             .build(reload_handle, network_client.clone(), state_client.clone())
             .await?;
 
@@ -92,9 +189,15 @@ impl StartServerCmd {
         }
 
         if !self.is_boot_node {
-            modules.push(Module::Ipfs)
+            modules.push(Module::Ipfs);
+            modules.push(Module::Mfs);
         }
 
+        let gateway_keys = match &self.gateway_keys_file {
+            Some(path) => Self::read_gateway_keys(path)?,
+            None => HashMap::new(),
+        };
+
         let ipfs_base_url = var("IPFS_BASE_URL").unwrap_or("http://localhost:5001".into());
         let push_gateway_url =
             var("PUSH_GATEWAY_BASE_URL").unwrap_or("http://localhost:9091".into());
@@ -109,43 +212,83 @@ impl StartServerCmd {
             topic: String::from("ipfs"),
             ipfs_base_url,
             push_gateway_url,
+            gc_on_expiry: self.gc_on_expiry,
+            max_storage: self.max_storage,
+            pin_queue_file: self.pin_queue_file,
+            retry_policy: RetryPolicy::new(
+                self.retry_attempts,
+                Duration::from_millis(self.retry_base_delay_ms),
+                Duration::from_millis(self.retry_max_delay_ms),
+            ),
+            normalize_cids: self.normalize_cids,
+            gateway_ip: self.gateway_ip,
+            gateway_port: self.gateway_port,
+            gateway_keys,
+            gateway_max_file_size: self.gateway_max_file_size,
         };
 
         Ok(config)
     }
 
-    fn build_network_gossip_callback_fns<I>(
-        modules: &[Module],
-        ipfs_base_url: I,
-    ) -> Vec<GossipCallBackFn>
-    where
-        I: ToString + std::marker::Send,
-    {
-        modules
+    fn read_gateway_keys(path: &PathBuf) -> Result<HashMap<String, Vec<u8>>, CommandError> {
+        let keys: HashMap<String, Vec<u8>> = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|err| CommandError::Arg(format!("Invalid gateway keys file: {}", err)))?;
+
+        if let Some((token, _)) = keys.iter().find(|(_, key)| key.len() != KEY_SIZE) {
+            return Err(CommandError::Arg(format!(
+                "Gateway key {} must be {} bytes",
+                token, KEY_SIZE
+            )));
+        }
+        // key ids are in the header of every ciphertext, so anyone holding one could read it
+        if let Some((token, _)) = keys
+            .iter()
+            .find(|(token, key)| **token == KeyId::of(key).to_string())
+        {
+            return Err(CommandError::Arg(format!(
+                "Gateway key {} is named by its key id, use a secret token instead",
+                token
+            )));
+        }
+
+        Ok(keys)
+    }
+
+    fn build_ipfs_backend(
+        kind: IpfsBackendKind,
+        server_config: &ServerConfig,
+        state_client: &StateClient,
+    ) -> Backend {
+        match kind {
+            IpfsBackendKind::Kubo => Backend::Kubo(KuboBackend::new(
+                &server_config.ipfs_base_url,
+                ReqwestClient::default(),
+                server_config.retry_policy,
+                state_client.clone(),
+            )),
+            IpfsBackendKind::Memory => Backend::Memory(MemoryBackend::new()),
+        }
+    }
+
+    fn build_network_gossip_callback_fns(
+        server_config: &ServerConfig,
+        backend: &Backend,
+        state_client: &StateClient,
+        local_peer_id: PeerId,
+    ) -> Vec<GossipCallBackFn> {
+        server_config
+            .modules
             .iter()
             .filter_map(|m| {
                 if let Module::Ipfs = m {
-                    let client = ReqwestClient::default();
-                    let ipfs_base_url = ipfs_base_url.to_string();
-
-                    let callback_fn: GossipCallBackFn = Box::new({
-                        move |msg: &[u8]| {
-                            let ipfs_base_url = ipfs_base_url.clone();
-                            let client = client.clone();
-
-                            async move {
-                                IpfsApi::<ReqwestClient>::gossip_callback_fn(
-                                    msg,
-                                    ipfs_base_url,
-                                    client,
-                                )
-                                .await;
-                            }
-                            .boxed()
-                        }
-                    });
-
-                    Some(callback_fn)
+                    let handler = GossipHandler::new(
+                        backend.clone(),
+                        state_client.clone(),
+                        local_peer_id,
+                        server_config.normalize_cids,
+                    );
+
+                    Some(handler.into_callback_fn())
                 } else {
                     None
                 }
@@ -153,3 +296,57 @@ impl StartServerCmd {
             .collect::<Vec<GossipCallBackFn>>()
     }
 }
+
+/// Parses a size such as `1073741824`, `512M` or `10G` into bytes.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (value, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => size.split_at(index),
+        None => (size, ""),
+    };
+
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| format!("Unable to parse size: {}", size))?;
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => return Err(format!("Unknown size unit: {}", unit)),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size is too large: {}", size))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_size_with_units() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("10gb").unwrap(), 10 << 30);
+        assert!(parse_size("10P").is_err());
+        assert!(parse_size("G").is_err());
+    }
+
+    #[test]
+    fn gateway_keys_are_not_named_by_their_key_id() {
+        let key = vec![7; KEY_SIZE];
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let write = |token: &str| {
+            let keys = HashMap::from([(token.to_string(), key.clone())]);
+            fs::write(file.path(), serde_json::to_vec(&keys).unwrap()).unwrap();
+        };
+
+        write("0f3a9c1e7b2d4f68a1c3");
+        assert!(StartServerCmd::read_gateway_keys(&file.path().to_path_buf()).is_ok());
+        write(&KeyId::of(&key).to_string());
+        assert!(StartServerCmd::read_gateway_keys(&file.path().to_path_buf()).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder, ZstdDecoder, ZstdEncoder};
+use std::{
+    io::{self, Read},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, BufReader, ReadBuf};
+
+use super::encryption::Compression;
+
+/// Compresses what it reads from `inner`, so data can be compressed on its way into an
+/// [`EncryptWriter`](super::encryption::EncryptWriter).
+pub enum CompressReader<R> {
+    None(R),
+    Zstd(ZstdEncoder<BufReader<R>>),
+    Gzip(GzipEncoder<BufReader<R>>),
+}
+
+impl<R: AsyncRead + Unpin> CompressReader<R> {
+    pub fn new(compression: Compression, inner: R) -> Self {
+        match compression {
+            Compression::None => Self::None(inner),
+            Compression::Zstd => Self::Zstd(ZstdEncoder::new(BufReader::new(inner))),
+            Compression::Gzip => Self::Gzip(GzipEncoder::new(BufReader::new(inner))),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        match self {
+            Self::None(inner) => inner,
+            Self::Zstd(encoder) => encoder.into_inner().into_inner(),
+            Self::Gzip(encoder) => encoder.into_inner().into_inner(),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CompressReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::None(inner) => Pin::new(inner).poll_read(cx, buf),
+            Self::Zstd(encoder) => Pin::new(encoder).poll_read(cx, buf),
+            Self::Gzip(encoder) => Pin::new(encoder).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Decompresses what it reads from `inner`.
+pub enum DecompressReader<R> {
+    None(R),
+    Zstd(ZstdDecoder<BufReader<R>>),
+    Gzip(GzipDecoder<BufReader<R>>),
+}
+
+impl<R: AsyncRead + Unpin> DecompressReader<R> {
+    pub fn new(compression: Compression, inner: R) -> Self {
+        match compression {
+            Compression::None => Self::None(inner),
+            Compression::Zstd => Self::Zstd(ZstdDecoder::new(BufReader::new(inner))),
+            Compression::Gzip => Self::Gzip(GzipDecoder::new(BufReader::new(inner))),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DecompressReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::None(inner) => Pin::new(inner).poll_read(cx, buf),
+            Self::Zstd(decoder) => Pin::new(decoder).poll_read(cx, buf),
+            Self::Gzip(decoder) => Pin::new(decoder).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Decompresses `data` in one piece, as [`Encryption::decrypt`](super::encryption::Encryption::decrypt)
+/// does.
+pub fn decompress(compression: Compression, data: Vec<u8>) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data),
+        Compression::Zstd => zstd::decode_all(data.as_slice()),
+        Compression::Gzip => {
+            let mut decompressed = Vec::with_capacity(data.len());
+            flate2::read::GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::encryption::{
+        DecryptReader, EncryptWriter, Encryption, EncryptionError, Header, NONCE_PREFIX_SIZE,
+    };
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn encrypt(encryption_key: &[u8], compression: Compression, data: &[u8]) -> Vec<u8> {
+        let mut reader = CompressReader::new(compression, data);
+        let mut writer = EncryptWriter::new(encryption_key, Vec::new()).compressed(compression);
+        tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        writer.shutdown().await.unwrap();
+        writer.into_inner()
+    }
+
+    async fn decrypt(encryption_key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        DecryptReader::new(encryption_key, data)
+            .read_to_end(&mut result)
+            .await
+            .unwrap();
+        result
+    }
+
+    /// Json log lines, which compress well.
+    fn json_logs(len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(len);
+        let mut line = 0;
+        while data.len() < len {
+            data.extend(
+                format!(
+                    "{{\"ts\":{},\"level\":\"{}\",\"target\":\"server::network\",\
+                     \"message\":\"Gossip message received\",\"peer\":{}}}\n",
+                    1_700_000_000 + line,
+                    ["INFO", "WARN", "DEBUG"][line % 3],
+                    line * 7919 % 64
+                )
+                .bytes(),
+            );
+            line += 1;
+        }
+        data.truncate(len);
+        data
+    }
+
+    /// Bytes that do not compress at all.
+    fn random(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn compressed_files_are_decompressed_on_decryption() {
+        let encryption_key = Encryption::generate_key().to_vec();
+        let data = json_logs(200_000);
+
+        for compression in [Compression::None, Compression::Zstd, Compression::Gzip] {
+            let ciphertext = encrypt(&encryption_key, compression, &data).await;
+            let header = Header::parse(&ciphertext).unwrap().unwrap();
+            assert_eq!(header.compression, compression);
+            if compression != Compression::None {
+                assert!(ciphertext.len() < data.len() / 5);
+            }
+
+            assert_eq!(decrypt(&encryption_key, &ciphertext).await, data);
+            assert_eq!(
+                Encryption::decrypt(&encryption_key, &ciphertext).unwrap(),
+                data
+            );
+        }
+
+        // the compression byte, before the key id and nonce prefix, is authenticated like the
+        // rest of the header
+        let mut ciphertext = encrypt(&encryption_key, Compression::Zstd, &data).await;
+        ciphertext[Header::LEN - 8 - NONCE_PREFIX_SIZE - 1] = Compression::Gzip as u8;
+        assert!(matches!(
+            Encryption::decrypt(&encryption_key, &ciphertext),
+            Err(EncryptionError::Aead)
+        ));
+    }
+
+    /// Size and time of encrypting and decrypting sample corpora with each compression.
+    /// Run with `cargo test -p server --release -- --ignored --nocapture compression_benchmark`.
+    #[tokio::test]
+    #[ignore]
+    async fn compression_benchmark() {
+        let encryption_key = Encryption::generate_key().to_vec();
+        let len = 16 * 1024 * 1024;
+        let corpora = [
+            ("json logs", json_logs(len)),
+            (
+                "source",
+                [
+                    include_bytes!("encryption.rs").as_slice(),
+                    include_bytes!("envelope.rs"),
+                    include_bytes!("convergent.rs"),
+                    include_bytes!("../gateway.rs"),
+                    include_bytes!("../state.rs"),
+                ]
+                .concat(),
+            ),
+            ("random", random(len)),
+        ];
+
+        println!(
+            "{:<10} {:<5} {:>12} {:>7} {:>12} {:>12}",
+            "corpus", "codec", "bytes", "ratio", "encrypt", "decrypt"
+        );
+        for (name, data) in &corpora {
+            for compression in [Compression::None, Compression::Zstd, Compression::Gzip] {
+                let (ciphertext, encrypt_time) =
+                    timed(encrypt(&encryption_key, compression, data)).await;
+                let (plaintext, decrypt_time) = timed(decrypt(&encryption_key, &ciphertext)).await;
+                assert_eq!(&plaintext, data);
+
+                println!(
+                    "{:<10} {:<5} {:>12} {:>6.2}x {:>10.1}ms {:>10.1}ms",
+                    name,
+                    compression,
+                    ciphertext.len(),
+                    data.len() as f64 / ciphertext.len() as f64,
+                    encrypt_time.as_secs_f64() * 1000.0,
+                    decrypt_time.as_secs_f64() * 1000.0
+                );
+            }
+        }
+    }
+
+    async fn timed<T>(future: impl std::future::Future<Output = T>) -> (T, Duration) {
+        let start = Instant::now();
+        let output = future.await;
+        (output, start.elapsed())
+    }
+}
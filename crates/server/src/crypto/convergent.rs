@@ -0,0 +1,139 @@
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use zeroize::Zeroizing;
+
+use super::{
+    encryption::{KeyId, NONCE_PREFIX_SIZE, SEGMENT_SIZE},
+    envelope::PublicKey,
+};
+
+/// Keys and nonces derived from a keyed hash of a file's plaintext, so adding the same file
+/// twice encrypts it to the same bytes and the cluster stores it once.
+///
+/// This leaks which files are identical: anyone who can list cids sees that two uploads are
+/// the same file, and anyone holding the key the hash is keyed with can confirm a guess of a
+/// file's contents by encrypting the guess and comparing cids.
+pub struct Convergent {
+    digest: Zeroizing<[u8; 32]>,
+}
+
+impl Convergent {
+    /// Hashes everything `reader` yields, keyed with a secret derived from `encryption_key`,
+    /// so only holders of the same key produce the same ciphertexts.
+    pub async fn derive<R: AsyncRead + Unpin>(
+        encryption_key: &[u8],
+        mut reader: R,
+    ) -> io::Result<Self> {
+        let mut secret = Zeroizing::new([0; 32]);
+        Hkdf::<Sha256>::new(None, encryption_key)
+            .expand(b"local-ipfs convergence secret", secret.as_mut())
+            .expect("32 bytes is a valid hkdf output length");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_slice())
+            .expect("hmac accepts keys of any length");
+        let mut buf = vec![0; SEGMENT_SIZE];
+        loop {
+            let len = reader.read(&mut buf).await?;
+            if len == 0 {
+                break;
+            }
+            mac.update(&buf[..len]);
+        }
+
+        Ok(Self {
+            digest: Zeroizing::new(mac.finalize().into_bytes().into()),
+        })
+    }
+
+    pub fn data_key(&self) -> Zeroizing<Vec<u8>> {
+        let mut key = Zeroizing::new(vec![0; 32]);
+        self.expand(&[b"data key"], &mut key);
+        key
+    }
+
+    pub fn nonce_prefix(&self) -> [u8; NONCE_PREFIX_SIZE] {
+        let mut prefix = [0; NONCE_PREFIX_SIZE];
+        self.expand(&[b"nonce prefix"], &mut prefix);
+        prefix
+    }
+
+    /// Nonce the data key is wrapped with for the key `key_id`.
+    pub fn key_nonce(&self, key_id: &KeyId) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        self.expand(&[b"key nonce", key_id.as_bytes()], &mut nonce);
+        nonce
+    }
+
+    /// Secret of the ephemeral key the data key is wrapped with for `recipient`.
+    pub fn ephemeral_secret(&self, recipient: &PublicKey) -> Zeroizing<[u8; 32]> {
+        let mut secret = Zeroizing::new([0; 32]);
+        self.expand(
+            &[b"ephemeral secret", recipient.as_bytes()],
+            secret.as_mut(),
+        );
+        secret
+    }
+
+    fn expand(&self, info: &[&[u8]], output: &mut [u8]) {
+        Hkdf::<Sha256>::from_prk(self.digest.as_slice())
+            .expect("the digest is a full length prk")
+            .expand_multi_info(info, output)
+            .expect("outputs are shorter than the hkdf limit");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::{
+        encryption::{EncryptWriter, Encryption},
+        envelope::{Envelope, Identity},
+    };
+    use tokio::io::AsyncWriteExt;
+
+    async fn encrypt(encryption_key: &[u8], recipients: &[PublicKey], data: &[u8]) -> Vec<u8> {
+        let convergent = Convergent::derive(encryption_key, data).await.unwrap();
+        let envelope = Envelope::convergent(&convergent, encryption_key, recipients).unwrap();
+        let mut writer = EncryptWriter::with_nonce_prefix(
+            &convergent.data_key(),
+            convergent.nonce_prefix(),
+            envelope.to_bytes(),
+        );
+        writer.write_all(data).await.unwrap();
+        writer.shutdown().await.unwrap();
+        writer.into_inner()
+    }
+
+    #[tokio::test]
+    async fn identical_files_encrypt_identically() {
+        let key = Encryption::generate_key().to_vec();
+        let teammate = Identity::generate();
+        let recipients = [teammate.public_key()];
+
+        let first = encrypt(&key, &recipients, b"quarterly report").await;
+        let second = encrypt(&key, &recipients, b"quarterly report").await;
+        // the leak: whoever sees both ciphertexts, or their cids, knows the files match
+        assert_eq!(first, second);
+        assert_ne!(first, encrypt(&key, &recipients, b"quarterly rep0rt").await);
+
+        let other_key = Encryption::generate_key().to_vec();
+        assert_ne!(
+            first,
+            encrypt(&other_key, &recipients, b"quarterly report").await
+        );
+
+        let (envelope, payload) = Envelope::parse(&first).unwrap().unwrap();
+        for data_key in [
+            envelope.unwrap_with_key(&key).unwrap().unwrap(),
+            envelope.unwrap_with_identity(&teammate).unwrap().unwrap(),
+        ] {
+            assert_eq!(
+                Encryption::decrypt(&data_key, payload).unwrap(),
+                b"quarterly report"
+            );
+        }
+    }
+}
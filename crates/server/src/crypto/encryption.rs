@@ -0,0 +1,785 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, Key, KeyInit, Nonce, OsRng, Payload},
+    Aes256Gcm,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::{self, Display},
+    io,
+    pin::Pin,
+    str::FromStr,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::compression::{self, DecompressReader};
+
+/// Length of the AES-256 keys files are encrypted with.
+pub const KEY_SIZE: usize = 32;
+/// Plaintext bytes sealed in each segment of an encrypted stream.
+pub const SEGMENT_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+pub const NONCE_PREFIX_SIZE: usize = 7;
+/// Streams written before the versioned header start with the length of their nonce prefix,
+/// as one shot ciphertexts start with the length of their 12 byte nonce.
+const LEGACY_STREAM_HEADER_SIZE: usize = 1 + NONCE_PREFIX_SIZE;
+
+const MAGIC: &[u8; 4] = b"LIPE";
+const VERSION: u8 = 1;
+
+/// Cipher the payload after a [`Header`] is sealed with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// AES-256-GCM over [`SEGMENT_SIZE`] segments, in the STREAM construction.
+    Aes256GcmStream = 1,
+}
+
+/// How the plaintext was compressed before it was encrypted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None = 0,
+    Zstd = 1,
+    Gzip = 2,
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Zstd => write!(f, "zstd"),
+            Self::Gzip => write!(f, "gzip"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "zstd" => Ok(Self::Zstd),
+            "gzip" => Ok(Self::Gzip),
+            _ => Err(format!("Unknown compression {}, expected zstd or gzip", s)),
+        }
+    }
+}
+
+/// Fingerprint of an encryption key, so data names the key it needs without revealing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct KeyId([u8; 8]);
+
+impl KeyId {
+    pub fn of(encryption_key: &[u8]) -> Self {
+        let digest = Sha256::new()
+            .chain_update(b"local-ipfs key id")
+            .chain_update(encryption_key)
+            .finalize();
+        let mut id = [0; 8];
+        id.copy_from_slice(&digest[..8]);
+        Self(id)
+    }
+
+    pub(crate) fn from_bytes(id: [u8; 8]) -> Self {
+        Self(id)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; 8] {
+        &self.0
+    }
+}
+
+impl Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// Leads every ciphertext: magic bytes, format version, algorithm, compression, the key id
+/// and the stream's nonce prefix. The whole header is authenticated with every segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub compression: Compression,
+    pub key_id: KeyId,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+}
+
+impl Header {
+    pub const LEN: usize = MAGIC.len() + 3 + 8 + NONCE_PREFIX_SIZE;
+
+    fn new(encryption_key: &[u8], nonce_prefix: [u8; NONCE_PREFIX_SIZE]) -> Self {
+        Self {
+            version: VERSION,
+            algorithm: Algorithm::Aes256GcmStream,
+            compression: Compression::None,
+            key_id: KeyId::of(encryption_key),
+            nonce_prefix,
+        }
+    }
+
+    /// Parses the header at the start of `data`, or returns `None` for data encrypted
+    /// before headers were written.
+    pub fn parse(data: &[u8]) -> Result<Option<Self>, EncryptionError> {
+        if !data.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        let data = &data[MAGIC.len()..];
+        match data.first() {
+            Some(&VERSION) => {}
+            Some(&version) => return Err(EncryptionError::UnsupportedVersion(version)),
+            None => return Err(EncryptionError::Truncated),
+        }
+        if data.len() < Self::LEN - MAGIC.len() {
+            return Err(EncryptionError::Truncated);
+        }
+
+        let algorithm = match data[1] {
+            1 => Algorithm::Aes256GcmStream,
+            algorithm => return Err(EncryptionError::UnsupportedAlgorithm(algorithm)),
+        };
+        let compression = match data[2] {
+            0 => Compression::None,
+            1 => Compression::Zstd,
+            2 => Compression::Gzip,
+            compression => return Err(EncryptionError::UnsupportedCompression(compression)),
+        };
+        let mut key_id = [0; 8];
+        key_id.copy_from_slice(&data[3..11]);
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&data[11..11 + NONCE_PREFIX_SIZE]);
+
+        Ok(Some(Self {
+            version: VERSION,
+            algorithm,
+            compression,
+            key_id: KeyId(key_id),
+            nonce_prefix,
+        }))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&[self.version, self.algorithm as u8, self.compression as u8]);
+        bytes.extend_from_slice(&self.key_id.0);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes
+    }
+
+    fn check_key(&self, expected: KeyId) -> Result<(), EncryptionError> {
+        if self.key_id != expected {
+            return Err(EncryptionError::WrongKey {
+                expected,
+                found: self.key_id,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct Encryption;
+
+impl Encryption {
+    pub fn generate_key() -> Key<Aes256Gcm> {
+        Aes256Gcm::generate_key(OsRng)
+    }
+
+    /// Seals `data` in one piece, the way files were encrypted before [`EncryptWriter`].
+    #[cfg(test)]
+    pub fn encrypt_legacy(encryption_key: &[u8], data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let key = Key::<Aes256Gcm>::from_slice(encryption_key);
+
+        let cipher = Aes256Gcm::new(key);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, data)?;
+
+        let mut result = vec![nonce.len() as u8];
+        let mut nonce_vec = nonce.to_vec();
+        nonce_vec.extend(ciphertext);
+        result.extend(nonce_vec);
+
+        Ok(result)
+    }
+
+    /// Decrypts data written by an [`EncryptWriter`], or by earlier versions of the cli, and
+    /// undoes the compression its header records.
+    pub fn decrypt(encryption_key: &[u8], data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key));
+
+        let (mut segments, compression, body) = match Header::parse(data)? {
+            Some(header) => {
+                header.check_key(KeyId::of(encryption_key))?;
+                let segments = Segments::new(cipher, &header);
+                (segments, header.compression, &data[Header::LEN..])
+            }
+            None if data.first() == Some(&(NONCE_PREFIX_SIZE as u8)) => {
+                let (prefix, body) = split_legacy_stream(data)?;
+                (Segments::legacy(cipher, prefix), Compression::None, body)
+            }
+            None => return decrypt_legacy(&cipher, data),
+        };
+
+        let body = body.chunks(SEGMENT_SIZE + TAG_SIZE).collect::<Vec<_>>();
+        if body.is_empty() {
+            return Err(EncryptionError::Truncated);
+        }
+        let mut plaintext = Vec::with_capacity(data.len());
+        for (index, segment) in body.iter().enumerate() {
+            plaintext.extend(segments.open(segment, index == body.len() - 1)?);
+        }
+
+        Ok(compression::decompress(compression, plaintext)?)
+    }
+}
+
+/// One shot ciphertexts: the nonce length, the nonce, then the AES-256-GCM output.
+fn decrypt_legacy(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    let nonce_length: usize = (*data.first().ok_or(EncryptionError::Truncated)?).into();
+    if nonce_length != 12 {
+        return Err(EncryptionError::Unrecognized);
+    }
+    if data.len() <= nonce_length {
+        return Err(EncryptionError::Truncated);
+    }
+    let nonce = &data[1..=nonce_length];
+    let ciphertext = &data[nonce_length + 1..];
+
+    Ok(cipher.decrypt(nonce.into(), ciphertext)?)
+}
+
+fn split_legacy_stream(data: &[u8]) -> Result<([u8; NONCE_PREFIX_SIZE], &[u8]), EncryptionError> {
+    if data.len() < LEGACY_STREAM_HEADER_SIZE {
+        return Err(EncryptionError::Truncated);
+    }
+    let mut prefix = [0; NONCE_PREFIX_SIZE];
+    prefix.copy_from_slice(&data[1..LEGACY_STREAM_HEADER_SIZE]);
+    Ok((prefix, &data[LEGACY_STREAM_HEADER_SIZE..]))
+}
+
+/// Seals and opens the segments of a stream. Segment nonces are the stream's random nonce
+/// prefix, a big endian segment counter and a flag set only on the last segment, so
+/// reordering, dropping or truncating segments fails authentication.
+struct Segments {
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    /// The header, empty for streams written before it existed.
+    aad: Vec<u8>,
+    counter: u32,
+}
+
+impl Segments {
+    fn new(cipher: Aes256Gcm, header: &Header) -> Self {
+        Self {
+            cipher,
+            nonce_prefix: header.nonce_prefix,
+            aad: header.to_bytes(),
+            counter: 0,
+        }
+    }
+
+    fn legacy(cipher: Aes256Gcm, nonce_prefix: [u8; NONCE_PREFIX_SIZE]) -> Self {
+        Self {
+            cipher,
+            nonce_prefix,
+            aad: Vec::new(),
+            counter: 0,
+        }
+    }
+
+    fn nonce(&mut self, last: bool) -> Result<Nonce<Aes256Gcm>, EncryptionError> {
+        let mut nonce = Nonce::<Aes256Gcm>::default();
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_SIZE..NONCE_PREFIX_SIZE + 4]
+            .copy_from_slice(&self.counter.to_be_bytes());
+        nonce[NONCE_PREFIX_SIZE + 4] = last as u8;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(EncryptionError::TooLong)?;
+        Ok(nonce)
+    }
+
+    fn seal(&mut self, plaintext: &[u8], last: bool) -> Result<Vec<u8>, EncryptionError> {
+        let nonce = self.nonce(last)?;
+        let payload = Payload {
+            msg: plaintext,
+            aad: &self.aad,
+        };
+        Ok(self.cipher.encrypt(&nonce, payload)?)
+    }
+
+    fn open(&mut self, segment: &[u8], last: bool) -> Result<Vec<u8>, EncryptionError> {
+        let nonce = self.nonce(last)?;
+        let payload = Payload {
+            msg: segment,
+            aad: &self.aad,
+        };
+        Ok(self.cipher.decrypt(&nonce, payload)?)
+    }
+}
+
+/// Encrypts everything written to it in [`SEGMENT_SIZE`] segments, so only one segment of
+/// plaintext is held at a time. The stream is only complete once shut down.
+pub struct EncryptWriter<W> {
+    inner: W,
+    header: Header,
+    segments: Segments,
+    plaintext: Vec<u8>,
+    /// Sealed bytes not yet written to `inner`.
+    output: Vec<u8>,
+    written: usize,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptWriter<W> {
+    pub fn new(encryption_key: &[u8], inner: W) -> Self {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&nonce[..NONCE_PREFIX_SIZE]);
+        Self::with_nonce_prefix(encryption_key, nonce_prefix, inner)
+    }
+
+    /// Encrypts under a nonce prefix chosen by the caller. A prefix must never be used twice
+    /// with a key, unless for the same plaintext, as convergent encryption does.
+    pub fn with_nonce_prefix(
+        encryption_key: &[u8],
+        nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+        inner: W,
+    ) -> Self {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key));
+        let header = Header::new(encryption_key, nonce_prefix);
+
+        let mut output = Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE);
+        output.extend(header.to_bytes());
+
+        Self {
+            inner,
+            segments: Segments::new(cipher, &header),
+            header,
+            plaintext: Vec::with_capacity(SEGMENT_SIZE),
+            output,
+            written: 0,
+            finished: false,
+        }
+    }
+
+    /// Records in the header that what is written was compressed with `compression`, so
+    /// readers undo it. Compressing is up to the caller, see
+    /// [`CompressReader`](super::compression::CompressReader).
+    pub fn compressed(mut self, compression: Compression) -> Self {
+        assert!(
+            self.segments.counter == 0 && self.plaintext.is_empty(),
+            "compression is set before writing"
+        );
+        self.header.compression = compression;
+        self.segments.aad = self.header.to_bytes();
+        self.output = self.header.to_bytes();
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let segment = self.segments.seal(&self.plaintext, last)?;
+        self.plaintext.clear();
+        self.output.extend(segment);
+        Ok(())
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let written =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.output[self.written..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += written;
+        }
+        self.output.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Stream is already finished",
+            )));
+        }
+        ready!(this.poll_drain(cx))?;
+
+        // a full segment is only sealed once more data arrives, as the last segment must
+        // be marked as such
+        if this.plaintext.len() == SEGMENT_SIZE && !buf.is_empty() {
+            this.seal(false)?;
+        }
+        let len = buf.len().min(SEGMENT_SIZE - this.plaintext.len());
+        this.plaintext.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.finished {
+            this.seal(true)?;
+            this.finished = true;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Decrypts data as [`Encryption::decrypt`] does while reading it, failing with
+/// [`io::ErrorKind::InvalidData`] if it was modified or cut short.
+pub struct DecryptReader<R> {
+    /// Set until the header has been read.
+    header: Option<SegmentReader<R>>,
+    body: Option<DecompressReader<SegmentReader<R>>>,
+}
+
+impl<R: AsyncRead + Unpin> DecryptReader<R> {
+    pub fn new(encryption_key: &[u8], inner: R) -> Self {
+        Self {
+            header: Some(SegmentReader::new(encryption_key, inner)),
+            body: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DecryptReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(reader) = this.header.as_mut() {
+            let compression = ready!(reader.poll_compression(cx))?;
+            let reader = this.header.take().expect("header is being read");
+            this.body = Some(DecompressReader::new(compression, reader));
+        }
+        let body = this.body.as_mut().expect("body is read after the header");
+        Pin::new(body).poll_read(cx, buf)
+    }
+}
+
+/// Decrypts the segments of a stream, leaving them compressed.
+struct SegmentReader<R> {
+    inner: R,
+    cipher: Aes256Gcm,
+    key_id: KeyId,
+    /// Set once the header has been read.
+    segments: Option<Segments>,
+    compression: Compression,
+    /// Ciphertext read from `inner` but not yet decrypted.
+    input: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    eof: bool,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> SegmentReader<R> {
+    fn new(encryption_key: &[u8], inner: R) -> Self {
+        Self {
+            inner,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key)),
+            key_id: KeyId::of(encryption_key),
+            segments: None,
+            compression: Compression::None,
+            input: Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE + 1),
+            plaintext: Vec::new(),
+            position: 0,
+            eof: false,
+            finished: false,
+        }
+    }
+
+    /// Reads from `inner` until `input` holds `len` bytes or the stream ends.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<io::Result<()>> {
+        while !self.eof && self.input.len() < len {
+            let start = self.input.len();
+            self.input.resize(len.min(start + SEGMENT_SIZE), 0);
+            let mut buf = ReadBuf::new(&mut self.input[start..]);
+            let result = Pin::new(&mut self.inner).poll_read(cx, &mut buf);
+            let read = buf.filled().len();
+            self.input.truncate(start + read);
+            ready!(result)?;
+            self.eof = read == 0;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Sets up `segments` from the header, or decrypts the whole of a one shot ciphertext.
+    fn poll_header(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), EncryptionError>> {
+        ready!(self.poll_fill(cx, Header::LEN))?;
+        match Header::parse(&self.input)? {
+            Some(header) => {
+                header.check_key(self.key_id)?;
+                self.segments = Some(Segments::new(self.cipher.clone(), &header));
+                self.compression = header.compression;
+                self.input.drain(..Header::LEN);
+            }
+            None if self.input.first() == Some(&(NONCE_PREFIX_SIZE as u8)) => {
+                let (prefix, _) = split_legacy_stream(&self.input)?;
+                self.segments = Some(Segments::legacy(self.cipher.clone(), prefix));
+                self.input.drain(..LEGACY_STREAM_HEADER_SIZE);
+            }
+            None => {
+                ready!(self.poll_fill(cx, usize::MAX))?;
+                self.plaintext = decrypt_legacy(&self.cipher, &self.input)?;
+                self.finished = true;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads the header, for the compression it records.
+    fn poll_compression(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Compression>> {
+        if self.segments.is_none() && !self.finished {
+            ready!(self.poll_header(cx))?;
+        }
+        Poll::Ready(Ok(self.compression))
+    }
+
+    fn poll_segment(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), EncryptionError>> {
+        // reading one byte past a full segment shows whether it is the last
+        ready!(self.poll_fill(cx, SEGMENT_SIZE + TAG_SIZE + 1))?;
+        let last = self.input.len() <= SEGMENT_SIZE + TAG_SIZE;
+        let len = self.input.len().min(SEGMENT_SIZE + TAG_SIZE);
+        let segments = self
+            .segments
+            .as_mut()
+            .expect("header is read before segments");
+
+        self.plaintext = segments.open(&self.input[..len], last)?;
+        self.position = 0;
+        self.input.drain(..len);
+        self.finished = last;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SegmentReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.position < this.plaintext.len() {
+                let len = buf.remaining().min(this.plaintext.len() - this.position);
+                buf.put_slice(&this.plaintext[this.position..this.position + len]);
+                this.position += len;
+                return Poll::Ready(Ok(()));
+            }
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+
+            match this.segments {
+                None => ready!(this.poll_header(cx))?,
+                Some(_) => ready!(this.poll_segment(cx))?,
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("Unable to decrypt, the data was modified or the key is wrong")]
+    Aead,
+
+    #[error("Data was not encrypted by the cli")]
+    Unrecognized,
+
+    #[error("Encrypted data is truncated")]
+    Truncated,
+
+    #[error("Encrypted data is too long")]
+    TooLong,
+
+    #[error("Unsupported encryption format version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Unsupported encryption algorithm {0}")]
+    UnsupportedAlgorithm(u8),
+
+    #[error("Unsupported compression {0}")]
+    UnsupportedCompression(u8),
+
+    #[error("Public key is not a usable x25519 key")]
+    InvalidPublicKey,
+
+    #[error("Data was encrypted with key {found}, not the configured key {expected}")]
+    WrongKey { expected: KeyId, found: KeyId },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<aes_gcm::Error> for EncryptionError {
+    fn from(_: aes_gcm::Error) -> Self {
+        Self::Aead
+    }
+}
+
+impl From<EncryptionError> for io::Error {
+    fn from(err: EncryptionError) -> Self {
+        match err {
+            EncryptionError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn encrypt_stream(encryption_key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptWriter::new(encryption_key, Vec::new());
+        // odd sized writes cross segment boundaries
+        for chunk in data.chunks(10_000) {
+            writer.write_all(chunk).await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+        writer.into_inner()
+    }
+
+    async fn decrypt_stream(encryption_key: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        DecryptReader::new(encryption_key, data)
+            .read_to_end(&mut result)
+            .await?;
+        Ok(result)
+    }
+
+    /// A stream as written before the versioned header, without additional data.
+    fn encrypt_legacy_stream(encryption_key: &[u8], data: &[u8]) -> Vec<u8> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key));
+        let prefix = [9; NONCE_PREFIX_SIZE];
+        let mut segments = Segments::legacy(cipher, prefix);
+
+        let mut result = vec![NONCE_PREFIX_SIZE as u8];
+        result.extend_from_slice(&prefix);
+        let chunks = data.chunks(SEGMENT_SIZE).collect::<Vec<_>>();
+        for (index, chunk) in chunks.iter().enumerate() {
+            result.extend(segments.seal(chunk, index == chunks.len() - 1).unwrap());
+        }
+        result
+    }
+
+    #[tokio::test]
+    async fn test_stream_encryption() {
+        let encryption_key = Encryption::generate_key().to_vec();
+        for len in [0, 1, SEGMENT_SIZE, 3 * SEGMENT_SIZE + 17] {
+            let data = (0..len).map(|i| i as u8).collect::<Vec<u8>>();
+            let ciphertext = encrypt_stream(&encryption_key, &data).await;
+
+            let segments = len.div_ceil(SEGMENT_SIZE).max(1);
+            assert_eq!(ciphertext.len(), Header::LEN + len + segments * TAG_SIZE);
+            assert_eq!(
+                decrypt_stream(&encryption_key, &ciphertext).await.unwrap(),
+                data
+            );
+            assert_eq!(
+                Encryption::decrypt(&encryption_key, &ciphertext).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_rejects_modified_ciphertext() {
+        let encryption_key = Encryption::generate_key().to_vec();
+        let data = vec![1u8; 2 * SEGMENT_SIZE + 5];
+        let ciphertext = encrypt_stream(&encryption_key, &data).await;
+
+        let mut flipped = ciphertext.clone();
+        flipped[Header::LEN + 3] ^= 1;
+        let truncated = &ciphertext[..Header::LEN + 2 * (SEGMENT_SIZE + TAG_SIZE)];
+
+        for ciphertext in [flipped.as_slice(), truncated] {
+            let err = decrypt_stream(&encryption_key, ciphertext)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(Encryption::decrypt(&encryption_key, ciphertext).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_is_authenticated_and_names_the_key() {
+        let encryption_key = Encryption::generate_key().to_vec();
+        let ciphertext = encrypt_stream(&encryption_key, b"hello world").await;
+
+        let header = Header::parse(&ciphertext).unwrap().unwrap();
+        assert_eq!(header.version, VERSION);
+        assert_eq!(header.algorithm, Algorithm::Aes256GcmStream);
+        assert_eq!(header.compression, Compression::None);
+        assert_eq!(header.key_id, KeyId::of(&encryption_key));
+
+        let other_key = Encryption::generate_key().to_vec();
+        assert!(matches!(
+            Encryption::decrypt(&other_key, &ciphertext),
+            Err(EncryptionError::WrongKey { found, .. }) if found == header.key_id
+        ));
+
+        // changing the header, even to another valid one, fails authentication
+        let mut modified = ciphertext.clone();
+        modified[Header::LEN - 1] ^= 1;
+        assert!(matches!(
+            Encryption::decrypt(&encryption_key, &modified),
+            Err(EncryptionError::Aead)
+        ));
+
+        let mut future = ciphertext.clone();
+        future[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            Encryption::decrypt(&encryption_key, &future),
+            Err(EncryptionError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_blobs_are_decrypted() {
+        let encryption_key = Encryption::generate_key().to_vec();
+        let data = vec![4u8; SEGMENT_SIZE + 100];
+
+        let one_shot = Encryption::encrypt_legacy(&encryption_key, &data).unwrap();
+        let stream = encrypt_legacy_stream(&encryption_key, &data);
+        for ciphertext in [one_shot, stream] {
+            assert_eq!(Header::parse(&ciphertext).unwrap(), None);
+            assert_eq!(
+                Encryption::decrypt(&encryption_key, &ciphertext).unwrap(),
+                data
+            );
+            assert_eq!(
+                decrypt_stream(&encryption_key, &ciphertext).await.unwrap(),
+                data
+            );
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, Key, KeyInit, OsRng, Payload},
+    Aes256Gcm,
+};
+use hkdf::Hkdf;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+use x25519_dalek::StaticSecret;
+use zeroize::Zeroizing;
+
+use super::{
+    convergent::Convergent,
+    encryption::{Encryption, EncryptionError, KeyId},
+    hex,
+};
+
+const MAGIC: &[u8; 4] = b"LIPK";
+const VERSION: u8 = 1;
+const KEY_STANZA: u8 = 1;
+const X25519_STANZA: u8 = 2;
+/// A wrapped 32 byte data key and its tag.
+const WRAPPED_LEN: usize = 32 + 16;
+const PUBLIC_KEY_PREFIX: &str = "x25519:";
+
+/// Leads a file whose payload is encrypted with its own data key, and holds that data key
+/// wrapped once for each key or recipient allowed to read the file, the way age wraps its
+/// file key in recipient stanzas. Sharing a file only rewrites the envelope.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Envelope {
+    stanzas: Vec<Stanza>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stanza {
+    /// Wrapped with a key from the keyring.
+    Key {
+        key_id: KeyId,
+        nonce: [u8; 12],
+        wrapped: [u8; WRAPPED_LEN],
+    },
+    /// Wrapped with a key agreed between an ephemeral key and the recipient's public key.
+    X25519 {
+        recipient: KeyId,
+        ephemeral: [u8; 32],
+        wrapped: [u8; WRAPPED_LEN],
+    },
+}
+
+/// The X25519 secret others wrap data keys for.
+pub struct Identity(StaticSecret);
+
+/// An identity's public key, written as `x25519:` and 64 hex digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey([u8; 32]);
+
+impl Envelope {
+    /// Generates the data key for a new file.
+    pub fn generate_data_key() -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(Encryption::generate_key().to_vec())
+    }
+
+    /// Splits an envelope from the payload after it, or returns `None` for files encrypted
+    /// directly with a key.
+    pub fn parse(data: &[u8]) -> Result<Option<(Self, &[u8])>, EncryptionError> {
+        if !data.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        let mut rest = &data[MAGIC.len()..];
+        match take::<2>(&mut rest)? {
+            [VERSION, count] => {
+                let mut stanzas = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let [kind] = take(&mut rest)?;
+                    let stanza = match kind {
+                        KEY_STANZA => Stanza::Key {
+                            key_id: KeyId::from_bytes(take(&mut rest)?),
+                            nonce: take(&mut rest)?,
+                            wrapped: take(&mut rest)?,
+                        },
+                        X25519_STANZA => Stanza::X25519 {
+                            recipient: KeyId::from_bytes(take(&mut rest)?),
+                            ephemeral: take(&mut rest)?,
+                            wrapped: take(&mut rest)?,
+                        },
+                        _ => return Err(EncryptionError::Unrecognized),
+                    };
+                    stanzas.push(stanza);
+                }
+                Ok(Some((Self { stanzas }, rest)))
+            }
+            [version, _] => Err(EncryptionError::UnsupportedVersion(version)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[VERSION, self.stanzas.len() as u8]);
+        for stanza in &self.stanzas {
+            match stanza {
+                Stanza::Key {
+                    key_id,
+                    nonce,
+                    wrapped,
+                } => {
+                    bytes.push(KEY_STANZA);
+                    bytes.extend_from_slice(key_id.as_bytes());
+                    bytes.extend_from_slice(nonce);
+                    bytes.extend_from_slice(wrapped);
+                }
+                Stanza::X25519 {
+                    recipient,
+                    ephemeral,
+                    wrapped,
+                } => {
+                    bytes.push(X25519_STANZA);
+                    bytes.extend_from_slice(recipient.as_bytes());
+                    bytes.extend_from_slice(ephemeral);
+                    bytes.extend_from_slice(wrapped);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Ids of the keyring keys the data key is wrapped for.
+    pub fn key_ids(&self) -> impl Iterator<Item = KeyId> + '_ {
+        self.stanzas.iter().filter_map(|stanza| match stanza {
+            Stanza::Key { key_id, .. } => Some(*key_id),
+            Stanza::X25519 { .. } => None,
+        })
+    }
+
+    pub fn has_recipient(&self, recipient: &PublicKey) -> bool {
+        let id = recipient.id();
+        self.stanzas.iter().any(|stanza| match stanza {
+            Stanza::X25519 { recipient, .. } => *recipient == id,
+            Stanza::Key { .. } => false,
+        })
+    }
+
+    /// Wraps `data_key` with `encryption_key`, replacing the keyring keys it was wrapped for.
+    pub fn wrap_for_key(
+        &mut self,
+        data_key: &[u8],
+        encryption_key: &[u8],
+    ) -> Result<(), EncryptionError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        self.insert_key_stanza(data_key, encryption_key, nonce.into())
+    }
+
+    /// Wraps `data_key` for `recipient`, unless it already is.
+    pub fn wrap_for_recipient(
+        &mut self,
+        data_key: &[u8],
+        recipient: &PublicKey,
+    ) -> Result<(), EncryptionError> {
+        self.push_recipient_stanza(data_key, recipient, StaticSecret::random_from_rng(OsRng))
+    }
+
+    /// Wraps the data key of `convergent` for `encryption_key` and `recipients` with nonces
+    /// and ephemeral keys derived from the file, so adding the same file for the same
+    /// recipients writes the same envelope.
+    pub fn convergent(
+        convergent: &Convergent,
+        encryption_key: &[u8],
+        recipients: &[PublicKey],
+    ) -> Result<Self, EncryptionError> {
+        let data_key = convergent.data_key();
+        let mut envelope = Self::default();
+        let nonce = convergent.key_nonce(&KeyId::of(encryption_key));
+        envelope.insert_key_stanza(&data_key, encryption_key, nonce)?;
+        for recipient in recipients {
+            let secret = StaticSecret::from(*convergent.ephemeral_secret(recipient));
+            envelope.push_recipient_stanza(&data_key, recipient, secret)?;
+        }
+
+        Ok(envelope)
+    }
+
+    fn insert_key_stanza(
+        &mut self,
+        data_key: &[u8],
+        encryption_key: &[u8],
+        nonce: [u8; 12],
+    ) -> Result<(), EncryptionError> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key));
+        let wrapped = wrap(&cipher, &nonce, data_key)?;
+
+        self.stanzas
+            .retain(|stanza| matches!(stanza, Stanza::X25519 { .. }));
+        self.stanzas.insert(
+            0,
+            Stanza::Key {
+                key_id: KeyId::of(encryption_key),
+                nonce,
+                wrapped,
+            },
+        );
+        Ok(())
+    }
+
+    fn push_recipient_stanza(
+        &mut self,
+        data_key: &[u8],
+        recipient: &PublicKey,
+        secret: StaticSecret,
+    ) -> Result<(), EncryptionError> {
+        if self.has_recipient(recipient) {
+            return Ok(());
+        }
+        if self.stanzas.len() == u8::MAX as usize {
+            return Err(EncryptionError::TooLong);
+        }
+
+        let ephemeral = x25519_dalek::PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(recipient.0));
+        if !shared.was_contributory() {
+            return Err(EncryptionError::InvalidPublicKey);
+        }
+        let cipher = recipient_cipher(shared.as_bytes(), ephemeral.as_bytes(), &recipient.0);
+
+        self.stanzas.push(Stanza::X25519 {
+            recipient: recipient.id(),
+            ephemeral: ephemeral.to_bytes(),
+            wrapped: wrap(&cipher, &[0; 12], data_key)?,
+        });
+        Ok(())
+    }
+
+    /// Unwraps the data key with a keyring key, if it is wrapped for that key.
+    pub fn unwrap_with_key(
+        &self,
+        encryption_key: &[u8],
+    ) -> Result<Option<Zeroizing<Vec<u8>>>, EncryptionError> {
+        let id = KeyId::of(encryption_key);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(encryption_key));
+        for stanza in &self.stanzas {
+            if let Stanza::Key {
+                key_id,
+                nonce,
+                wrapped,
+            } = stanza
+            {
+                if *key_id == id {
+                    return unwrap(&cipher, nonce, wrapped).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Unwraps the data key with `identity`, if it is wrapped for its public key.
+    pub fn unwrap_with_identity(
+        &self,
+        identity: &Identity,
+    ) -> Result<Option<Zeroizing<Vec<u8>>>, EncryptionError> {
+        let public_key = identity.public_key();
+        for stanza in &self.stanzas {
+            if let Stanza::X25519 {
+                recipient,
+                ephemeral,
+                wrapped,
+            } = stanza
+            {
+                if *recipient == public_key.id() {
+                    let shared = identity
+                        .0
+                        .diffie_hellman(&x25519_dalek::PublicKey::from(*ephemeral));
+                    let cipher = recipient_cipher(shared.as_bytes(), ephemeral, &public_key.0);
+                    return unwrap(&cipher, &[0; 12], wrapped).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes = <[u8; 32]>::try_from(bytes).ok()?;
+        Some(Self(StaticSecret::from(bytes)))
+    }
+
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.0.to_bytes().to_vec())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(x25519_dalek::PublicKey::from(&self.0).to_bytes())
+    }
+}
+
+impl PublicKey {
+    pub fn id(&self) -> KeyId {
+        KeyId::of(&self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PUBLIC_KEY_PREFIX, hex::encode(&self.0))
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .strip_prefix(PUBLIC_KEY_PREFIX)
+            .and_then(hex::decode)
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(Self)
+            .ok_or_else(|| format!("{} is not an x25519 public key", value))
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Every ephemeral key wraps a single data key, so the derived key is used with one nonce.
+fn recipient_cipher(shared: &[u8], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> Aes256Gcm {
+    let salt = [ephemeral.as_slice(), recipient.as_slice()].concat();
+    let mut key = Zeroizing::new([0; 32]);
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(b"local-ipfs x25519", key.as_mut())
+        .expect("32 bytes is a valid hkdf output length");
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_slice()))
+}
+
+fn wrap(
+    cipher: &Aes256Gcm,
+    nonce: &[u8; 12],
+    data_key: &[u8],
+) -> Result<[u8; WRAPPED_LEN], EncryptionError> {
+    let payload = Payload {
+        msg: data_key,
+        aad: MAGIC,
+    };
+    let wrapped = cipher.encrypt(nonce.into(), payload)?;
+    wrapped.try_into().map_err(|_| EncryptionError::Aead)
+}
+
+fn unwrap(
+    cipher: &Aes256Gcm,
+    nonce: &[u8; 12],
+    wrapped: &[u8; WRAPPED_LEN],
+) -> Result<Zeroizing<Vec<u8>>, EncryptionError> {
+    let payload = Payload {
+        msg: wrapped,
+        aad: MAGIC,
+    };
+    Ok(Zeroizing::new(cipher.decrypt(nonce.into(), payload)?))
+}
+
+fn take<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], EncryptionError> {
+    if data.len() < N {
+        return Err(EncryptionError::Truncated);
+    }
+    let (bytes, rest) = data.split_at(N);
+    *data = rest;
+    Ok(bytes.try_into().expect("split at N"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn data_keys_are_unwrapped_by_keys_and_recipients() {
+        let data_key = Envelope::generate_data_key();
+        let owner_key = Encryption::generate_key().to_vec();
+        let teammate = Identity::generate();
+        let outsider = Identity::generate();
+
+        let mut envelope = Envelope::default();
+        envelope.wrap_for_key(&data_key, &owner_key).unwrap();
+        envelope
+            .wrap_for_recipient(&data_key, &teammate.public_key())
+            .unwrap();
+        envelope
+            .wrap_for_recipient(&data_key, &teammate.public_key())
+            .unwrap();
+
+        let mut data = envelope.to_bytes();
+        data.extend_from_slice(b"payload");
+        let (parsed, payload) = Envelope::parse(&data).unwrap().unwrap();
+        assert_eq!(parsed, envelope);
+        assert_eq!(parsed.stanzas.len(), 2);
+        assert_eq!(payload, b"payload");
+
+        assert_eq!(
+            parsed.unwrap_with_key(&owner_key).unwrap().unwrap(),
+            data_key
+        );
+        assert_eq!(
+            parsed.unwrap_with_identity(&teammate).unwrap().unwrap(),
+            data_key
+        );
+        assert!(parsed.unwrap_with_identity(&outsider).unwrap().is_none());
+        let other_key = Encryption::generate_key().to_vec();
+        assert!(parsed.unwrap_with_key(&other_key).unwrap().is_none());
+
+        let mut rotated = parsed.clone();
+        rotated.wrap_for_key(&data_key, &other_key).unwrap();
+        assert!(rotated.unwrap_with_key(&owner_key).unwrap().is_none());
+        assert!(rotated.has_recipient(&teammate.public_key()));
+
+        assert!(matches!(
+            Envelope::parse(&data[..20]),
+            Err(EncryptionError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn public_keys_round_trip_through_text() {
+        let public_key = Identity::generate().public_key();
+        let text = public_key.to_string();
+        assert!(text.starts_with(PUBLIC_KEY_PREFIX));
+        assert_eq!(text.parse::<PublicKey>().unwrap(), public_key);
+        assert!(text[PUBLIC_KEY_PREFIX.len()..]
+            .parse::<PublicKey>()
+            .is_err());
+    }
+}
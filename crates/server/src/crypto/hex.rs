@@ -0,0 +1,27 @@
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::encryption::Encryption;
+
+    #[test]
+    fn keys_round_trip_through_hex() {
+        let key = Encryption::generate_key().to_vec();
+        assert_eq!(decode(&encode(&key)).unwrap(), key);
+        assert!(decode("abc").is_none());
+        assert!(decode("zz").is_none());
+    }
+}
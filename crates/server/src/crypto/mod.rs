@@ -0,0 +1,9 @@
+//! The format the cli encrypts files in: a header naming the key, the payload sealed in
+//! segments and, for shared files, an envelope of wrapped data keys. The gateway decrypts
+//! with the same code.
+
+pub mod compression;
+pub mod convergent;
+pub mod encryption;
+pub mod envelope;
+pub mod hex;
@@ -0,0 +1,658 @@
+//! An http gateway serving `/ipfs/<cid>[/<path>]` from the ipfs backend, so content can be
+//! opened in a browser. Files encrypted by the cli are decrypted when the request presents
+//! a token the server was configured with a key for, as an `Authorization: Bearer <token>`
+//! header or a `gateway_token` cookie. Tokens are never taken from the url, where they would
+//! end up in logs, browser history and referers.
+
+use bytes::Bytes;
+use cid::Cid;
+use http_body_util::Full;
+use hyper::{
+    header::{self, HeaderValue},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use std::{collections::HashMap, convert::Infallible, ops::Range, sync::Arc};
+use tokio::{io::AsyncReadExt, net::TcpListener};
+use tracing::{debug, error};
+
+use crate::{
+    api::types::ipfs::IpfsLinkType,
+    backend::{unixfs, BackendError, IpfsBackend},
+    crypto::{
+        encryption::{DecryptReader, KEY_SIZE},
+        envelope::Envelope,
+    },
+};
+
+/// Largest decrypted file served unless configured otherwise.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Gateway<B> {
+    backend: B,
+    keys: Arc<HashMap<String, Vec<u8>>>,
+    max_file_size: u64,
+}
+
+impl<B: IpfsBackend> Gateway<B> {
+    /// `keys` maps the secret tokens requests may present to the keys they decrypt with.
+    /// Anyone who can reach the gateway and knows a token can read what it decrypts.
+    pub fn new(backend: B, keys: HashMap<String, Vec<u8>>) -> Self {
+        Self {
+            backend,
+            keys: Arc::new(keys),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+
+    /// Refuses to decrypt files that come out larger than `max_file_size`, which compressed
+    /// files can whatever their stored size.
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    error!("Error accepting gateway connection: {}", err);
+                    continue;
+                }
+            };
+
+            let gateway = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| {
+                    let gateway = gateway.clone();
+                    async move { Ok::<_, Infallible>(gateway.respond(&request).await) }
+                });
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    debug!("Gateway connection closed: {}", err);
+                }
+            });
+        }
+    }
+
+    pub async fn respond<T>(&self, request: &Request<T>) -> Response<Full<Bytes>> {
+        let mut response = match self.handle(request).await {
+            Ok(response) => response,
+            Err(err) => err.into_response(),
+        };
+        if request.method() == Method::HEAD {
+            *response.body_mut() = Full::default();
+        }
+        response
+    }
+
+    async fn handle<T>(&self, request: &Request<T>) -> Result<Response<Full<Bytes>>, GatewayError> {
+        if request.method() != Method::GET && request.method() != Method::HEAD {
+            return Err(GatewayError::MethodNotAllowed);
+        }
+
+        let path =
+            request.uri().path().strip_prefix("/ipfs/").ok_or_else(|| {
+                GatewayError::NotFound("Only /ipfs/<cid> paths are served".into())
+            })?;
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+        let root = components
+            .next()
+            .ok_or_else(|| GatewayError::BadRequest("Missing cid".into()))?;
+        let root = Cid::try_from(root)
+            .map_err(|err| GatewayError::BadRequest(format!("Invalid cid {}: {}", root, err)))?;
+        let names = components
+            .map(|c| {
+                percent_decode(c).ok_or_else(|| {
+                    GatewayError::BadRequest(format!("Invalid path component {}", c))
+                })
+            })
+            .collect::<Result<Vec<String>, GatewayError>>()?;
+
+        let token = request_token(request);
+        let key = match &token {
+            Some(token) => Some(
+                self.keys
+                    .get(token)
+                    .ok_or(GatewayError::Forbidden("Unknown token"))?,
+            ),
+            None => None,
+        };
+
+        let cid = self.resolve(root, &names).await?;
+        let hash = cid.to_string();
+        let block = self.backend.block_get(&hash).await?;
+        if unixfs::node_info(&cid, &block)?.is_directory {
+            return self.listing(request.uri().path(), &hash).await;
+        }
+
+        let mut data = self.backend.cat(&hash).await?;
+        if let Some(key) = key {
+            data = decrypt(key, &data, self.max_file_size).await?;
+        }
+
+        let content_type = names
+            .last()
+            .and_then(|name| mime_guess::from_path(name).first_raw())
+            .unwrap_or_else(|| sniff(&data));
+        let mut response = Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(header::ETAG, format!("\"{}\"", hash));
+        if key.is_some() {
+            response = response.header(header::CACHE_CONTROL, "no-store");
+        }
+
+        let range = request
+            .headers()
+            .get(header::RANGE)
+            .and_then(|range| range.to_str().ok());
+        let response = match range.map(|range| parse_range(range, data.len())) {
+            None | Some(RangeRequest::Ignored) => response.body(Full::new(data)),
+            Some(RangeRequest::Unsatisfiable) => response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", data.len()))
+                .body(Full::default()),
+            Some(RangeRequest::Range(range)) => response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, data.len()),
+                )
+                .body(Full::new(data.slice(range))),
+        };
+
+        response.map_err(|err| GatewayError::Internal(err.to_string()))
+    }
+
+    /// Follows `names` down the directories below `root`.
+    async fn resolve(&self, root: Cid, names: &[String]) -> Result<Cid, GatewayError> {
+        let mut cid = root;
+        for name in names {
+            let link = self
+                .backend
+                .ls(&cid.to_string())
+                .await?
+                .links
+                .into_iter()
+                .find(|link| &link.name == name)
+                .ok_or_else(|| GatewayError::NotFound(format!("No link named {}", name)))?;
+            cid = Cid::try_from(link.hash.as_str()).map_err(BackendError::from)?;
+        }
+        Ok(cid)
+    }
+
+    async fn listing(&self, path: &str, hash: &str) -> Result<Response<Full<Bytes>>, GatewayError> {
+        let base = path.trim_end_matches('/');
+
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+             <body>\n<h1>{0}</h1>\n<ul>\n",
+            escape_html(base)
+        );
+        for link in self.backend.ls(hash).await?.links {
+            let (name, size) = match link.link_type {
+                IpfsLinkType::Directory | IpfsLinkType::HamtShard => {
+                    (format!("{}/", link.name), String::new())
+                }
+                _ => (link.name.clone(), format!(" ({} bytes)", link.size)),
+            };
+            html.push_str(&format!(
+                "<li><a href=\"{}/{}\">{}</a>{}</li>\n",
+                escape_html(base),
+                percent_encode(&link.name),
+                escape_html(&name),
+                size
+            ));
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+
+        Response::builder()
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::ETAG, format!("\"{}\"", hash))
+            .body(Full::new(Bytes::from(html)))
+            .map_err(|err| GatewayError::Internal(err.to_string()))
+    }
+}
+
+/// The token a request presents, from its bearer authorization or its `gateway_token` cookie.
+fn request_token<T>(request: &Request<T>) -> Option<String> {
+    let headers = request.headers();
+    if let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(token.trim().to_string());
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix("gateway_token="))
+        .and_then(percent_decode)
+}
+
+/// Reverses the cli's encryption. The stored file is the ciphertext as a json array of
+/// bytes, which shared files put behind an envelope holding their wrapped data key. The
+/// plaintext is decompressed as it is read, up to `max_size` bytes.
+async fn decrypt(key: &[u8], data: &[u8], max_size: u64) -> Result<Bytes, GatewayError> {
+    const UNDECRYPTABLE: GatewayError = GatewayError::Forbidden("Unable to decrypt");
+    if key.len() != KEY_SIZE {
+        return Err(UNDECRYPTABLE);
+    }
+    let data = serde_json::from_slice::<Vec<u8>>(data).map_err(|_| UNDECRYPTABLE)?;
+    let data_key;
+    let (key, payload) = match Envelope::parse(&data).map_err(|_| UNDECRYPTABLE)? {
+        Some((envelope, payload)) => {
+            data_key = envelope
+                .unwrap_with_key(key)
+                .ok()
+                .flatten()
+                .ok_or(UNDECRYPTABLE)?;
+            (data_key.as_slice(), payload)
+        }
+        None => (key, data.as_slice()),
+    };
+
+    let mut plaintext = Vec::new();
+    DecryptReader::new(key, payload)
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut plaintext)
+        .await
+        .map_err(|_| UNDECRYPTABLE)?;
+    if plaintext.len() as u64 > max_size {
+        return Err(GatewayError::TooLarge(max_size));
+    }
+    Ok(Bytes::from(plaintext))
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    Range(Range<usize>),
+    Unsatisfiable,
+    /// Malformed or multipart ranges, answered with the whole file.
+    Ignored,
+}
+
+fn parse_range(range: &str, len: usize) -> RangeRequest {
+    let Some(range) = range.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Ignored;
+    };
+    let Some((start, end)) = range.split_once('-') else {
+        return RangeRequest::Ignored;
+    };
+    if range.contains(',') {
+        return RangeRequest::Ignored;
+    }
+
+    let parse = |value: &str| value.trim().parse::<usize>().ok();
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => match parse(suffix) {
+            Some(0) => return RangeRequest::Unsatisfiable,
+            Some(suffix) => len.saturating_sub(suffix)..len,
+            None => return RangeRequest::Ignored,
+        },
+        (start, "") => match parse(start) {
+            Some(start) => start..len,
+            None => return RangeRequest::Ignored,
+        },
+        (start, end) => match (parse(start), parse(end)) {
+            (Some(start), Some(end)) if start <= end => start..len.min(end.saturating_add(1)),
+            _ => return RangeRequest::Ignored,
+        },
+    };
+
+    if range.start >= len {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Range(range)
+    }
+}
+
+/// Guesses the type of content without a file name from its leading bytes.
+fn sniff(data: &[u8]) -> &'static str {
+    const SIGNATURES: [(&[u8], &str); 8] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x00\x00\x00\x18ftypmp4", "video/mp4"),
+    ];
+    if let Some((_, content_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+    {
+        return content_type;
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+
+    let head = &data[..data.len().min(512)];
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // the head may end part way through a character
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return "application/octet-stream",
+    };
+    if text.contains('\0') {
+        return "application/octet-stream";
+    }
+    let start = text.trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[derive(Debug, thiserror::Error)]
+enum GatewayError {
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    Forbidden(&'static str),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("Method not allowed")]
+    MethodNotAllowed,
+
+    #[error("Decrypted file is larger than the gateway's limit of {0} bytes")]
+    TooLarge(u64),
+
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl GatewayError {
+    fn into_response(self) -> Response<Full<Bytes>> {
+        let status = match &self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) | Self::Backend(BackendError::NotFound(_)) => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Backend(_) => StatusCode::BAD_GATEWAY,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let mut response = Response::new(Full::new(Bytes::from(format!("{}\n", self))));
+        *response.status_mut() = status;
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        if status == StatusCode::METHOD_NOT_ALLOWED {
+            response
+                .headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::types::{ipfs::CidVersion, mfs::MfsPath},
+        backend::memory::MemoryBackend,
+        crypto::{
+            compression::CompressReader,
+            encryption::{Compression, EncryptWriter, Encryption},
+            envelope::Identity,
+        },
+    };
+    use futures::executor::block_on;
+    use http_body_util::BodyExt;
+    use tokio::io::AsyncWriteExt;
+
+    fn get(gateway: &Gateway<MemoryBackend>, uri: &str, range: Option<&str>) -> Response<Bytes> {
+        let mut request = Request::get(uri);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        send(gateway, request)
+    }
+
+    fn get_with_token(gateway: &Gateway<MemoryBackend>, uri: &str, token: &str) -> Response<Bytes> {
+        let request = Request::get(uri).header(header::AUTHORIZATION, format!("Bearer {}", token));
+        send(gateway, request)
+    }
+
+    fn send(
+        gateway: &Gateway<MemoryBackend>,
+        request: hyper::http::request::Builder,
+    ) -> Response<Bytes> {
+        let response = block_on(gateway.respond(&request.body(()).unwrap()));
+        let (parts, body) = response.into_parts();
+        Response::from_parts(parts, block_on(body.collect()).unwrap().to_bytes())
+    }
+
+    #[test]
+    fn ranges_are_parsed() {
+        assert_eq!(parse_range("bytes=0-4", 10), RangeRequest::Range(0..5));
+        assert_eq!(parse_range("bytes=5-", 10), RangeRequest::Range(5..10));
+        assert_eq!(parse_range("bytes=-3", 10), RangeRequest::Range(7..10));
+        assert_eq!(parse_range("bytes=8-20", 10), RangeRequest::Range(8..10));
+        assert_eq!(
+            parse_range("bytes=1-18446744073709551615", 10),
+            RangeRequest::Range(1..10)
+        );
+        assert_eq!(parse_range("bytes=10-", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), RangeRequest::Ignored);
+        assert_eq!(parse_range("items=0-1", 10), RangeRequest::Ignored);
+    }
+
+    #[test]
+    fn content_types_are_sniffed() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff(b"  <!DOCTYPE html><p>"), "text/html; charset=utf-8");
+        assert_eq!(sniff("héllo".as_bytes()), "text/plain; charset=utf-8");
+        assert_eq!(sniff(&[0, 159, 146, 150]), "application/octet-stream");
+    }
+
+    #[test]
+    fn files_and_directories_are_served() {
+        let backend = MemoryBackend::new();
+        let gateway = Gateway::new(backend.clone(), HashMap::new());
+
+        let root = block_on(async {
+            backend
+                .files_mkdir(&"/site".parse().unwrap(), false)
+                .await
+                .unwrap();
+            backend
+                .files_write(
+                    &"/site/notes.txt".parse().unwrap(),
+                    b"hello gateway".to_vec(),
+                    true,
+                )
+                .await
+                .unwrap();
+            backend.files_stat(&MfsPath::root()).await.unwrap().hash
+        });
+
+        let listing = get(&gateway, &format!("/ipfs/{}/site", root), None);
+        assert_eq!(listing.status(), StatusCode::OK);
+        let html = String::from_utf8(listing.body().to_vec()).unwrap();
+        assert!(html.contains(&format!("href=\"/ipfs/{}/site/notes.txt\"", root)));
+
+        let file = get(&gateway, &format!("/ipfs/{}/site/notes.txt", root), None);
+        assert_eq!(file.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(file.body(), "hello gateway");
+
+        let partial = get(
+            &gateway,
+            &format!("/ipfs/{}/site/notes.txt", root),
+            Some("bytes=6-"),
+        );
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(partial.headers()[header::CONTENT_RANGE], "bytes 6-12/13");
+        assert_eq!(partial.body(), "gateway");
+
+        let missing = get(&gateway, &format!("/ipfs/{}/site/other", root), None);
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            get(&gateway, "/ipfs/not-a-cid", None).status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn encrypted_files_are_decrypted_with_configured_keys() {
+        let key = Encryption::generate_key().to_vec();
+        let backend = MemoryBackend::new();
+        let gateway = Gateway::new(
+            backend.clone(),
+            HashMap::from([("docs".into(), key.clone())]),
+        );
+        let add = |ciphertext: &[u8]| {
+            let stored = serde_json::to_vec(ciphertext).unwrap();
+            let hash = block_on(backend.add(stored.clone(), CidVersion::V1))
+                .unwrap()
+                .hash;
+            (hash, stored)
+        };
+
+        let (hash, stored) =
+            add(&Encryption::encrypt_legacy(&key, b"<html>secret</html>").unwrap());
+        let encrypted = get(&gateway, &format!("/ipfs/{}", hash), None);
+        assert_eq!(encrypted.body(), &stored);
+
+        let decrypted = get_with_token(&gateway, &format!("/ipfs/{}", hash), "docs");
+        assert_eq!(decrypted.body(), "<html>secret</html>");
+        assert_eq!(
+            decrypted.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(decrypted.headers()[header::CACHE_CONTROL], "no-store");
+
+        let unknown = get_with_token(&gateway, &format!("/ipfs/{}", hash), "other");
+        assert_eq!(unknown.status(), StatusCode::FORBIDDEN);
+
+        let from_cookie = send(
+            &gateway,
+            Request::get(format!("/ipfs/{}", hash))
+                .header(header::COOKIE, "theme=dark; gateway_token=docs"),
+        );
+        assert_eq!(from_cookie.body(), "<html>secret</html>");
+        // tokens in the url would leak through logs and referers
+        let from_query = get(&gateway, &format!("/ipfs/{}?token=docs", hash), None);
+        assert_eq!(from_query.body(), &stored);
+
+        let (hash, _) = add(&encrypt(&key, Compression::None, vec![], b"streamed"));
+        let streamed = get_with_token(&gateway, &format!("/ipfs/{}", hash), "docs");
+        assert_eq!(streamed.body(), "streamed");
+
+        // a stream under its own data key, behind an envelope wrapping that data key for an
+        // x25519 recipient and for the configured key
+        let data_key = Envelope::generate_data_key();
+        let mut envelope = Envelope::default();
+        envelope
+            .wrap_for_recipient(&data_key, &Identity::generate().public_key())
+            .unwrap();
+        envelope.wrap_for_key(&data_key, &key).unwrap();
+        let shared = encrypt(&data_key, Compression::None, envelope.to_bytes(), b"shared");
+        let (hash, _) = add(&shared);
+        let opened = get_with_token(&gateway, &format!("/ipfs/{}", hash), "docs");
+        assert_eq!(opened.body(), "shared");
+
+        for compression in [Compression::Zstd, Compression::Gzip] {
+            let (hash, _) = add(&encrypt(&key, compression, vec![], b"compressed"));
+            let decompressed = get_with_token(&gateway, &format!("/ipfs/{}", hash), "docs");
+            assert_eq!(decompressed.body(), "compressed");
+        }
+    }
+
+    #[test]
+    fn decompression_stops_at_the_size_limit() {
+        let key = Encryption::generate_key().to_vec();
+        let backend = MemoryBackend::new();
+        let gateway = Gateway::new(
+            backend.clone(),
+            HashMap::from([("docs".into(), key.clone())]),
+        )
+        .with_max_file_size(1024 * 1024);
+
+        for (len, status) in [
+            (1024 * 1024, StatusCode::OK),
+            (1024 * 1024 + 1, StatusCode::PAYLOAD_TOO_LARGE),
+        ] {
+            let ciphertext = encrypt(&key, Compression::Zstd, vec![], &vec![0; len]);
+            assert!(ciphertext.len() < 1024);
+            let stored = serde_json::to_vec(&ciphertext).unwrap();
+            let hash = block_on(backend.add(stored, CidVersion::V1)).unwrap().hash;
+            let response = get_with_token(&gateway, &format!("/ipfs/{}", hash), "docs");
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    /// Encrypts `plaintext` the way the cli adds files, after `prefix`.
+    fn encrypt(key: &[u8], compression: Compression, prefix: Vec<u8>, plaintext: &[u8]) -> Vec<u8> {
+        block_on(async {
+            let mut reader = CompressReader::new(compression, plaintext);
+            let mut writer = EncryptWriter::new(key, prefix).compressed(compression);
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+            writer.shutdown().await.unwrap();
+            writer.into_inner()
+        })
+    }
+}
@@ -1,7 +1,11 @@
 pub mod api;
+pub mod backend;
 pub mod cli;
 mod commands;
+pub mod crypto;
+pub mod gateway;
 pub mod network;
+pub mod placement;
 pub mod rpc;
 pub mod server;
 pub mod state;
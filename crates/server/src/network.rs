@@ -1,17 +1,23 @@
 use std::{
+    collections::HashMap,
     hash::{DefaultHasher, Hash, Hasher},
     io,
     str::FromStr,
+    sync::Arc,
     time::Duration,
 };
 
+use bytes::Bytes;
+use cid::Cid;
 use futures::{future::BoxFuture, StreamExt};
 use libp2p::{
     gossipsub, identify, kad,
     multiaddr::Protocol,
+    request_response::{self, OutboundRequestId, ProtocolSupport, ResponseChannel},
     swarm::{NetworkBehaviour, SwarmEvent},
-    Multiaddr, PeerId, Swarm,
+    Multiaddr, PeerId, StreamProtocol, Swarm,
 };
+use serde::{Deserialize, Serialize};
 
 use tokio::{
     select,
@@ -22,7 +28,12 @@ use tokio::{
 use tracing::{error, info, warn, Instrument, Span};
 
 type GossipMessage = Vec<u8>;
-pub type GossipCallBackFn = Box<dyn for<'a> Fn(&'a [u8]) -> BoxFuture<'a, ()> + Send + Sync>;
+pub type GossipCallBackFn =
+    Box<dyn for<'a> Fn(&'a [u8], &'a NetworkClient) -> BoxFuture<'a, ()> + Send + Sync>;
+/// Looks up a block requested by a peer in the local block store.
+pub type BlockProviderFn = Arc<dyn Fn(Cid) -> BoxFuture<'static, Option<Bytes>> + Send + Sync>;
+
+const BLOCK_PROTOCOL: &str = "/local_ipfs/blocks/1.0.0";
 pub struct NoP;
 pub struct NoB;
 pub struct NoA;
@@ -33,6 +44,7 @@ pub struct NetworkBuilder<P, B, A, T> {
     is_boot_node: B,
     boot_addr: A,
     topic: T,
+    block_provider: Option<BlockProviderFn>,
 }
 
 pub struct Network {
@@ -41,6 +53,7 @@ pub struct Network {
     is_boot_node: bool,
     boot_addr: String,
     topic: String,
+    block_provider: Option<BlockProviderFn>,
 }
 
 #[derive(Clone)]
@@ -58,6 +71,7 @@ impl NetworkBuilder<NoP, NoB, NoA, NoT> {
             is_boot_node: NoB,
             boot_addr: NoA,
             topic: NoT,
+            block_provider: None,
         }
     }
 }
@@ -75,6 +89,7 @@ impl<P, B, A, T> NetworkBuilder<P, B, A, T> {
             is_boot_node: self.is_boot_node,
             boot_addr: self.boot_addr,
             topic: self.topic,
+            block_provider: self.block_provider,
         }
     }
 
@@ -84,6 +99,7 @@ impl<P, B, A, T> NetworkBuilder<P, B, A, T> {
             is_boot_node,
             boot_addr: self.boot_addr,
             topic: self.topic,
+            block_provider: self.block_provider,
         }
     }
 
@@ -93,6 +109,7 @@ impl<P, B, A, T> NetworkBuilder<P, B, A, T> {
             is_boot_node: self.is_boot_node,
             boot_addr: boot_addr.into(),
             topic: self.topic,
+            block_provider: self.block_provider,
         }
     }
 
@@ -102,8 +119,15 @@ impl<P, B, A, T> NetworkBuilder<P, B, A, T> {
             is_boot_node: self.is_boot_node,
             boot_addr: self.boot_addr,
             topic: topic.into(),
+            block_provider: self.block_provider,
         }
     }
+
+    /// Serves blocks to peers that request them over the block exchange protocol.
+    pub fn with_block_provider(mut self, block_provider: BlockProviderFn) -> Self {
+        self.block_provider = Some(block_provider);
+        self
+    }
 }
 
 impl NetworkBuilder<String, bool, String, String> {
@@ -127,7 +151,7 @@ impl NetworkBuilder<String, bool, String, String> {
                     .validation_mode(gossipsub::ValidationMode::Strict)
                     .message_id_fn(message_id_fn)
                     .build()
-                    .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?;
+                    .map_err(io::Error::other)?;
 
                 let gossipsub = gossipsub::Behaviour::new(
                     gossipsub::MessageAuthenticity::Signed(key.clone()),
@@ -145,10 +169,17 @@ impl NetworkBuilder<String, bool, String, String> {
                     identify::Config::new("/local_ipfs/id/0.0.0".into(), public_key);
                 let identify = identify::Behaviour::new(identify_config);
 
+                let block_exchange = request_response::cbor::Behaviour::new(
+                    [(StreamProtocol::new(BLOCK_PROTOCOL), ProtocolSupport::Full)],
+                    request_response::Config::default()
+                        .with_request_timeout(Duration::from_secs(4)),
+                );
+
                 Ok(Behavior {
                     gossipsub,
                     kademlia,
                     identify,
+                    block_exchange,
                 })
             })
             .map_err(|err| NetworkError::Behavior(err.to_string()))?
@@ -163,6 +194,7 @@ impl NetworkBuilder<String, bool, String, String> {
             is_boot_node: self.is_boot_node,
             boot_addr: self.boot_addr,
             topic: self.topic,
+            block_provider: self.block_provider,
         })
     }
 }
@@ -202,6 +234,17 @@ impl NetworkClient {
         Ok(peer_id)
     }
 
+    /// Peers that can hold content: the local node and every connected peer except the
+    /// boot node.
+    pub async fn get_cluster_peers(&self) -> Result<Vec<PeerId>, NetworkError> {
+        let payload = ClientRequestPayload::ClusterPeers;
+        let ClientResponse::ClusterPeers { peers } = self.send_request(payload).await? else {
+            return Err(NetworkError::UnexpectedResponse);
+        };
+
+        Ok(peers)
+    }
+
     pub async fn get_connected_peers(&self) -> Result<Vec<PeerId>, NetworkError> {
         let payload = ClientRequestPayload::ConnectedPeers;
         let ClientResponse::ConnectedPeers { peers } = self.send_request(payload).await? else {
@@ -211,6 +254,20 @@ impl NetworkClient {
         Ok(peers)
     }
 
+    /// Asks `peer_id` for the block `cid`. Returns `None` when the peer does not have it.
+    pub async fn request_block(
+        &self,
+        peer_id: PeerId,
+        cid: Cid,
+    ) -> Result<Option<Bytes>, NetworkError> {
+        let payload = ClientRequestPayload::RequestBlock { peer_id, cid };
+        let ClientResponse::RequestBlock { block } = self.send_request(payload).await? else {
+            return Err(NetworkError::UnexpectedResponse);
+        };
+
+        Ok(block)
+    }
+
     pub async fn gossip_receiver(&self) -> broadcast::Receiver<GossipMessage> {
         self.gossip_msg_tx.subscribe()
     }
@@ -272,6 +329,10 @@ impl NetworkClient {
 }
 
 impl Network {
+    pub fn peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
     pub async fn start(
         mut self,
         gossip_callback_fns: Vec<GossipCallBackFn>,
@@ -291,15 +352,31 @@ impl Network {
             self.dial_bootnode().await;
         }
 
+        let boot_peer_id = self.boot_peer_id();
+
         let span = Span::current();
-        tokio::spawn(
-            async move { Self::start_gossip_hanlder(gossip_msg_rx, gossip_callback_fns).await }
-                .instrument(span.clone()),
-        );
+        tokio::spawn({
+            let network_client = network_client.clone();
+            async move {
+                Self::start_gossip_hanlder(gossip_msg_rx, gossip_callback_fns, network_client).await
+            }
+            .instrument(span.clone())
+        });
 
+        let block_provider = self.block_provider;
         tokio::spawn(
-            async move { Self::run(self.swarm, req_rx, gossip_msg_tx, stop_rx).await }
-                .instrument(span),
+            async move {
+                Self::run(
+                    self.swarm,
+                    req_rx,
+                    gossip_msg_tx,
+                    stop_rx,
+                    boot_peer_id,
+                    block_provider,
+                )
+                .await
+            }
+            .instrument(span),
         );
 
         network_client.subscribe().await?;
@@ -310,14 +387,23 @@ impl Network {
     async fn start_gossip_hanlder(
         mut gossip_msg_rx: broadcast::Receiver<Vec<u8>>,
         gossip_callback_fns: Vec<GossipCallBackFn>,
+        network_client: NetworkClient,
     ) {
         while let Ok(msg) = gossip_msg_rx.recv().await {
             for func in &gossip_callback_fns {
-                func(&msg).await;
+                func(&msg, &network_client).await;
             }
         }
     }
 
+    fn boot_peer_id(&self) -> Option<PeerId> {
+        let address = Multiaddr::from_str(&self.boot_addr).ok()?;
+        address.iter().find_map(|protocol| match protocol {
+            Protocol::P2p(peer_id) => Some(peer_id),
+            _ => None,
+        })
+    }
+
     async fn wait_listener_addresses(&mut self) -> Result<(), NetworkError> {
         let peer_id = PeerId::from_bytes(&self.swarm.local_peer_id().to_bytes())?;
 
@@ -407,17 +493,32 @@ impl Network {
         mut req_rx: mpsc::Receiver<ClientRequest>,
         gossip_msg_tx: broadcast::Sender<GossipMessage>,
         mut stop_rx: watch::Receiver<()>,
+        boot_peer_id: Option<PeerId>,
+        block_provider: Option<BlockProviderFn>,
     ) -> Result<(), ()> {
+        let (served_tx, mut served_rx) = mpsc::channel(100);
+        let mut block_exchange = BlockExchange {
+            provider: block_provider,
+            pending: HashMap::new(),
+            served_tx,
+        };
+
         loop {
             select! {
-                Some(request) = req_rx.recv() => Self::handle_client_request(request, &mut swarm),
-                event = swarm.select_next_some() => Self::handle_event(event, &gossip_msg_tx, &mut swarm).await,
+                Some(request) = req_rx.recv() => Self::handle_client_request(request, &mut swarm, &boot_peer_id, &mut block_exchange),
+                event = swarm.select_next_some() => Self::handle_event(event, &gossip_msg_tx, &mut swarm, &mut block_exchange).await,
+                Some((channel, block)) = served_rx.recv() => Self::send_block(&mut swarm, channel, block),
                 _ = stop_rx.changed() => break Ok(()),
             }
         }
     }
 
-    fn handle_client_request(request: ClientRequest, swarm: &mut Swarm<Behavior>) {
+    fn handle_client_request(
+        request: ClientRequest,
+        swarm: &mut Swarm<Behavior>,
+        boot_peer_id: &Option<PeerId>,
+        block_exchange: &mut BlockExchange,
+    ) {
         let sender = request.sender;
         let result = match request.payload {
             ClientRequestPayload::Publish { topic, msg } => {
@@ -448,11 +549,33 @@ impl Network {
                 let result = ClientResponse::ConnectedPeers { peers };
                 Ok(result)
             }
+            ClientRequestPayload::ClusterPeers => {
+                let peers = swarm
+                    .connected_peers()
+                    .filter(|peer_id| Some(**peer_id) != *boot_peer_id)
+                    .chain(std::iter::once(swarm.local_peer_id()))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let result = ClientResponse::ClusterPeers { peers };
+                Ok(result)
+            }
             ClientRequestPayload::PeerId => {
                 let peer_id = *swarm.local_peer_id();
                 let result = ClientResponse::PeerId { peer_id };
                 Ok(result)
             }
+            ClientRequestPayload::RequestBlock { peer_id, cid } => {
+                let request = BlockRequest {
+                    cid: cid.to_bytes(),
+                };
+                let request_id = swarm
+                    .behaviour_mut()
+                    .block_exchange
+                    .send_request(&peer_id, request);
+                // answered once the peer responds, see `handle_block_exchange_event`
+                block_exchange.pending.insert(request_id, sender);
+                return;
+            }
         };
 
         Self::send_client_response(result, sender);
@@ -471,6 +594,7 @@ impl Network {
         event: SwarmEvent<BehaviorEvent>,
         gossip_msg_tx: &broadcast::Sender<GossipMessage>,
         swarm: &mut Swarm<Behavior>,
+        block_exchange: &mut BlockExchange,
     ) {
         match event {
             SwarmEvent::Behaviour(BehaviorEvent::Gossipsub(gossipsub::Event::Message {
@@ -503,10 +627,111 @@ impl Network {
                         .add_address(&identified_peer, addr);
                 }
             }
+            SwarmEvent::Behaviour(BehaviorEvent::BlockExchange(event)) => {
+                Self::handle_block_exchange_event(event, swarm, block_exchange)
+            }
             _ => {}
         }
         yield_now().await;
     }
+
+    fn handle_block_exchange_event(
+        event: request_response::Event<BlockRequest, BlockResponse>,
+        swarm: &mut Swarm<Behavior>,
+        block_exchange: &mut BlockExchange,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let (Ok(cid), Some(provider)) =
+                    (Cid::try_from(request.cid), block_exchange.provider.clone())
+                else {
+                    Self::send_block(swarm, channel, None);
+                    return;
+                };
+
+                info!("Block {} requested by {}", cid, peer);
+                let served_tx = block_exchange.served_tx.clone();
+                tokio::spawn(
+                    async move {
+                        let block = provider(cid).await;
+                        if served_tx.send((channel, block)).await.is_err() {
+                            error!("Error serving block {}. The network has stopped", cid);
+                        }
+                    }
+                    .instrument(Span::current()),
+                );
+            }
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            } => {
+                if let Some(sender) = block_exchange.pending.remove(&request_id) {
+                    let block = response.data.map(Bytes::from);
+                    Self::send_client_response(Ok(ClientResponse::RequestBlock { block }), sender);
+                }
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                warn!("Block request to {} failed: {}", peer, error);
+                if let Some(sender) = block_exchange.pending.remove(&request_id) {
+                    let err = NetworkError::BlockExchange(error.to_string());
+                    Self::send_client_response(Err(err), sender);
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                warn!("Failed to serve block request from {}: {}", peer, error)
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    fn send_block(
+        swarm: &mut Swarm<Behavior>,
+        channel: ResponseChannel<BlockResponse>,
+        block: Option<Bytes>,
+    ) {
+        let response = BlockResponse {
+            data: block.map(|block| block.to_vec()),
+        };
+        if swarm
+            .behaviour_mut()
+            .block_exchange
+            .send_response(channel, response)
+            .is_err()
+        {
+            warn!("Error sending block. The requesting peer has disconnected");
+        }
+    }
+}
+
+/// Block exchange bookkeeping owned by the swarm loop.
+struct BlockExchange {
+    provider: Option<BlockProviderFn>,
+    pending: HashMap<OutboundRequestId, oneshot::Sender<Result<ClientResponse, NetworkError>>>,
+    served_tx: mpsc::Sender<(ResponseChannel<BlockResponse>, Option<Bytes>)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BlockRequest {
+    cid: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BlockResponse {
+    data: Option<Vec<u8>>,
 }
 
 #[derive(NetworkBehaviour)]
@@ -514,6 +739,7 @@ struct Behavior {
     gossipsub: gossipsub::Behaviour,
     kademlia: kad::Behaviour<kad::store::MemoryStore>,
     identify: identify::Behaviour,
+    block_exchange: request_response::cbor::Behaviour<BlockRequest, BlockResponse>,
 }
 
 struct ClientRequest {
@@ -524,14 +750,18 @@ pub enum ClientRequestPayload {
     Publish { topic: String, msg: Vec<u8> },
     Subscribe { topic: String },
     ConnectedPeers,
+    ClusterPeers,
     PeerId,
+    RequestBlock { peer_id: PeerId, cid: Cid },
 }
 
 pub enum ClientResponse {
     Publish,
     Subscribe,
     ConnectedPeers { peers: Vec<PeerId> },
+    ClusterPeers { peers: Vec<PeerId> },
     PeerId { peer_id: PeerId },
+    RequestBlock { block: Option<Bytes> },
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -595,4 +825,7 @@ pub enum NetworkError {
 
     #[error("Behavior Error: {0}")]
     Behavior(String),
+
+    #[error("Block exchange Error: {0}")]
+    BlockExchange(String),
 }
@@ -0,0 +1,377 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, info, warn};
+
+use crate::{
+    api::types::ipfs::{IpfsCid, IpfsPinRmResponse, PinState, ReplicationFactor},
+    backend::{BackendError, IpfsBackend},
+    network::NetworkClient,
+    rpc::ipfs::GossipMessage,
+    state::StateClient,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub replication: ReplicationFactor,
+    pub peers: Vec<String>,
+}
+
+impl Allocation {
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains(&peer_id.to_string())
+    }
+
+    pub fn is_satisfied(&self) -> bool {
+        self.peers.len() >= self.replication.min
+    }
+}
+
+pub struct Placement;
+
+impl Placement {
+    /// Picks the peers that should hold `hash` using rendezvous hashing, so every node
+    /// computes the same allocation from the same set of peers.
+    pub fn allocate(hash: &str, peers: &[PeerId], replication: &ReplicationFactor) -> Allocation {
+        let peers = Self::rank(hash, peers)
+            .into_iter()
+            .take(replication.max)
+            .map(|p| p.to_string())
+            .collect::<Vec<String>>();
+
+        Allocation {
+            replication: *replication,
+            peers,
+        }
+    }
+
+    pub fn rank(hash: &str, peers: &[PeerId]) -> Vec<PeerId> {
+        let mut scored = peers
+            .iter()
+            .map(|peer_id| (Self::score(hash, peer_id), *peer_id))
+            .collect::<Vec<([u8; 32], PeerId)>>();
+        scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+        scored.dedup_by(|(_, a), (_, b)| a == b);
+
+        scored.into_iter().map(|(_, peer_id)| peer_id).collect()
+    }
+
+    fn score(hash: &str, peer_id: &PeerId) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(hash.as_bytes());
+        hasher.update(peer_id.to_bytes());
+        hasher.finalize().into()
+    }
+}
+
+pub async fn start_rebalance_process<B>(
+    state_client: StateClient,
+    network_client: NetworkClient,
+    backend: B,
+) where
+    B: IpfsBackend,
+{
+    info!("starting rebalance process");
+    loop {
+        sleep(Duration::from_secs(10)).await;
+
+        if let Err(err) = rebalance(&state_client, &network_client, &backend).await {
+            debug!("Error rebalancing allocations: {}", err);
+        }
+    }
+}
+
+async fn rebalance<B>(
+    state_client: &StateClient,
+    network_client: &NetworkClient,
+    backend: &B,
+) -> Result<(), String>
+where
+    B: IpfsBackend,
+{
+    let local_peer_id = network_client
+        .get_peer_id()
+        .await
+        .map_err(|err| err.to_string())?;
+    let cluster_peers = network_client
+        .get_cluster_peers()
+        .await
+        .map_err(|err| err.to_string())?;
+    let allocations = state_client
+        .get_allocations()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    for (hash, allocation) in allocations {
+        // a reallocation can leave this node holding content it is no longer allocated
+        if let Err(err) =
+            release_if_handed_off(state_client, backend, &local_peer_id, &hash, &allocation).await
+        {
+            warn!("Unable to release {}: {}", hash, err);
+        }
+
+        let Some(new_allocation) = reallocate(&hash, &allocation, &cluster_peers) else {
+            continue;
+        };
+
+        // Only the highest ranked live holder announces the new allocation so a peer
+        // joining or leaving does not cause every node to gossip at once.
+        let live_peers = cluster_peers
+            .iter()
+            .filter(|p| allocation.contains(p))
+            .cloned()
+            .collect::<Vec<PeerId>>();
+        let Some(leader) = Placement::rank(&hash, &live_peers).first().cloned() else {
+            warn!("No live peers hold {}, unable to reallocate", hash);
+            continue;
+        };
+        if leader != local_peer_id {
+            continue;
+        }
+
+        if !new_allocation.is_satisfied() {
+            warn!(
+                "{} is under-replicated: {} of minimum {} peers available",
+                hash,
+                new_allocation.peers.len(),
+                allocation.replication.min
+            );
+        }
+
+        let cid = hash.parse::<IpfsCid>().map_err(|err| err.to_string())?;
+        // the leader holds the content, so peers can be told its size
+        let size = backend.stat(&hash).await.ok();
+        let msg = serde_json::to_vec(&GossipMessage::AddPin {
+            hash: cid,
+            allocation: Some(new_allocation.clone()),
+            size,
+        })
+        .map_err(|err| err.to_string())?;
+        network_client
+            .publish(msg)
+            .await
+            .map_err(|err| err.to_string())?;
+        state_client
+            .set_allocation(hash.clone(), new_allocation.clone())
+            .await
+            .map_err(|err| err.to_string())?;
+
+        info!("Reallocated {} to peers: {:?}", hash, new_allocation.peers);
+    }
+
+    Ok(())
+}
+
+/// Returns the allocation `hash` should have now that the cluster is `cluster_peers`, if
+/// it differs from `allocation`: an allocated peer left, or a peer joined that ranks
+/// above one allocated or fills an allocation short of its maximum.
+fn reallocate(hash: &str, allocation: &Allocation, cluster_peers: &[PeerId]) -> Option<Allocation> {
+    let new_allocation = Placement::allocate(hash, cluster_peers, &allocation.replication);
+    (new_allocation != *allocation).then_some(new_allocation)
+}
+
+/// Unpins `hash` from this node when it is allocated to other peers and enough of them
+/// report it pinned, so the node that added it, or one dropped by a reallocation, does not
+/// keep a copy beyond the replication maximum. Returns whether it was released.
+pub(crate) async fn release_if_handed_off<B>(
+    state_client: &StateClient,
+    backend: &B,
+    local_peer_id: &PeerId,
+    hash: &str,
+    allocation: &Allocation,
+) -> Result<bool, String>
+where
+    B: IpfsBackend,
+{
+    if allocation.contains(local_peer_id) {
+        return Ok(false);
+    }
+
+    let statuses = state_client
+        .get_pin_statuses(hash.to_string())
+        .await
+        .map_err(|err| err.to_string())?;
+    let is_pinned = |peer_id: &String| {
+        statuses
+            .iter()
+            .any(|(p, status)| p == peer_id && status.state == PinState::Pinned)
+    };
+    let local_peer_id = local_peer_id.to_string();
+    if !is_pinned(&local_peer_id) {
+        return Ok(false);
+    }
+    let holders = allocation.peers.iter().filter(|p| is_pinned(p)).count();
+    if holders == 0 || holders < allocation.replication.min {
+        return Ok(false);
+    }
+
+    // names pin their targets whatever the allocation
+    let names = state_client
+        .get_names(String::new())
+        .await
+        .map_err(|err| err.to_string())?;
+    if names.iter().any(|(_, target)| target == hash) {
+        return Ok(false);
+    }
+
+    backend.pin_rm(hash).await.map_err(|err| err.to_string())?;
+    state_client
+        .rm_pin_status(hash.to_string(), local_peer_id)
+        .await
+        .map_err(|err| err.to_string())?;
+    info!(
+        "Released {} now held by {} of its allocated peers",
+        hash, holders
+    );
+
+    Ok(true)
+}
+
+/// Unpins `hash` from this node. Content handed off to the peers it is allocated to is no
+/// longer pinned here, which is not an error when it is being removed from the cluster.
+pub(crate) async fn unpin_local<B>(
+    state_client: &StateClient,
+    backend: &B,
+    local_peer_id: &PeerId,
+    hash: &str,
+) -> Result<IpfsPinRmResponse, BackendError>
+where
+    B: IpfsBackend,
+{
+    let err = match backend.pin_rm(hash).await {
+        Ok(response) => return Ok(response),
+        Err(err) => err,
+    };
+    let allocations = state_client.get_allocations().await.unwrap_or_default();
+    let is_allocated_elsewhere = allocations
+        .iter()
+        .any(|(h, allocation)| h == hash && !allocation.contains(local_peer_id));
+    if !is_allocated_elsewhere {
+        return Err(err);
+    }
+
+    debug!("{} was handed off to its allocated peers: {}", hash, err);
+    Ok(IpfsPinRmResponse {
+        pins: vec![hash.to_string()],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::types::ipfs::{CidVersion, PinStatus},
+        backend::memory::MemoryBackend,
+        state::State,
+    };
+
+    #[test]
+    fn allocation_is_deterministic_and_bounded_by_max() {
+        let peers = (0..5).map(|_| PeerId::random()).collect::<Vec<PeerId>>();
+        let mut reversed = peers.clone();
+        reversed.reverse();
+        let replication = ReplicationFactor::new(1, 3);
+
+        let allocation = Placement::allocate("hash", &peers, &replication);
+        let reversed_allocation = Placement::allocate("hash", &reversed, &replication);
+
+        assert_eq!(allocation.peers.len(), 3);
+        assert_eq!(allocation, reversed_allocation);
+    }
+
+    #[test]
+    fn removing_an_unallocated_peer_keeps_allocation() {
+        let peers = (0..5).map(|_| PeerId::random()).collect::<Vec<PeerId>>();
+        let replication = ReplicationFactor::new(1, 2);
+        let allocation = Placement::allocate("hash", &peers, &replication);
+
+        let departed = peers.iter().find(|p| !allocation.contains(p)).unwrap();
+        let remaining = peers
+            .iter()
+            .filter(|p| *p != departed)
+            .cloned()
+            .collect::<Vec<PeerId>>();
+
+        assert_eq!(
+            allocation,
+            Placement::allocate("hash", &remaining, &replication)
+        );
+    }
+
+    #[test]
+    fn peers_joining_or_leaving_reallocate_only_when_the_allocation_changes() {
+        let peers = (0..5).map(|_| PeerId::random()).collect::<Vec<PeerId>>();
+        let replication = ReplicationFactor::new(1, 2);
+        let allocation = Placement::allocate("hash", &peers[..4], &replication);
+
+        // a departed holder is replaced
+        let remaining = peers[..4]
+            .iter()
+            .filter(|p| p.to_string() != allocation.peers[0])
+            .cloned()
+            .collect::<Vec<PeerId>>();
+        let replaced = reallocate("hash", &allocation, &remaining).unwrap();
+        assert!(!replaced.peers.contains(&allocation.peers[0]));
+        assert_eq!(replaced.peers.len(), 2);
+
+        // a joining peer takes a place only if it ranks among the top peers
+        let joined = reallocate("hash", &allocation, &peers);
+        let ranks_high = Placement::rank("hash", &peers)[..2].contains(&peers[4]);
+        assert_eq!(joined.is_some(), ranks_high);
+        assert!(reallocate("hash", &allocation, &peers[..4]).is_none());
+
+        // an allocation short of its maximum grows as peers join
+        let short = Placement::allocate("hash", &peers[..1], &replication);
+        let grown = reallocate("hash", &short, &peers[..4]).unwrap();
+        assert_eq!(grown.peers.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn content_is_released_once_enough_allocated_peers_hold_it() {
+        let backend = MemoryBackend::new();
+        let hash = backend
+            .add(b"handed off".to_vec(), CidVersion::V1)
+            .await
+            .unwrap()
+            .hash;
+        let state_client = State::new().start();
+        let local = PeerId::random();
+        let peers = (0..2).map(|_| PeerId::random()).collect::<Vec<PeerId>>();
+        let allocation = Placement::allocate(&hash, &peers, &ReplicationFactor::new(2, 2));
+        let release = || release_if_handed_off(&state_client, &backend, &local, &hash, &allocation);
+        let pinned_by = |peer_id: &PeerId| {
+            state_client.set_pin_status(
+                hash.clone(),
+                peer_id.to_string(),
+                PinStatus::new(PinState::Pinned),
+            )
+        };
+
+        pinned_by(&local).await.unwrap();
+        pinned_by(&peers[0]).await.unwrap();
+        assert!(!release().await.unwrap());
+        pinned_by(&peers[1]).await.unwrap();
+        assert!(release().await.unwrap());
+        assert!(backend.pin_ls().await.unwrap().keys.get(&hash).is_none());
+        // released content is not released again
+        assert!(!release().await.unwrap());
+
+        // the allocated peers keep their copies
+        let allocated = Placement::allocate(&hash, &[local], &ReplicationFactor::new(1, 1));
+        pinned_by(&local).await.unwrap();
+        assert!(
+            !release_if_handed_off(&state_client, &backend, &local, &hash, &allocated)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn allocation_with_too_few_peers_is_not_satisfied() {
+        let peers = vec![PeerId::random()];
+        let allocation = Placement::allocate("hash", &peers, &ReplicationFactor::new(2, 3));
+
+        assert!(!allocation.is_satisfied());
+    }
+}
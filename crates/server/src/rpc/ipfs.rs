@@ -2,59 +2,105 @@ use crate::{
     api::{
         ipfs::IpfsServer,
         types::ipfs::{
-            IpfsAddResponse, IpfsIdResponse, IpfsPinAddResponse, IpfsPinLsResponse,
-            IpfsPinResponse, IpfsPinRmResponse, PinAction,
+            is_valid_name, Chunk, CidVersion, IpfsAddResponse, IpfsCid, IpfsDagImportResponse,
+            IpfsDownload, IpfsIdResponse, IpfsKey, IpfsKeyListResponse, IpfsLsResponse,
+            IpfsNamePublishResponse, IpfsNameResolveResponse, IpfsPinResponse,
+            IpfsPinStatusResponse, IpfsRefsResponse, IpfsStatResponse, PeerPinStatus, PinAction,
+            PinState, PinStatus, ReplicationFactor, TransferId, MAX_CHUNK_SIZE,
         },
     },
-    network::NetworkClient,
-    rpc::error::RpcServeError,
+    backend::{BackendError, IpfsBackend},
+    network::{GossipCallBackFn, NetworkClient},
+    placement::{
+        release_if_handed_off, start_rebalance_process, unpin_local, Allocation, Placement,
+    },
+    rpc::{
+        error::RpcServeError,
+        mfs::mfs_root_key,
+        staging::{Staged, Staging},
+    },
     state::StateClient,
 };
-use bytes::Bytes;
 use futures::FutureExt;
 use jsonrpsee::{
     core::{async_trait, RpcResult},
     Methods,
 };
-use reqwest::{
-    multipart::{Form, Part},
-    Body, Client,
-};
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use tracing::{debug, error, info};
+use std::time::SystemTime;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error, info, warn, Instrument, Span};
 
-use super::Call;
+const MAX_PIN_RETRIES: u32 = 3;
+/// Delay before the first retry of a failed pin, doubled for each retry after it.
+const PIN_RETRY_BASE_DELAY: Duration = Duration::from_secs(10);
 
-pub struct IpfsApi<C> {
-    ipfs_base_url: String,
-    client: C,
+pub struct IpfsApi<B> {
+    backend: B,
     state_client: StateClient,
     network_client: NetworkClient,
+    normalize_cids: bool,
+    staging: Staging,
 }
 
-#[cfg(not(feature = "mock-ipfs"))]
-impl IpfsApi<ReqwestClient> {
+impl<B> IpfsApi<B>
+where
+    B: IpfsBackend,
+{
     pub fn new(
-        ipfs_base_url: impl Into<String>,
+        backend: B,
         state_client: StateClient,
         network_client: NetworkClient,
+        gc_on_expiry: bool,
+        normalize_cids: bool,
+        max_storage: Option<u64>,
     ) -> Self {
-        let client = ReqwestClient::new();
+        let span = Span::current();
+        tokio::spawn(
+            start_pin_queue_process(
+                state_client.clone(),
+                network_client.clone(),
+                backend.clone(),
+                max_storage,
+            )
+            .instrument(span.clone()),
+        );
+        tokio::spawn(
+            start_rebalance_process(
+                state_client.clone(),
+                network_client.clone(),
+                backend.clone(),
+            )
+            .instrument(span.clone()),
+        );
+        tokio::spawn(
+            start_expiry_process(
+                state_client.clone(),
+                network_client.clone(),
+                backend.clone(),
+                gc_on_expiry,
+            )
+            .instrument(span),
+        );
 
         Self {
-            ipfs_base_url: ipfs_base_url.into(),
-            client,
+            backend,
             state_client,
             network_client,
+            normalize_cids,
+            staging: Staging::default(),
         }
     }
-}
 
-impl<C> IpfsApi<C>
-where
-    C: HttpClient + std::marker::Send + std::marker::Sync + 'static,
-{
+    /// Takes the staged file of `transfer` out of staging while it is used.
+    fn staged(&self, transfer: &TransferId) -> Result<Staged, RpcServeError> {
+        self.staging
+            .take(transfer)
+            .ok_or_else(|| RpcServeError::Message(format!("Unknown transfer: {}", transfer)))
+    }
+
     async fn add_ipfs_to_state(&self, hash: &str) {
         match self.state_client.add_ipfs_hash(hash.to_string()).await {
             Ok(_) => debug!("Saved ipfs hash {} to state", hash),
@@ -76,123 +122,517 @@ where
         };
     }
 
-    async fn gossip(&self, gossip_msg: &GossipMessage) {
-        let msg = match serde_json::to_vec(gossip_msg) {
-            Ok(msg) => msg,
-            Err(err) => {
-                error!("Unable to seralize add file gossip message: {}", err);
-                return;
-            }
+    async fn add_expiry_to_state(&self, hash: &str, expires_at: Option<SystemTime>) {
+        let Some(expires_at) = expires_at else {
+            return;
         };
-        match self.network_client.publish(msg).await {
-            Ok(_) => info!("Successfully gossiped {} message", gossip_msg.to_str()),
-            Err(err) => error!("Error while gossiping add file message: {}", err),
+
+        match self
+            .state_client
+            .set_expiry(hash.to_string(), expires_at)
+            .await
+        {
+            Ok(_) => debug!("Saved expiry for {} to state", hash),
+            Err(err) => error!("Error saving expiry to state: {:?}", err),
         };
     }
 
-    pub async fn gossip_callback_fn(msg: &[u8], ipfs_base_url: String, client: C) {
-        if let Ok(GossipMessage::AddFile { hash }) = serde_json::from_slice::<GossipMessage>(msg) {
-            info!("Processing add file gossip message");
-            let url = format!("{}/api/v0/pin/add?arg={}", ipfs_base_url, hash);
-            let request = || async move { client.post(url).await }.boxed();
+    async fn add_allocation_to_state(&self, hash: &str, allocation: &Allocation) {
+        match self
+            .state_client
+            .set_allocation(hash.to_string(), allocation.clone())
+            .await
+        {
+            Ok(_) => debug!("Saved allocation for {} to state", hash),
+            Err(err) => error!("Error saving allocation to state: {:?}", err),
+        };
+    }
 
-            match <Self as Call>::call::<IpfsPinAddResponse, IpfsApiError>(request).await {
-                Ok(Some(_)) => {
-                    info!("Successfully added {} from gossip message", hash)
-                }
-                Ok(None) => error!("Received empty response from ipfs server"),
-                Err(err) => error!("Error adding file from gossip message: {}", err),
-            };
-        } else if let Ok(GossipMessage::AddPin { hash }) =
-            serde_json::from_slice::<GossipMessage>(msg)
+    /// Returns the peers available for placement, or an error when the cluster is too small
+    /// to satisfy the minimum of the requested replication factor.
+    async fn placement_peers(
+        &self,
+        replication: &ReplicationFactor,
+    ) -> Result<Vec<PeerId>, RpcServeError> {
+        if !replication.is_valid() {
+            return Err(RpcServeError::Message(format!(
+                "Invalid replication factor: min {} max {}",
+                replication.min, replication.max
+            )));
+        }
+
+        let peers = self
+            .network_client
+            .get_cluster_peers()
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+
+        if peers.len() < replication.min {
+            return Err(RpcServeError::Message(format!(
+                "Replication factor requires {} peers but only {} are available",
+                replication.min,
+                peers.len()
+            )));
+        }
+
+        Ok(peers)
+    }
+
+    async fn allocate(
+        &self,
+        hash: &str,
+        replication: Option<(ReplicationFactor, Vec<PeerId>)>,
+    ) -> Option<Allocation> {
+        let (replication, peers) = replication?;
+        let allocation = Placement::allocate(hash, &peers, &replication);
+        info!("allocated {} to peers: {:?}", hash, allocation.peers);
+
+        self.add_allocation_to_state(hash, &allocation).await;
+        Some(allocation)
+    }
+
+    async fn report_pinned(&self, hash: &IpfsCid) {
+        match self.network_client.get_peer_id().await {
+            Ok(peer_id) => {
+                report_pin_status(
+                    &self.state_client,
+                    &self.network_client,
+                    &peer_id,
+                    hash,
+                    PinStatus::new(PinState::Pinned),
+                )
+                .await
+            }
+            Err(err) => error!("Unable to get local peer id: {}", err),
+        }
+    }
+
+    /// Returns the size of `hash`, announced with it so peers can check their storage quota
+    /// without fetching it.
+    async fn size(&self, hash: &str) -> Option<u64> {
+        match self.backend.stat(hash).await {
+            Ok(size) => Some(size),
+            Err(err) => {
+                warn!("Unable to get size of {}: {}", hash, err);
+                None
+            }
+        }
+    }
+
+    /// Records a file the backend has just added and announces it to the cluster.
+    async fn added(
+        &self,
+        mut response: IpfsAddResponse,
+        expires_at: Option<SystemTime>,
+        replication: Option<(ReplicationFactor, Vec<PeerId>)>,
+    ) -> Result<IpfsAddResponse, RpcServeError> {
+        let hash = response
+            .hash
+            .parse::<IpfsCid>()
+            .map_err(|err| RpcServeError::Message(err.to_string()))?
+            .normalize(self.normalize_cids);
+        response.hash = hash.to_string();
+
+        info!("added {} to ipfs", response.hash);
+
+        self.add_ipfs_to_state(&response.hash).await;
+        self.add_expiry_to_state(&response.hash, expires_at).await;
+        self.report_pinned(&hash).await;
+        let allocation = self.allocate(&response.hash, replication).await;
+        let size = self.size(&response.hash).await;
+        self.gossip(&GossipMessage::AddFile {
+            hash,
+            allocation,
+            size,
+        })
+        .await;
+
+        Ok(response)
+    }
+
+    async fn gossip(&self, gossip_msg: &GossipMessage) {
+        gossip(&self.network_client, gossip_msg).await;
+    }
+}
+
+/// Applies gossip messages from peers to the local ipfs node.
+#[derive(Clone)]
+pub struct GossipHandler<B> {
+    backend: B,
+    state_client: StateClient,
+    local_peer_id: PeerId,
+    normalize_cids: bool,
+}
+
+impl<B> GossipHandler<B>
+where
+    B: IpfsBackend,
+{
+    pub fn new(
+        backend: B,
+        state_client: StateClient,
+        local_peer_id: PeerId,
+        normalize_cids: bool,
+    ) -> Self {
+        Self {
+            backend,
+            state_client,
+            local_peer_id,
+            normalize_cids,
+        }
+    }
+
+    pub fn into_callback_fn(self) -> GossipCallBackFn {
+        Box::new(move |msg: &[u8], network_client: &NetworkClient| {
+            let handler = self.clone();
+
+            async move {
+                handler.handle(msg, network_client).await;
+            }
+            .boxed()
+        })
+    }
+
+    pub async fn handle(&self, msg: &[u8], network_client: &NetworkClient) {
+        if let Ok(GossipMessage::AddFile {
+            hash,
+            allocation,
+            size,
+        }) = serde_json::from_slice::<GossipMessage>(msg)
+        {
+            info!("Processing add file gossip message");
+            let hash = hash.normalize(self.normalize_cids);
+            self.pin(hash, allocation, size, network_client).await;
+        } else if let Ok(GossipMessage::AddPin {
+            hash,
+            allocation,
+            size,
+        }) = serde_json::from_slice::<GossipMessage>(msg)
         {
             info!("Processing add pin gossip message");
-            let url = format!("{}/api/v0/pin/add?arg={}", ipfs_base_url, hash);
-            let request = || async move { client.post(url).await }.boxed();
-
-            match <Self as Call>::call::<IpfsPinAddResponse, IpfsApiError>(request).await {
-                Ok(Some(_)) => info!("Successfully added {} pin from gossip message", hash),
-                Ok(None) => error!("Received empty response from ipfs server"),
-                Err(err) => error!("Error adding pin from gossip message: {}", err),
-            };
+            let hash = hash.normalize(self.normalize_cids);
+            self.pin(hash, allocation, size, network_client).await;
         } else if let Ok(GossipMessage::RmPin { hash }) =
             serde_json::from_slice::<GossipMessage>(msg)
         {
             info!("Processing rm pin gossip message");
-            let url = format!("{}/api/v0/pin/rm?arg={}", ipfs_base_url, hash);
-            let request = || async move { client.post(url).await }.boxed();
-
-            match <Self as Call>::call::<IpfsPinRmResponse, IpfsApiError>(request).await {
-                Ok(Some(_)) => info!("Successfully removed {} pin from gossip message", hash),
-                Ok(None) => error!("Received empty response from ipfs server"),
+            let hash = hash.normalize(self.normalize_cids).to_string();
+            if let Err(err) = self.state_client.rm_pin_ipfs_hash(hash.clone()).await {
+                error!("Error removing ipfs hash from state: {:?}", err);
+            }
+            if let Err(err) = self.state_client.rm_allocation(hash.clone()).await {
+                error!("Error removing allocation from state: {:?}", err);
+            }
+            if let Err(err) = self.state_client.rm_pin_statuses(hash.clone()).await {
+                error!("Error removing pin statuses from state: {:?}", err);
+            }
+            match unpin_local(
+                &self.state_client,
+                &self.backend,
+                &self.local_peer_id,
+                &hash,
+            )
+            .await
+            {
+                Ok(_) => info!("Successfully removed {} pin from gossip message", hash),
                 Err(err) => error!("Error removing pin from gossip message: {}", err),
             };
+        } else if let Ok(GossipMessage::PinStatus {
+            hash,
+            peer_id,
+            status,
+        }) = serde_json::from_slice::<GossipMessage>(msg)
+        {
+            info!("Processing pin status gossip message");
+            let hash = hash.normalize(self.normalize_cids);
+            match status.state {
+                PinState::Failed | PinState::Refused => warn!(
+                    "Peer {} could not pin {}: {}",
+                    peer_id,
+                    hash,
+                    status.error.as_deref().unwrap_or("unknown error")
+                ),
+                _ => debug!("Peer {} reported {} as {:?}", peer_id, hash, status.state),
+            }
+            let is_pinned = status.state == PinState::Pinned;
+            if let Err(err) = self
+                .state_client
+                .set_pin_status(hash.to_string(), peer_id, status)
+                .await
+            {
+                error!("Error saving pin status to state: {:?}", err);
+            }
+            if is_pinned {
+                self.release_if_handed_off(&hash.to_string()).await;
+            }
+        } else if let Ok(GossipMessage::NamePublish {
+            name,
+            hash,
+            previous,
+            size,
+        }) = serde_json::from_slice::<GossipMessage>(msg)
+        {
+            info!("Processing name publish gossip message");
+            let hash = hash.normalize(self.normalize_cids);
+            info!("Name {} now points at {}", name, hash);
+            self.repoint(name, hash, previous, size, network_client)
+                .await;
+        } else if let Ok(GossipMessage::MfsRoot {
+            peer_id,
+            root,
+            previous,
+            size,
+        }) = serde_json::from_slice::<GossipMessage>(msg)
+        {
+            info!("Processing mfs root gossip message");
+            let root = root.normalize(self.normalize_cids);
+            info!("Mfs root of {} is now {}", peer_id, root);
+            self.repoint(mfs_root_key(&peer_id), root, previous, size, network_client)
+                .await;
+        } else {
+            warn!("Ignoring malformed gossip message");
         }
     }
-}
 
-impl<C> Call for IpfsApi<C> {}
+    /// Points `name`, an ipns name or a peer's mfs root, at `hash`, pinning it and
+    /// unpinning the hash it replaced if that was only pinned because of the name. The
+    /// `previous` a peer gossips is never trusted to be unpinned on its own.
+    async fn repoint(
+        &self,
+        name: String,
+        hash: IpfsCid,
+        previous: Option<IpfsCid>,
+        size: Option<u64>,
+        network_client: &NetworkClient,
+    ) {
+        let local_previous = match self
+            .state_client
+            .set_name(name.clone(), hash.to_string())
+            .await
+        {
+            Ok(local_previous) => local_previous,
+            Err(err) => {
+                error!("Error saving name to state: {:?}", err);
+                None
+            }
+        };
+
+        if let Err(err) = self.state_client.add_name_pin(hash.to_string()).await {
+            error!("Error saving name pin to state: {:?}", err);
+        }
+        self.enqueue_pin(hash, None, size, network_client).await;
+
+        let mut previous = previous
+            .map(|previous| previous.normalize(self.normalize_cids).to_string())
+            .into_iter()
+            .chain(local_previous)
+            .filter(|previous| *previous != hash.to_string())
+            .collect::<Vec<String>>();
+        previous.dedup();
+        for previous in previous {
+            match self.state_client.release_name_pin(previous.clone()).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    error!("Error checking pins of {} in state: {:?}", previous, err);
+                    continue;
+                }
+            }
+            // also drops the previous target from the pin queue if it has not been pinned yet
+            if let Err(err) = self.state_client.rm_pin_statuses(previous.clone()).await {
+                error!("Error removing pin statuses from state: {:?}", err);
+            }
+            match self.backend.pin_rm(&previous).await {
+                Ok(_) => info!("Unpinned {} which {} no longer points at", previous, name),
+                Err(err) => warn!("Unable to unpin previous target of {}: {}", name, err),
+            }
+        }
+    }
+
+    /// Unpins `hash` if it is allocated to other peers that now hold it.
+    async fn release_if_handed_off(&self, hash: &str) {
+        let allocation = match self.state_client.get_allocations().await {
+            Ok(allocations) => allocations.into_iter().find(|(h, _)| h == hash),
+            Err(err) => {
+                error!("Error getting allocations from state: {:?}", err);
+                return;
+            }
+        };
+        let Some((_, allocation)) = allocation else {
+            return;
+        };
+        if let Err(err) = release_if_handed_off(
+            &self.state_client,
+            &self.backend,
+            &self.local_peer_id,
+            hash,
+            &allocation,
+        )
+        .await
+        {
+            warn!("Unable to release {}: {}", hash, err);
+        }
+    }
+
+    /// Pins `hash` for its own sake, as announced by a peer that added or pinned it.
+    async fn pin(
+        &self,
+        hash: IpfsCid,
+        allocation: Option<Allocation>,
+        size: Option<u64>,
+        network_client: &NetworkClient,
+    ) {
+        if !self
+            .enqueue_pin(hash, allocation, size, network_client)
+            .await
+        {
+            return;
+        }
+        if let Err(err) = self.state_client.pin_ipfs_hash(hash.to_string()).await {
+            error!("Error saving ipfs hash to state: {:?}", err);
+        }
+    }
+
+    /// Queues `hash` for the pin worker so large pins do not hold up the gossip handler.
+    /// `size` is the size the announcing peer reported, checked against the storage quota
+    /// before pinning.
+    /// Returns whether it was queued.
+    async fn enqueue_pin(
+        &self,
+        hash: IpfsCid,
+        allocation: Option<Allocation>,
+        size: Option<u64>,
+        network_client: &NetworkClient,
+    ) -> bool {
+        if !self.is_allocated(&hash.to_string(), allocation).await {
+            return false;
+        }
+
+        if let Err(err) = self.state_client.enqueue_pin(hash.to_string(), size).await {
+            error!("Error adding {} to pin queue: {:?}", hash, err);
+            return false;
+        }
+        report_pin_status(
+            &self.state_client,
+            network_client,
+            &self.local_peer_id,
+            &hash,
+            PinStatus::new(PinState::Queued),
+        )
+        .await;
+        true
+    }
+
+    async fn is_allocated(&self, hash: &str, allocation: Option<Allocation>) -> bool {
+        let Some(allocation) = allocation else {
+            return true;
+        };
+        let is_allocated = allocation.contains(&self.local_peer_id);
+
+        match self
+            .state_client
+            .set_allocation(hash.to_string(), allocation)
+            .await
+        {
+            Ok(_) => debug!("Saved allocation for {} to state", hash),
+            Err(err) => error!("Error saving allocation to state: {:?}", err),
+        };
+
+        if !is_allocated {
+            info!(
+                "Skipping pin of {} which is not allocated to this node",
+                hash
+            );
+        }
+        is_allocated
+    }
+}
 
 #[async_trait]
-impl<C> IpfsServer for IpfsApi<C>
+impl<B> IpfsServer for IpfsApi<B>
 where
-    C: HttpClient + std::marker::Send + std::marker::Sync + 'static,
+    B: IpfsBackend,
 {
     async fn id(&self) -> RpcResult<IpfsIdResponse> {
-        let url = format!("{}{}", self.ipfs_base_url, "/api/v0/id");
-        let request = || async move { self.client.post(url).await }.boxed();
-        let response = <Self as Call>::call::<IpfsIdResponse, IpfsApiError>(request)
+        let response = self
+            .backend
+            .id()
             .await
-            .map_err(|err| RpcServeError::Message(err.to_string()))?
-            .ok_or_else(|| RpcServeError::Message("Received empty response from ipfs".into()))?;
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
 
         Ok(response)
     }
 
-    async fn pin(&self, pin_action: PinAction, hash: Option<String>) -> RpcResult<IpfsPinResponse> {
+    async fn pin(
+        &self,
+        pin_action: PinAction,
+        hash: Option<IpfsCid>,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+    ) -> RpcResult<IpfsPinResponse> {
+        let hash = hash.map(|hash| hash.normalize(self.normalize_cids));
         let r: IpfsPinResponse = match pin_action {
             PinAction::ls => {
-                let url = format!("{}{}", self.ipfs_base_url, "/api/v0/pin/ls");
-                let request = || async move { self.client.post(url).await }.boxed();
-                let response = <Self as Call>::call::<IpfsPinLsResponse, IpfsApiError>(request)
+                let response = self
+                    .backend
+                    .pin_ls()
                     .await
-                    .map_err(|err| RpcServeError::Message(err.to_string()))?
-                    .ok_or_else(|| {
-                        RpcServeError::Message("Received empty response from ipfs".into())
-                    })?;
+                    .map_err(|err| RpcServeError::Message(err.to_string()))?;
                 response.into()
             }
             PinAction::add => {
                 let hash =
                     hash.ok_or_else(|| RpcServeError::Message("Hash not supplied".to_string()))?;
-                let url = format!("{}/api/v0/pin/add?arg={}", self.ipfs_base_url, hash);
-                let request = || async move { self.client.post(url).await }.boxed();
-                let response = <Self as Call>::call::<IpfsPinAddResponse, IpfsApiError>(request)
+                let expires_at = expires_at(ttl)?;
+                let replication = match replication {
+                    Some(replication) => {
+                        Some((replication, self.placement_peers(&replication).await?))
+                    }
+                    None => None,
+                };
+                let response = self
+                    .backend
+                    .pin_add(&hash.to_string())
                     .await
-                    .map_err(|err| RpcServeError::Message(err.to_string()))?
-                    .ok_or_else(|| {
-                        RpcServeError::Message("Received empty response from ipfs".into())
-                    })?;
+                    .map_err(|err| RpcServeError::Message(err.to_string()))?;
                 info!("added {} pin", hash);
 
-                self.add_ipfs_pin_to_state(&hash).await;
-                self.gossip(&GossipMessage::AddPin { hash }).await;
+                let key = hash.to_string();
+                self.add_ipfs_pin_to_state(&key).await;
+                self.add_expiry_to_state(&key, expires_at).await;
+                self.report_pinned(&hash).await;
+                let allocation = self.allocate(&key, replication).await;
+                let size = self.size(&key).await;
+                self.gossip(&GossipMessage::AddPin {
+                    hash,
+                    allocation,
+                    size,
+                })
+                .await;
                 response.into()
             }
             PinAction::rm => {
                 let hash =
                     hash.ok_or_else(|| RpcServeError::Message("Hash not supplied".to_string()))?;
-                let url = format!("{}/api/v0/pin/rm?arg={}", self.ipfs_base_url, hash);
-                let request = || async move { self.client.post(url).await }.boxed();
-                let response = <Self as Call>::call::<IpfsPinRmResponse, IpfsApiError>(request)
+                let local_peer_id = self
+                    .network_client
+                    .get_peer_id()
                     .await
-                    .map_err(|err| RpcServeError::Message(err.to_string()))?
-                    .ok_or_else(|| {
-                        RpcServeError::Message("Received empty response from ipfs".into())
-                    })?;
+                    .map_err(|err| RpcServeError::Message(err.to_string()))?;
+                let response = unpin_local(
+                    &self.state_client,
+                    &self.backend,
+                    &local_peer_id,
+                    &hash.to_string(),
+                )
+                .await
+                .map_err(|err| RpcServeError::Message(err.to_string()))?;
                 info!("removed {} pin", hash);
 
-                self.rm_ipfs_pin_from_state(&hash).await;
+                self.rm_ipfs_pin_from_state(&hash.to_string()).await;
+                if let Err(err) = self.state_client.rm_pin_statuses(hash.to_string()).await {
+                    error!("Error removing pin statuses from state: {:?}", err);
+                }
                 self.gossip(&GossipMessage::RmPin { hash }).await;
                 response.into()
             }
@@ -200,260 +640,646 @@ where
         Ok(r)
     }
 
-    async fn add(&self, data: Vec<u8>) -> RpcResult<IpfsAddResponse> {
-        let url = format!("{}{}", self.ipfs_base_url, "/api/v0/add");
-        let bytes = Bytes::from_iter(data.into_iter());
+    async fn add(
+        &self,
+        data: Vec<u8>,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+        cid_version: Option<CidVersion>,
+    ) -> RpcResult<IpfsAddResponse> {
+        let expires_at = expires_at(ttl)?;
+        let replication = match replication {
+            Some(replication) => Some((replication, self.placement_peers(&replication).await?)),
+            None => None,
+        };
+        let response = self
+            .backend
+            .add(data, cid_version.unwrap_or_default())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
 
-        let body = Body::from(bytes);
-        let part = Part::stream(body);
-        let form = Form::new().part("file", part);
+        Ok(self.added(response, expires_at, replication).await?)
+    }
 
-        let request = || async move { self.client.post_multipart(url, form).await }.boxed();
-        let response = <Self as Call>::call::<IpfsAddResponse, IpfsApiError>(request)
+    async fn add_upload(
+        &self,
+        transfer: TransferId,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+        cid_version: Option<CidVersion>,
+    ) -> RpcResult<IpfsAddResponse> {
+        let staged = self.staged(&transfer)?;
+        let expires_at = expires_at(ttl)?;
+        let replication = match replication {
+            Some(replication) => Some((replication, self.placement_peers(&replication).await?)),
+            None => None,
+        };
+        let response = self
+            .backend
+            .add_file(staged.path(), cid_version.unwrap_or_default())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        drop(staged);
+
+        Ok(self.added(response, expires_at, replication).await?)
+    }
+
+    async fn pin_status(&self, hash: IpfsCid) -> RpcResult<IpfsPinStatusResponse> {
+        let hash = hash.normalize(self.normalize_cids).to_string();
+        let peers = self
+            .state_client
+            .get_pin_statuses(hash.clone())
             .await
             .map_err(|err| RpcServeError::Message(err.to_string()))?
-            .ok_or_else(|| RpcServeError::Message("Received empty response from ipfs".into()))?;
+            .into_iter()
+            .map(|(peer_id, status)| PeerPinStatus { peer_id, status })
+            .collect::<Vec<PeerPinStatus>>();
 
-        info!("added {} to ipfs", response.hash);
+        Ok(IpfsPinStatusResponse { hash, peers })
+    }
 
-        self.add_ipfs_to_state(&response.hash).await;
-        self.gossip(&GossipMessage::AddFile {
-            hash: response.hash.clone(),
-        })
-        .await;
+    async fn cat(&self, hash: IpfsCid) -> RpcResult<String> {
+        let body = self.backend.cat(&hash.to_string()).await.map_err(|err| {
+            error!("{}", err);
+            RpcServeError::Message(err.to_string())
+        })?;
+
+        info!("read {} from ipfs", hash);
+
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    async fn cat_range(&self, hash: IpfsCid, offset: u64, length: u64) -> RpcResult<Chunk> {
+        let body = self
+            .backend
+            .cat_range(&hash.to_string(), offset, length.min(MAX_CHUNK_SIZE as u64))
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        debug!("read {} bytes of {} from {}", body.len(), hash, offset);
+
+        Ok(Chunk(body.to_vec()))
+    }
+
+    async fn stat(&self, hash: IpfsCid) -> RpcResult<IpfsStatResponse> {
+        let response = self
+            .backend
+            .dag_stat(&hash.to_string())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
 
         Ok(response)
     }
 
-    async fn cat(&self, hash: String) -> RpcResult<String> {
-        let url = format!("{}/api/v0/cat?arg={}", self.ipfs_base_url, hash);
-        let request = || async move { self.client.post(url).await }.boxed();
-        match request().await {
-            Err(err) => {
-                error!("{}", err);
-                return Err(RpcServeError::Message(err.to_string()).into());
-            }
-            Ok(response) => {
-                let resp = response
-                    .error_for_status()
-                    .map_err(|err| RpcServeError::Message(err.to_string()))?;
-                let body = resp
-                    .text()
-                    .await
-                    .map_err(|err| RpcServeError::Message(err.to_string()))?;
+    async fn ls(&self, hash: IpfsCid) -> RpcResult<IpfsLsResponse> {
+        let response = self
+            .backend
+            .ls(&hash.to_string())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
 
-                info!("read {} from ipfs", hash);
+        Ok(response)
+    }
 
-                return Ok(body);
-            }
-        }
+    async fn refs(&self, hash: IpfsCid) -> RpcResult<IpfsRefsResponse> {
+        let response = self
+            .backend
+            .refs(&hash.to_string())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+
+        Ok(response)
     }
-}
 
-impl<C> From<IpfsApi<C>> for Methods
-where
-    C: HttpClient + std::marker::Send + std::marker::Sync + 'static,
-{
-    fn from(val: IpfsApi<C>) -> Self {
-        val.into_rpc().into()
+    async fn block_get(&self, hash: IpfsCid) -> RpcResult<Vec<u8>> {
+        let block = self
+            .backend
+            .block_get(&hash.to_string())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+
+        Ok(block.to_vec())
     }
-}
 
-#[derive(Clone)]
-pub struct ReqwestClient {
-    client: Client,
-}
+    async fn upload(&self, transfer: Option<TransferId>, chunk: Chunk) -> RpcResult<TransferId> {
+        let (transfer, staged) = match transfer {
+            Some(transfer) => {
+                let staged = self.staged(&transfer)?;
+                (transfer, staged)
+            }
+            None => self
+                .staging
+                .create()
+                .await
+                .map_err(|err| RpcServeError::Message(err.to_string()))?,
+        };
+        staged
+            .append(&chunk)
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        self.staging.put(transfer.clone(), staged);
 
-impl ReqwestClient {
-    pub fn new() -> Self {
-        Self {
-            client: Client::new(),
+        Ok(transfer)
+    }
+
+    async fn download(&self, transfer: TransferId, offset: u64) -> RpcResult<Chunk> {
+        let staged = self.staged(&transfer)?;
+        let (chunk, end) = staged
+            .read(offset)
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        if !end {
+            self.staging.put(transfer, staged);
         }
+
+        Ok(chunk)
     }
-}
 
-impl Default for ReqwestClient {
-    fn default() -> Self {
-        Self::new()
+    async fn dag_export(&self, hash: IpfsCid) -> RpcResult<IpfsDownload> {
+        let (transfer, staged) = self
+            .staging
+            .create()
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        let size = self
+            .backend
+            .dag_export_file(&hash.to_string(), staged.path())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        info!("exported {} as a car", hash);
+        self.staging.put(transfer.clone(), staged);
+
+        Ok(IpfsDownload { transfer, size })
     }
-}
 
-pub trait HttpClient {
-    fn post(
+    async fn dag_import(
         &self,
-        url: String,
-    ) -> impl std::future::Future<Output = Result<reqwest::Response, reqwest::Error>> + std::marker::Send;
+        transfer: TransferId,
+        replication: Option<ReplicationFactor>,
+        ttl: Option<u64>,
+    ) -> RpcResult<IpfsDagImportResponse> {
+        let staged = self.staged(&transfer)?;
+        let expires_at = expires_at(ttl)?;
+        let replication = match replication {
+            Some(replication) => Some((replication, self.placement_peers(&replication).await?)),
+            None => None,
+        };
+        let response = self
+            .backend
+            .dag_import_file(staged.path())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        drop(staged);
 
-    fn post_multipart(
-        &self,
-        url: String,
-        form: Form,
-    ) -> impl std::future::Future<Output = Result<reqwest::Response, reqwest::Error>> + std::marker::Send;
-}
+        let mut roots = Vec::new();
+        for root in response.roots {
+            let hash = root
+                .parse::<IpfsCid>()
+                .map_err(|err| RpcServeError::Message(err.to_string()))?
+                .normalize(self.normalize_cids);
+            info!("imported {} from a car", hash);
+
+            let key = hash.to_string();
+            self.add_ipfs_pin_to_state(&key).await;
+            self.add_expiry_to_state(&key, expires_at).await;
+            self.report_pinned(&hash).await;
+            let allocation = self.allocate(&key, replication.clone()).await;
+            let size = self.size(&key).await;
+            self.gossip(&GossipMessage::AddPin {
+                hash,
+                allocation,
+                size,
+            })
+            .await;
+            roots.push(key);
+        }
+
+        Ok(IpfsDagImportResponse { roots })
+    }
 
-impl HttpClient for ReqwestClient {
-    async fn post(&self, url: String) -> Result<reqwest::Response, reqwest::Error> {
-        self.client.post(url).send().await
+    async fn key_gen(&self, name: String) -> RpcResult<IpfsKey> {
+        validate_name(&name)?;
+        let key = self
+            .backend
+            .key_gen(&name)
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+
+        info!("generated ipns key {}", key.name);
+        Ok(key)
+    }
+
+    async fn key_list(&self) -> RpcResult<IpfsKeyListResponse> {
+        let response = self
+            .backend
+            .key_list()
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+
+        Ok(response)
     }
 
-    async fn post_multipart(
+    async fn name_publish(
         &self,
-        url: String,
-        form: Form,
-    ) -> Result<reqwest::Response, reqwest::Error> {
-        self.client
-            .post(url)
-            .multipart(form)
-            .header("Content-Type", "application/octet-stream")
-            .send()
+        hash: IpfsCid,
+        key: Option<String>,
+    ) -> RpcResult<IpfsNamePublishResponse> {
+        let hash = hash.normalize(self.normalize_cids);
+        let key = key.unwrap_or_else(|| "self".to_string());
+        validate_name(&key)?;
+
+        let keys = self
+            .backend
+            .key_list()
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        if !keys.keys.iter().any(|k| k.name == key) {
+            self.key_gen(key.clone()).await?;
+        }
+
+        let response = self
+            .backend
+            .name_publish(&hash.to_string(), &key)
             .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        info!("published {} as {}", hash, response.name);
+
+        let previous = match self
+            .state_client
+            .set_name(response.name.clone(), hash.to_string())
+            .await
+        {
+            Ok(previous) => previous.and_then(|previous| previous.parse::<IpfsCid>().ok()),
+            Err(err) => {
+                error!("Error saving name to state: {:?}", err);
+                None
+            }
+        };
+        let size = self.size(&hash.to_string()).await;
+        self.gossip(&GossipMessage::NamePublish {
+            name: response.name.clone(),
+            hash,
+            previous,
+            size,
+        })
+        .await;
+
+        Ok(response)
     }
-}
 
-#[derive(thiserror::Error, Debug)]
-enum IpfsApiError {
-    #[error(transparent)]
-    Request(#[from] reqwest::Error),
+    async fn name_resolve(&self, name: String) -> RpcResult<IpfsNameResolveResponse> {
+        let name = name.trim_start_matches("/ipns/").to_string();
+        validate_name(&name)?;
 
-    #[error(transparent)]
-    Gossip(#[from] crate::network::NetworkError),
+        match self.backend.name_resolve(&name).await {
+            Ok(response) => Ok(response),
+            // names published elsewhere in the cluster are known from gossip even when the
+            // backend cannot resolve them itself
+            Err(err) => match self.state_client.get_name(name.clone()).await {
+                Ok(Some(hash)) => Ok(IpfsNameResolveResponse {
+                    path: format!("/ipfs/{}", hash),
+                }),
+                _ => Err(RpcServeError::Message(err.to_string()).into()),
+            },
+        }
+    }
+}
 
-    #[error("Error deserializing http response")]
-    SerdeDeserialize(#[from] serde_json::Error),
+/// Returns when a pin added now with `ttl` seconds to live expires, checked up front so an
+/// out of range ttl is rejected before anything is added.
+fn expires_at(ttl: Option<u64>) -> Result<Option<SystemTime>, RpcServeError> {
+    ttl.map(|ttl| {
+        SystemTime::now()
+            .checked_add(Duration::from_secs(ttl))
+            .ok_or_else(|| RpcServeError::Message(format!("Ttl is out of range: {}", ttl)))
+    })
+    .transpose()
 }
 
-#[derive(Serialize, Deserialize)]
-pub enum GossipMessage {
-    AddFile { hash: String },
-    AddPin { hash: String },
-    RmPin { hash: String },
+fn validate_name(name: &str) -> Result<(), RpcServeError> {
+    if !is_valid_name(name) {
+        return Err(RpcServeError::Message(format!("Invalid name: {}", name)));
+    }
+    Ok(())
 }
 
-impl GossipMessage {
-    fn to_str(&self) -> &str {
-        match self {
-            GossipMessage::AddFile { hash: _ } => "add_file",
-            GossipMessage::AddPin { hash: _ } => "add_pin",
-            GossipMessage::RmPin { hash: _ } => "rm_pin",
+pub(super) async fn gossip(network_client: &NetworkClient, gossip_msg: &GossipMessage) {
+    let msg = match serde_json::to_vec(gossip_msg) {
+        Ok(msg) => msg,
+        Err(err) => {
+            error!("Unable to seralize add file gossip message: {}", err);
+            return;
         }
+    };
+    match network_client.publish(msg).await {
+        Ok(_) => info!("Successfully gossiped {} message", gossip_msg.to_str()),
+        Err(err) => error!("Error while gossiping add file message: {}", err),
+    };
+}
+
+/// Saves the local pin status of `hash` and reports it to the rest of the cluster.
+async fn report_pin_status(
+    state_client: &StateClient,
+    network_client: &NetworkClient,
+    local_peer_id: &PeerId,
+    hash: &IpfsCid,
+    status: PinStatus,
+) {
+    let peer_id = local_peer_id.to_string();
+    if let Err(err) = state_client
+        .set_pin_status(hash.to_string(), peer_id.clone(), status.clone())
+        .await
+    {
+        error!("Error saving pin status to state: {:?}", err);
     }
+    gossip(
+        network_client,
+        &GossipMessage::PinStatus {
+            hash: *hash,
+            peer_id,
+            status,
+        },
+    )
+    .await;
 }
 
-#[cfg(feature = "mock-ipfs")]
-mod mock_ipfs {
-    use super::*;
-    use http::response::Builder;
-    use reqwest::Response;
-    use std::collections::HashMap;
+async fn start_pin_queue_process<B>(
+    state_client: StateClient,
+    network_client: NetworkClient,
+    backend: B,
+    max_storage: Option<u64>,
+) where
+    B: IpfsBackend,
+{
+    info!("starting pin queue process");
+    let local_peer_id = match network_client.get_peer_id().await {
+        Ok(peer_id) => peer_id,
+        Err(err) => {
+            error!("Unable to start pin queue process: {}", err);
+            return;
+        }
+    };
 
-    pub struct MockRequestClient {
-        responses: HashMap<String, String>,
-    }
+    loop {
+        let (hash, mut status, size) = match state_client.next_queued_pin().await {
+            Ok(Some(next)) => next,
+            Ok(None) => {
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+            Err(err) => {
+                debug!("Error getting next queued pin: {}", err);
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let cid = match hash.parse::<IpfsCid>() {
+            Ok(cid) => cid,
+            Err(err) => {
+                error!("Dropping invalid hash from pin queue: {}", err);
+                if let Err(err) = state_client.rm_pin_statuses(hash).await {
+                    error!("Error updating pin queue: {:?}", err);
+                }
+                continue;
+            }
+        };
+        report_pin_status(
+            &state_client,
+            &network_client,
+            &local_peer_id,
+            &cid,
+            status.clone(),
+        )
+        .await;
 
-    impl MockRequestClient {
-        fn build_response(&self, url: String) -> Result<reqwest::Response, reqwest::Error> {
-            let (_, body) = self
-                .responses
-                .iter()
-                .find(|(stored_url, _)| url.contains(stored_url.as_str()))
-                .unwrap();
-            let response = Builder::new().status(200).body(body.clone()).unwrap();
-            let response = Response::from(response);
+        let quota = match max_storage {
+            Some(max_storage) => check_quota(&backend, &hash, size, max_storage).await,
+            None => Ok(Quota::Within),
+        };
+        let result = match quota {
+            Ok(Quota::Within) => backend.pin_add(&hash).await.map(|_| ()),
+            Ok(Quota::Exceeded(reason)) => {
+                warn!("Refusing to pin {}: {}", hash, reason);
+                status.state = PinState::Refused;
+                status.error = Some(reason);
+                if let Err(err) = state_client
+                    .update_queued_pin(hash.clone(), status.clone(), None)
+                    .await
+                {
+                    error!("Error updating pin queue: {:?}", err);
+                }
+                report_pin_status(&state_client, &network_client, &local_peer_id, &cid, status)
+                    .await;
+                continue;
+            }
+            // the size is not known until the content is announced with it or is local,
+            // so the pin is retried like one that failed
+            Err(err) => Err(err),
+        };
 
-            Ok(response)
+        let mut retry_at = None;
+        match result {
+            Ok(_) => {
+                info!("Successfully pinned {} from pin queue", hash);
+                status.state = PinState::Pinned;
+                status.error = None;
+            }
+            Err(err) => {
+                status.retries += 1;
+                status.error = Some(err.to_string());
+                if status.retries < MAX_PIN_RETRIES {
+                    warn!(
+                        "Error pinning {}, attempt {} of {}: {}",
+                        hash, status.retries, MAX_PIN_RETRIES, err
+                    );
+                    status.state = PinState::Queued;
+                    let delay = PIN_RETRY_BASE_DELAY.saturating_mul(1 << (status.retries - 1));
+                    retry_at = SystemTime::now().checked_add(delay);
+                } else {
+                    error!("Giving up pinning {}: {}", hash, err);
+                    status.state = PinState::Failed;
+                }
+            }
+        }
+
+        if let Err(err) = state_client
+            .update_queued_pin(hash.clone(), status.clone(), retry_at)
+            .await
+        {
+            error!("Error updating pin queue: {:?}", err);
         }
+        report_pin_status(&state_client, &network_client, &local_peer_id, &cid, status).await;
     }
+}
 
-    impl IpfsApi<MockRequestClient> {
-        pub fn new(
-            ipfs_base_url: String,
-            state_client: StateClient,
-            network_client: NetworkClient,
-        ) -> Self {
-            let client = MockRequestClient {
-                responses: Self::build_responses(ipfs_base_url.as_str()),
-            };
+enum Quota {
+    Within,
+    Exceeded(String),
+}
 
-            Self {
-                ipfs_base_url: ipfs_base_url,
-                client,
-                state_client,
-                network_client,
-            }
-        }
+/// Checks that pinning `hash` keeps the repo under `max_storage`. It is sized from `size`,
+/// reported by the peer that announced it, or else from the blocks held locally, as
+/// fetching it to find out would use the storage the check protects.
+async fn check_quota<B>(
+    backend: &B,
+    hash: &str,
+    size: Option<u64>,
+    max_storage: u64,
+) -> Result<Quota, BackendError>
+where
+    B: IpfsBackend,
+{
+    let repo_size = backend.repo_stat().await?.repo_size;
+    let size = match size {
+        Some(size) => size,
+        None => backend.stat(hash).await?,
+    };
 
-        fn build_responses(ipfs_base_url: &str) -> HashMap<String, String> {
-            let mut responses = HashMap::new();
-            let ipfs_id_response = IpfsIdResponse {
-                id: "12D3KooWGaDT5BxsWnaqtkh7iTnpcEUtijzwR6FpuVnGFeA6kSB9".to_string(),
-            };
+    if repo_size.saturating_add(size) <= max_storage {
+        return Ok(Quota::Within);
+    }
+    Ok(Quota::Exceeded(format!(
+        "pinning {} bytes would exceed storage quota ({} of {} bytes used)",
+        size, repo_size, max_storage
+    )))
+}
 
-            let ipfs_pin_ls_response = IpfsPinLsResponse {
-                keys: serde_json::json!({
-                    "QmPAq3VfMBd6Sd7Fv3DtGDfNjSAt82JrMiwft5jtJwqKZ2": {
-                        "Type": "recursive",
-                        "Name": ""
-                    },
-                        "QmPWUHJZiCuWZaYJxLmAmY5yeL6caF9kmQvHUX4iSLxzJ2": {
-                        "Type": "recursive",
-                        "Name": ""
-                    }
-                }),
-            };
+async fn start_expiry_process<B>(
+    state_client: StateClient,
+    network_client: NetworkClient,
+    backend: B,
+    gc_on_expiry: bool,
+) where
+    B: IpfsBackend,
+{
+    info!("starting expiry process");
+    let local_peer_id = match network_client.get_peer_id().await {
+        Ok(peer_id) => peer_id,
+        Err(err) => {
+            error!("Unable to start expiry process: {}", err);
+            return;
+        }
+    };
 
-            let ipfs_pin_add_response = IpfsPinAddResponse {
-                pins: vec!["QmcurmkpXB4rDeQ7tVdQ3ss413YWEhCgskbL46yMmgB8wu".to_string()],
-            };
+    loop {
+        sleep(Duration::from_secs(5)).await;
 
-            let ipfs_pin_rm_response = IpfsPinRmResponse {
-                pins: vec!["QmcurmkpXB4rDeQ7tVdQ3ss413YWEhCgskbL46yMmgB8wu".to_string()],
-            };
+        let hashes = match state_client.take_expired_hashes().await {
+            Ok(hashes) => hashes,
+            Err(err) => {
+                debug!("Error getting expired hashes: {}", err);
+                continue;
+            }
+        };
 
-            let ipfs_add_response = IpfsAddResponse {
-                hash: "QmRgUFjmHJ5nFVCJnCtcVtRhJy87Rc4gyJ3iCK4WWbVUDa".to_string(),
-                name: "QmRgUFjmHJ5nFVCJnCtcVtRhJy87Rc4gyJ3iCK4WWbVUDa".to_string(),
-            };
+        if hashes.is_empty() {
+            continue;
+        }
 
-            responses.insert(
-                format!("{}/api/v0/id", ipfs_base_url),
-                serde_json::to_string(&ipfs_id_response).unwrap(),
-            );
-            responses.insert(
-                format!("{}/api/v0/pin/ls", ipfs_base_url),
-                serde_json::to_string(&ipfs_pin_ls_response).unwrap(),
-            );
-            responses.insert(
-                format!("{}/api/v0/pin/add?arg=", ipfs_base_url),
-                serde_json::to_string(&ipfs_pin_add_response).unwrap(),
-            );
-            responses.insert(
-                format!("{}/api/v0/pin/rm?arg=", ipfs_base_url),
-                serde_json::to_string(&ipfs_pin_rm_response).unwrap(),
-            );
-            responses.insert(
-                format!("{}/api/v0/add", ipfs_base_url),
-                serde_json::to_string(&ipfs_add_response).unwrap(),
-            );
-            responses.insert(
-                format!("{}/api/v0/cat?arg=", ipfs_base_url),
-                "Text from a file!".into(),
-            );
+        for (hash, expires_at) in hashes {
+            if let Err(err) = unpin_expired(&state_client, &backend, &local_peer_id, &hash).await {
+                error!("Error unpinning expired hash {}: {}", hash, err);
+                // tried again on the next pass
+                if let Err(err) = state_client.set_expiry(hash.clone(), expires_at).await {
+                    error!("Error restoring expiry of {}: {:?}", hash, err);
+                }
+                continue;
+            }
+            info!("unpinned expired hash {}", hash);
+            match hash.parse::<IpfsCid>() {
+                Ok(hash) => gossip(&network_client, &GossipMessage::RmPin { hash }).await,
+                Err(err) => error!("Unable to gossip expiry of {}: {}", hash, err),
+            }
+        }
 
-            responses
+        if gc_on_expiry {
+            match backend.repo_gc().await {
+                Ok(count) => info!("Garbage collected {} blocks from ipfs", count),
+                Err(err) => error!("Error running ipfs garbage collection: {}", err),
+            }
         }
     }
+}
 
-    impl HttpClient for MockRequestClient {
-        async fn post(&self, url: String) -> Result<reqwest::Response, reqwest::Error> {
-            self.build_response(url)
-        }
+async fn unpin_expired<B>(
+    state_client: &StateClient,
+    backend: &B,
+    local_peer_id: &PeerId,
+    hash: &str,
+) -> Result<(), BackendError>
+where
+    B: IpfsBackend,
+{
+    unpin_local(state_client, backend, local_peer_id, hash).await?;
 
-        async fn post_multipart(
-            &self,
-            url: String,
-            _form: Form,
-        ) -> Result<reqwest::Response, reqwest::Error> {
-            self.build_response(url)
+    if let Err(err) = state_client.rm_pin_ipfs_hash(hash.to_string()).await {
+        error!("Error removing expired hash from state: {:?}", err);
+    }
+    if let Err(err) = state_client.rm_allocation(hash.to_string()).await {
+        error!("Error removing allocation from state: {:?}", err);
+    }
+    if let Err(err) = state_client.rm_pin_statuses(hash.to_string()).await {
+        error!("Error removing pin statuses from state: {:?}", err);
+    }
+
+    Ok(())
+}
+
+impl<B> From<IpfsApi<B>> for Methods
+where
+    B: IpfsBackend,
+{
+    fn from(val: IpfsApi<B>) -> Self {
+        val.into_rpc().into()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum GossipMessage {
+    AddFile {
+        hash: IpfsCid,
+        allocation: Option<Allocation>,
+        #[serde(default)]
+        size: Option<u64>,
+    },
+    AddPin {
+        hash: IpfsCid,
+        allocation: Option<Allocation>,
+        #[serde(default)]
+        size: Option<u64>,
+    },
+    RmPin {
+        hash: IpfsCid,
+    },
+    PinStatus {
+        hash: IpfsCid,
+        peer_id: String,
+        status: PinStatus,
+    },
+    NamePublish {
+        name: String,
+        hash: IpfsCid,
+        previous: Option<IpfsCid>,
+        #[serde(default)]
+        size: Option<u64>,
+    },
+    MfsRoot {
+        peer_id: String,
+        root: IpfsCid,
+        previous: Option<IpfsCid>,
+        #[serde(default)]
+        size: Option<u64>,
+    },
+}
+
+impl GossipMessage {
+    fn to_str(&self) -> &str {
+        match self {
+            GossipMessage::AddFile { .. } => "add_file",
+            GossipMessage::AddPin { .. } => "add_pin",
+            GossipMessage::RmPin { .. } => "rm_pin",
+            GossipMessage::PinStatus { .. } => "pin_status",
+            GossipMessage::NamePublish { .. } => "name_publish",
+            GossipMessage::MfsRoot { .. } => "mfs_root",
         }
     }
 }
@@ -461,20 +1287,56 @@ mod mock_ipfs {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::backend::memory::MemoryBackend;
 
     #[test]
     fn serialization_deserialization_of_gossip_messages() {
-        let initial = "hash".to_string();
+        let initial = "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o"
+            .parse::<IpfsCid>()
+            .unwrap();
         let msg = serde_json::to_vec(&GossipMessage::AddFile {
-            hash: initial.clone(),
+            hash: initial,
+            allocation: None,
+            size: Some(42),
         })
         .unwrap();
-        if let GossipMessage::AddFile { hash } =
+        if let GossipMessage::AddFile { hash, size, .. } =
             serde_json::from_slice::<GossipMessage>(&msg).unwrap()
         {
             assert_eq!(initial, hash);
+            assert_eq!(size, Some(42));
         } else {
             panic!("Unexpected hash")
         }
     }
+
+    #[test]
+    fn gossip_messages_with_invalid_hashes_are_rejected() {
+        let msg = br#"{"RmPin":{"hash":"foo&recursive=false"}}"#;
+        assert!(serde_json::from_slice::<GossipMessage>(msg).is_err());
+    }
+
+    #[tokio::test]
+    async fn pins_are_checked_against_the_storage_quota() {
+        let backend = MemoryBackend::new();
+        let local = backend
+            .add(vec![7; 1000], CidVersion::V1)
+            .await
+            .unwrap()
+            .hash;
+        let repo_size = backend.repo_stat().await.unwrap().repo_size;
+        let remote = "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy";
+
+        let within = check_quota(&backend, remote, Some(100), repo_size + 100).await;
+        assert!(matches!(within, Ok(Quota::Within)));
+        let over = check_quota(&backend, remote, Some(101), repo_size + 100).await;
+        assert!(matches!(over, Ok(Quota::Exceeded(_))));
+
+        // without an announced size only local content can be sized, anything else is
+        // left to be retried rather than fetched or refused
+        let unknown = check_quota(&backend, remote, None, u64::MAX).await;
+        assert!(unknown.is_err());
+        let local_size = check_quota(&backend, &local, None, repo_size * 2 - 1).await;
+        assert!(matches!(local_size, Ok(Quota::Exceeded(_))));
+    }
 }
@@ -3,7 +3,7 @@ use jsonrpsee::{
     core::{async_trait, RpcResult},
     Methods,
 };
-use prometheus::{Encoder, IntGaugeVec, Opts, Registry, TextEncoder};
+use prometheus::{Encoder, IntCounter, IntGaugeVec, Opts, Registry, TextEncoder};
 use reqwest::Client;
 use serde::Serialize;
 use tokio::{
@@ -60,6 +60,8 @@ async fn start_metric_process(state_client: StateClient, push_gateway_base_url:
 async fn handle(state_client: &StateClient) -> Result<MetricsData, String> {
     let mut metrics_data = MetricsData::default();
     get_ipfs_hashes(state_client, &mut metrics_data).await?;
+    get_refused_pins(state_client, &mut metrics_data).await?;
+    get_retry_count(state_client, &mut metrics_data).await?;
 
     Ok(metrics_data)
 }
@@ -98,6 +100,32 @@ async fn get_ipfs_hashes(
     }
 }
 
+async fn get_refused_pins(
+    state_client: &StateClient,
+    metrics_data: &mut MetricsData,
+) -> Result<(), String> {
+    match state_client.get_refused_pins().await {
+        Ok(data) => {
+            metrics_data.refused_pins = data;
+            Ok(())
+        }
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+async fn get_retry_count(
+    state_client: &StateClient,
+    metrics_data: &mut MetricsData,
+) -> Result<(), String> {
+    match state_client.get_retry_count().await {
+        Ok(data) => {
+            metrics_data.ipfs_retries = data;
+            Ok(())
+        }
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 #[async_trait]
 impl MetricsServer for MetricsApi {
     async fn check_status(&self) -> RpcResult<String> {
@@ -119,6 +147,8 @@ impl From<MetricsApi> for Methods {
 #[derive(Default, Serialize)]
 struct MetricsData {
     ipfs_hashes: Vec<String>,
+    refused_pins: Vec<(String, String)>,
+    ipfs_retries: u64,
 }
 
 impl MetricsData {
@@ -138,6 +168,28 @@ impl MetricsData {
 
         registry.register(Box::new(gauge_vec.clone()))?;
 
+        let refused_gauge_vec = IntGaugeVec::new(
+            Opts::new(
+                "ipfs_refused_pins",
+                "Pins refused by a peer because they would exceed its storage quota",
+            ),
+            &["hash", "peer"],
+        )?;
+
+        self.refused_pins
+            .into_iter()
+            .for_each(|(hash, peer)| refused_gauge_vec.with_label_values(&[&hash, &peer]).set(1));
+
+        registry.register(Box::new(refused_gauge_vec.clone()))?;
+
+        let retries_counter = IntCounter::new(
+            "ipfs_request_retries_total",
+            "Ipfs requests retried after a transient failure",
+        )?;
+        retries_counter.inc_by(self.ipfs_retries);
+
+        registry.register(Box::new(retries_counter.clone()))?;
+
         let metric_families = registry.gather();
         let encoder = TextEncoder::new();
         let mut buffer = Vec::new();
@@ -0,0 +1,225 @@
+use crate::{
+    api::{
+        mfs::MfsServer,
+        types::{
+            ipfs::IpfsCid,
+            mfs::{
+                MfsChangeResponse, MfsLsResponse, MfsPath, MfsPeerRoot, MfsRootsResponse,
+                MfsSource, MfsStatResponse,
+            },
+        },
+    },
+    backend::IpfsBackend,
+    network::NetworkClient,
+    rpc::{
+        error::RpcServeError,
+        ipfs::{gossip, GossipMessage},
+    },
+    state::StateClient,
+};
+use jsonrpsee::{
+    core::{async_trait, RpcResult},
+    Methods,
+};
+use tracing::{error, info, warn};
+
+/// Peers' mfs roots are kept alongside ipns names, under a key no valid name can take.
+pub(crate) fn mfs_root_key(peer_id: &str) -> String {
+    format!("/mfs/{}", peer_id)
+}
+
+pub struct MfsApi<B> {
+    backend: B,
+    state_client: StateClient,
+    network_client: NetworkClient,
+}
+
+impl<B> MfsApi<B>
+where
+    B: IpfsBackend,
+{
+    pub fn new(backend: B, state_client: StateClient, network_client: NetworkClient) -> Self {
+        Self {
+            backend,
+            state_client,
+            network_client,
+        }
+    }
+
+    /// Gossips the root after a change so peers pin the new tree and unpin the old one.
+    async fn publish_root(&self) -> RpcResult<MfsChangeResponse> {
+        let root = self
+            .backend
+            .files_stat(&MfsPath::root())
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?
+            .hash
+            .parse::<IpfsCid>()
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        let peer_id = self
+            .network_client
+            .get_peer_id()
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?
+            .to_string();
+
+        let previous = match self
+            .state_client
+            .set_name(mfs_root_key(&peer_id), root.to_string())
+            .await
+        {
+            Ok(previous) => previous.and_then(|previous| previous.parse::<IpfsCid>().ok()),
+            Err(err) => {
+                error!("Error saving mfs root to state: {:?}", err);
+                None
+            }
+        };
+        if previous != Some(root) {
+            info!("mfs root is now {}", root);
+            // lets peers check their storage quota without fetching the tree
+            let size = match self.backend.stat(&root.to_string()).await {
+                Ok(size) => Some(size),
+                Err(err) => {
+                    warn!("Unable to get size of mfs root {}: {}", root, err);
+                    None
+                }
+            };
+            gossip(
+                &self.network_client,
+                &GossipMessage::MfsRoot {
+                    peer_id,
+                    root,
+                    previous,
+                    size,
+                },
+            )
+            .await;
+        }
+
+        Ok(MfsChangeResponse { root })
+    }
+}
+
+#[async_trait]
+impl<B> MfsServer for MfsApi<B>
+where
+    B: IpfsBackend,
+{
+    async fn ls(&self, path: Option<MfsPath>) -> RpcResult<MfsLsResponse> {
+        let path = path.unwrap_or_else(MfsPath::root);
+        let response = self
+            .backend
+            .files_ls(&path)
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+
+        Ok(response)
+    }
+
+    async fn mkdir(&self, path: MfsPath, parents: Option<bool>) -> RpcResult<MfsChangeResponse> {
+        self.backend
+            .files_mkdir(&path, parents.unwrap_or(false))
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        info!("created mfs directory {}", path);
+
+        self.publish_root().await
+    }
+
+    async fn cp(&self, from: MfsSource, to: MfsPath) -> RpcResult<MfsChangeResponse> {
+        self.backend
+            .files_cp(&from, &to)
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        info!("copied {} to {}", from, to);
+
+        self.publish_root().await
+    }
+
+    async fn mv(&self, from: MfsPath, to: MfsPath) -> RpcResult<MfsChangeResponse> {
+        self.backend
+            .files_mv(&from, &to)
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        info!("moved {} to {}", from, to);
+
+        self.publish_root().await
+    }
+
+    async fn rm(&self, path: MfsPath, recursive: Option<bool>) -> RpcResult<MfsChangeResponse> {
+        self.backend
+            .files_rm(&path, recursive.unwrap_or(false))
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        info!("removed {}", path);
+
+        self.publish_root().await
+    }
+
+    async fn stat(&self, path: Option<MfsPath>) -> RpcResult<MfsStatResponse> {
+        let path = path.unwrap_or_else(MfsPath::root);
+        let response = self
+            .backend
+            .files_stat(&path)
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+
+        Ok(response)
+    }
+
+    async fn write(
+        &self,
+        path: MfsPath,
+        data: Vec<u8>,
+        create: Option<bool>,
+    ) -> RpcResult<MfsChangeResponse> {
+        self.backend
+            .files_write(&path, data, create.unwrap_or(true))
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?;
+        info!("wrote {}", path);
+
+        self.publish_root().await
+    }
+
+    async fn read(&self, path: MfsPath) -> RpcResult<String> {
+        let body = self.backend.files_read(&path).await.map_err(|err| {
+            error!("{}", err);
+            RpcServeError::Message(err.to_string())
+        })?;
+
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    async fn roots(&self) -> RpcResult<MfsRootsResponse> {
+        let local_peer_id = self
+            .network_client
+            .get_peer_id()
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?
+            .to_string();
+        let roots = self
+            .state_client
+            .get_names(mfs_root_key(""))
+            .await
+            .map_err(|err| RpcServeError::Message(err.to_string()))?
+            .into_iter()
+            .filter_map(|(name, root)| {
+                let peer_id = name.strip_prefix(&mfs_root_key(""))?.to_string();
+                let root = root.parse().ok()?;
+                (peer_id != local_peer_id).then_some(MfsPeerRoot { peer_id, root })
+            })
+            .collect();
+
+        Ok(MfsRootsResponse { roots })
+    }
+}
+
+impl<B> From<MfsApi<B>> for Methods
+where
+    B: IpfsBackend,
+{
+    fn from(val: MfsApi<B>) -> Self {
+        val.into_rpc().into()
+    }
+}
@@ -1,20 +1,30 @@
 mod error;
 pub mod ipfs;
 pub mod metrics;
+pub mod mfs;
+pub mod retry;
+mod staging;
 pub mod util;
 
+use bytes::Bytes;
 use futures::future::BoxFuture;
+use retry::RetryPolicy;
 use serde::de::DeserializeOwned;
-use tracing::error;
+use std::future::Future;
+use tokio::time::sleep;
+use tracing::{error, warn};
+
+use crate::state::StateClient;
 
 #[derive(Debug)]
 pub enum Module {
     Util,
     Ipfs,
     Metrics,
+    Mfs,
 }
 
-trait Call {
+pub(crate) trait Call {
     async fn call<'a, D, E>(
         request: impl FnOnce() -> BoxFuture<'a, Result<reqwest::Response, reqwest::Error>>,
     ) -> Result<Option<D>, E>
@@ -39,4 +49,90 @@ trait Call {
             }
         }
     }
+
+    /// Like `call`, but retries failures `retry_policy` considers transient. Each retry
+    /// is recorded in state so it shows up in metrics.
+    async fn call_with_retry<'a, D, E>(
+        retry_policy: &RetryPolicy,
+        state_client: &StateClient,
+        request: impl Fn() -> BoxFuture<'a, Result<reqwest::Response, reqwest::Error>>,
+    ) -> Result<Option<D>, E>
+    where
+        D: DeserializeOwned,
+        E: From<reqwest::Error> + From<serde_json::Error>,
+    {
+        let body = fetch_with_retry::<E>(retry_policy, state_client, request).await?;
+
+        if body.trim_ascii().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(serde_json::from_slice::<D>(&body)?))
+        }
+    }
+}
+
+/// Returns the raw response body, retrying like `call_with_retry`, for endpoints that
+/// do not answer with a single json object.
+pub(crate) async fn fetch_with_retry<'a, E>(
+    retry_policy: &RetryPolicy,
+    state_client: &StateClient,
+    request: impl Fn() -> BoxFuture<'a, Result<reqwest::Response, reqwest::Error>>,
+) -> Result<Bytes, E>
+where
+    E: From<reqwest::Error>,
+{
+    Ok(retry(retry_policy, state_client, || fetch_body(&request)).await?)
+}
+
+/// Returns the response once it has a successful status, retrying like
+/// `call_with_retry`, for bodies too large to be read into memory. Failures while the
+/// body is read are left to the caller.
+pub(crate) async fn send_with_retry<'a, E>(
+    retry_policy: &RetryPolicy,
+    state_client: &StateClient,
+    request: impl Fn() -> BoxFuture<'a, Result<reqwest::Response, reqwest::Error>>,
+) -> Result<reqwest::Response, E>
+where
+    E: From<reqwest::Error>,
+{
+    let send = || async { request().await?.error_for_status() };
+    Ok(retry(retry_policy, state_client, send).await?)
+}
+
+async fn retry<T, F>(
+    retry_policy: &RetryPolicy,
+    state_client: &StateClient,
+    attempt_request: impl Fn() -> F,
+) -> Result<T, reqwest::Error>
+where
+    F: Future<Output = Result<T, reqwest::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match attempt_request().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < retry_policy.attempts && retry_policy.is_retryable(&err) => {
+                let delay = retry_policy.delay(attempt);
+                warn!(
+                    "Request failed (attempt {} of {}), retrying in {:?}: {}",
+                    attempt, retry_policy.attempts, delay, err
+                );
+                if let Err(err) = state_client.record_retry().await {
+                    error!("Error recording retry in state: {:?}", err);
+                }
+                sleep(delay).await;
+                attempt += 1;
+            }
+            Err(err) => {
+                error!("{}", err);
+                return Err(err);
+            }
+        }
+    }
+}
+
+async fn fetch_body<'a>(
+    request: &impl Fn() -> BoxFuture<'a, Result<reqwest::Response, reqwest::Error>>,
+) -> Result<Bytes, reqwest::Error> {
+    request().await?.error_for_status()?.bytes().await
 }
@@ -0,0 +1,67 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use tokio::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            base_delay,
+            max_delay,
+        }
+    }
+
+    /// Transport errors, 5xx and 429 responses are worth retrying, anything else will
+    /// fail the same way again.
+    pub fn is_retryable(&self, err: &reqwest::Error) -> bool {
+        match err.status() {
+            Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+            None => err.is_connect() || err.is_timeout() || err.is_request() || err.is_body(),
+        }
+    }
+
+    /// Exponential backoff capped at `max_delay`, with up to half of the delay replaced
+    /// by jitter so peers retrying the same gossip message do not hit kubo in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let half = exp / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+
+        half + Duration::from_millis(jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3, Duration::from_millis(200), Duration::from_secs(5))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delay_grows_and_is_capped() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100), Duration::from_millis(300));
+
+        let first = policy.delay(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+
+        let second = policy.delay(2);
+        assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+
+        let capped = policy.delay(10);
+        assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+    }
+}
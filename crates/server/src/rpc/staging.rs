@@ -0,0 +1,145 @@
+//! Files staged on disk while they move between a client and the ipfs node in chunks, so
+//! neither the rpc server nor a single request has to hold a whole file.
+
+use std::{
+    collections::HashMap,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+use crate::api::types::ipfs::{Chunk, TransferId, MAX_CHUNK_SIZE};
+
+/// How long a transfer is kept without being touched before it is dropped.
+const STAGING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Default)]
+pub struct Staging {
+    transfers: Arc<Mutex<HashMap<TransferId, Staged>>>,
+}
+
+/// A staged file, removed from disk when dropped.
+pub struct Staged {
+    path: PathBuf,
+    last_used: Instant,
+}
+
+impl Staging {
+    /// Creates an empty staged file for a new transfer.
+    pub async fn create(&self) -> io::Result<(TransferId, Staged)> {
+        self.expire();
+
+        let transfer = TransferId::random();
+        let path = std::env::temp_dir().join(format!("crate-transfer-{}", transfer));
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+
+        Ok((
+            transfer,
+            Staged {
+                path,
+                last_used: Instant::now(),
+            },
+        ))
+    }
+
+    /// Takes `transfer` out of staging while it is used, so it is never used twice at once.
+    pub fn take(&self, transfer: &TransferId) -> Option<Staged> {
+        self.lock().remove(transfer)
+    }
+
+    /// Puts `staged` back for the next request of `transfer`.
+    pub fn put(&self, transfer: TransferId, mut staged: Staged) {
+        staged.last_used = Instant::now();
+        self.lock().insert(transfer, staged);
+    }
+
+    /// Drops transfers a client has abandoned.
+    fn expire(&self) {
+        self.lock().retain(|transfer, staged| {
+            let keep = staged.last_used.elapsed() < STAGING_TIMEOUT;
+            if !keep {
+                debug!("Dropping idle transfer {}", transfer);
+            }
+            keep
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<TransferId, Staged>> {
+        self.transfers.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Staged {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn append(&self, chunk: &Chunk) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.path).await?;
+        file.write_all(&chunk.0).await?;
+        file.flush().await
+    }
+
+    /// Reads up to `MAX_CHUNK_SIZE` bytes from `offset`, and whether that reached the end.
+    pub async fn read(&self, offset: u64) -> io::Result<(Chunk, bool)> {
+        let mut file = File::open(&self.path).await?;
+        let size = file.metadata().await?.len();
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        let mut chunk = Vec::new();
+        file.take(MAX_CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)
+            .await?;
+        let end = offset + chunk.len() as u64 >= size;
+
+        Ok((Chunk(chunk), end))
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!("Unable to remove staged file {:?}: {}", self.path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn staged_files_are_read_back_in_chunks_and_removed_when_dropped() {
+        let staging = Staging::default();
+        let (transfer, staged) = staging.create().await.unwrap();
+        staged
+            .append(&Chunk(vec![1; MAX_CHUNK_SIZE]))
+            .await
+            .unwrap();
+        staged.append(&Chunk(vec![2; 10])).await.unwrap();
+        let path = staged.path().to_path_buf();
+        staging.put(transfer.clone(), staged);
+
+        let staged = staging.take(&transfer).unwrap();
+        assert!(staging.take(&transfer).is_none());
+        let (first, end) = staged.read(0).await.unwrap();
+        assert!(first.0 == vec![1; MAX_CHUNK_SIZE]);
+        assert!(!end);
+        let (last, end) = staged.read(MAX_CHUNK_SIZE as u64).await.unwrap();
+        assert_eq!(last.0, vec![2; 10]);
+        assert!(end);
+
+        drop(staged);
+        assert!(!path.exists());
+    }
+}
@@ -1,7 +1,12 @@
 use super::{Server, ServerConfig, ServerError};
 use crate::{
+    backend::{
+        kubo::{KuboBackend, ReqwestClient},
+        Backend,
+    },
+    gateway::Gateway,
     network::NetworkClient,
-    rpc::{ipfs::IpfsApi, metrics::MetricsApi, util::UtilApi, Module},
+    rpc::{ipfs::IpfsApi, metrics::MetricsApi, mfs::MfsApi, util::UtilApi, Module},
     state::StateClient,
 };
 use std::ops::ControlFlow;
@@ -12,11 +17,21 @@ use tracing_subscriber::{reload::Handle, EnvFilter, Registry};
 
 pub struct ServerBuilder {
     config: ServerConfig,
+    backend: Option<Backend>,
 }
 
 impl ServerBuilder {
     pub fn new(config: ServerConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            backend: None,
+        }
+    }
+
+    /// Uses `backend` for ipfs instead of the kubo node at `ipfs_base_url`.
+    pub fn with_ipfs_backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
     }
 }
 
@@ -28,11 +43,28 @@ impl ServerBuilder {
         state_client: StateClient,
     ) -> Result<Server, ServerError> {
         let mut rpc_module = RpcModule::new(());
+        let backend = self.backend.unwrap_or_else(|| {
+            Backend::Kubo(KuboBackend::new(
+                self.config.ipfs_base_url.clone(),
+                ReqwestClient::default(),
+                self.config.retry_policy,
+                state_client.clone(),
+            ))
+        });
 
         let result = self.config.modules.iter().try_for_each(|m| {
             let methods: Methods = match m {
                 Module::Ipfs => IpfsApi::new(
-                    self.config.ipfs_base_url.clone(),
+                    backend.clone(),
+                    state_client.clone(),
+                    network_client.clone(),
+                    self.config.gc_on_expiry,
+                    self.config.normalize_cids,
+                    self.config.max_storage,
+                )
+                .into(),
+                Module::Mfs => MfsApi::new(
+                    backend.clone(),
                     state_client.clone(),
                     network_client.clone(),
                 )
@@ -52,7 +84,15 @@ impl ServerBuilder {
         match result {
             ControlFlow::Continue(()) => {
                 info!("Configured server with modules: {:?}", self.config.modules);
-                Ok(Server::new(rpc_module, self.config.port, self.config.ip))
+                let server = Server::new(rpc_module, self.config.port, self.config.ip);
+                Ok(match self.config.gateway_port {
+                    Some(port) => {
+                        let gateway = Gateway::new(backend, self.config.gateway_keys)
+                            .with_max_file_size(self.config.gateway_max_file_size);
+                        server.with_gateway(gateway, self.config.gateway_ip, port)
+                    }
+                    None => server,
+                })
             }
             ControlFlow::Break(err) => Err(err.into()),
         }
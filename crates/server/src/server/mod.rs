@@ -1,16 +1,23 @@
 pub mod builder;
 
 use crate::{
+    backend::Backend,
+    gateway::Gateway,
     network::{NetworkClient, NetworkError},
-    rpc::Module,
+    rpc::{retry::RetryPolicy, Module},
     state::StateClient,
 };
 use jsonrpsee::{
     server::{ServerBuilder as JosnRpseeServerBuilder, ServerHandle},
     RpcModule,
 };
-use tokio::{select, signal::ctrl_c};
-use tracing::{error, info};
+use std::{collections::HashMap, path::PathBuf};
+use tokio::{net::TcpListener, select, signal::ctrl_c};
+use tracing::{error, info, Instrument};
+
+/// Largest rpc request or response the server accepts, and clients should allow, in
+/// bytes. Files and cars larger than this move in chunks, see `api::transfer`.
+pub const MAX_RPC_BODY_SIZE: u32 = 10 * 1024 * 1024;
 
 pub struct ServerConfig {
     pub port: String,
@@ -22,12 +29,26 @@ pub struct ServerConfig {
     pub topic: String,
     pub ipfs_base_url: String,
     pub push_gateway_url: String,
+    pub gc_on_expiry: bool,
+    pub max_storage: Option<u64>,
+    pub pin_queue_file: Option<PathBuf>,
+    pub retry_policy: RetryPolicy,
+    pub normalize_cids: bool,
+    /// Address the http gateway listens on, apart from the rpc server's
+    pub gateway_ip: String,
+    /// Serve an http gateway on this port next to the rpc server
+    pub gateway_port: Option<String>,
+    /// Keys the gateway decrypts with, by the secret token requests present
+    pub gateway_keys: HashMap<String, Vec<u8>>,
+    /// Largest decrypted file the gateway serves
+    pub gateway_max_file_size: u64,
 }
 
 pub struct Server {
     rpc_module: RpcModule<()>,
     port: String,
     ip: String,
+    gateway: Option<(Gateway<Backend>, String)>,
 }
 
 impl Server {
@@ -36,16 +57,42 @@ impl Server {
             rpc_module,
             port,
             ip,
+            gateway: None,
         }
     }
 
+    /// Serves `gateway` on `ip` and `port` for as long as the rpc server runs.
+    pub fn with_gateway(mut self, gateway: Gateway<Backend>, ip: String, port: String) -> Self {
+        self.gateway = Some((gateway, format!("{0}:{1}", ip, port)));
+        self
+    }
+
     pub async fn run(self) -> Result<ServerHandle, ServerError> {
         let addr = format!("{0}:{1}", self.ip, self.port);
         info!("Starting Server on: {}", addr);
-        let server = JosnRpseeServerBuilder::default().build(&addr).await?;
+        let server = JosnRpseeServerBuilder::default()
+            .max_request_body_size(MAX_RPC_BODY_SIZE)
+            .max_response_body_size(MAX_RPC_BODY_SIZE)
+            .build(&addr)
+            .await?;
 
         let server_handle = server.start(self.rpc_module);
 
+        if let Some((gateway, addr)) = self.gateway {
+            info!("Starting Gateway on: {}", addr);
+            let listener = TcpListener::bind(&addr).await?;
+            let stopped = server_handle.clone();
+            tokio::spawn(
+                async move {
+                    select! {
+                        _ = gateway.serve(listener) => {},
+                        _ = stopped.stopped() => {},
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+
         Ok(server_handle)
     }
 
@@ -1,16 +1,54 @@
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use tokio::{
-    select,
+    fs, select,
     sync::{mpsc, oneshot, watch},
     time::{timeout, Duration},
 };
 
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info};
 
+use crate::{
+    api::types::ipfs::{PinState, PinStatus},
+    placement::Allocation,
+};
+
 pub struct State {
     added_ipfs_hashes: HashSet<String>,
     pinned_ipfs_hashes: HashSet<String>,
+    allocations: HashMap<String, Allocation>,
+    expirations: HashMap<String, SystemTime>,
+    pin_queue: BTreeMap<String, QueuedPin>,
+    /// Sequence number of the next enqueued pin, so pins are worked on in the order they
+    /// were queued.
+    next_pin_seq: u64,
+    pin_queue_file: Option<PathBuf>,
+    pin_statuses: HashMap<String, HashMap<String, PinStatus>>,
+    ipfs_retries: u64,
+    names: HashMap<String, String>,
+    /// Hashes pinned only because a name or peer's mfs root pointed at them.
+    name_pins: HashSet<String>,
+}
+
+/// A pin in the local pin queue, in the order it was queued and, after a failed attempt,
+/// not retried before `retry_at`. `size` is the size of the content the peer that announced
+/// it reported.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct QueuedPin {
+    #[serde(flatten)]
+    status: PinStatus,
+    #[serde(default)]
+    seq: u64,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    retry_at: Option<SystemTime>,
 }
 
 #[derive(Clone)]
@@ -27,10 +65,73 @@ pub struct StateRequest {
 
 #[derive(Debug)]
 enum StateRequestPayload {
-    AddIpfsHash { hash: String },
-    PinIpfsHash { hash: String },
-    RmPinIpfsHash { hash: String },
+    AddIpfsHash {
+        hash: String,
+    },
+    PinIpfsHash {
+        hash: String,
+    },
+    RmPinIpfsHash {
+        hash: String,
+    },
     GetIpfsHashes,
+    SetAllocation {
+        hash: String,
+        allocation: Allocation,
+    },
+    RmAllocation {
+        hash: String,
+    },
+    GetAllocations,
+    SetExpiry {
+        hash: String,
+        expires_at: SystemTime,
+    },
+    TakeExpiredHashes,
+    EnqueuePin {
+        hash: String,
+        size: Option<u64>,
+    },
+    NextQueuedPin,
+    UpdateQueuedPin {
+        hash: String,
+        status: PinStatus,
+        retry_at: Option<SystemTime>,
+    },
+    SetPinStatus {
+        hash: String,
+        peer_id: String,
+        status: PinStatus,
+    },
+    RmPinStatuses {
+        hash: String,
+    },
+    RmPinStatus {
+        hash: String,
+        peer_id: String,
+    },
+    GetPinStatuses {
+        hash: String,
+    },
+    GetRefusedPins,
+    RecordRetry,
+    GetRetryCount,
+    SetName {
+        name: String,
+        hash: String,
+    },
+    GetName {
+        name: String,
+    },
+    GetNames {
+        prefix: String,
+    },
+    AddNamePin {
+        hash: String,
+    },
+    ReleaseNamePin {
+        hash: String,
+    },
 }
 
 #[derive(Debug)]
@@ -38,7 +139,49 @@ enum StateResponse {
     AddIpfsHash,
     PinIpfsHash,
     RmIpfsHash,
-    GetIpfsHashes { hashes: Vec<String> },
+    GetIpfsHashes {
+        hashes: Vec<String>,
+    },
+    SetAllocation,
+    RmAllocation,
+    GetAllocations {
+        allocations: Vec<(String, Allocation)>,
+    },
+    SetExpiry,
+    TakeExpiredHashes {
+        hashes: Vec<(String, SystemTime)>,
+    },
+    EnqueuePin,
+    NextQueuedPin {
+        next: Option<(String, PinStatus, Option<u64>)>,
+    },
+    UpdateQueuedPin,
+    SetPinStatus,
+    RmPinStatuses,
+    RmPinStatus,
+    GetPinStatuses {
+        statuses: Vec<(String, PinStatus)>,
+    },
+    GetRefusedPins {
+        refused_pins: Vec<(String, String)>,
+    },
+    RecordRetry,
+    GetRetryCount {
+        count: u64,
+    },
+    SetName {
+        previous: Option<String>,
+    },
+    GetName {
+        hash: Option<String>,
+    },
+    GetNames {
+        names: Vec<(String, String)>,
+    },
+    AddNamePin,
+    ReleaseNamePin {
+        released: bool,
+    },
 }
 
 impl StateClient {
@@ -84,6 +227,226 @@ impl StateClient {
         Ok(hashes)
     }
 
+    pub async fn set_allocation(
+        &self,
+        hash: String,
+        allocation: Allocation,
+    ) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::SetAllocation { hash, allocation };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    pub async fn rm_allocation(&self, hash: String) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::RmAllocation { hash };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    pub async fn get_allocations(
+        &self,
+    ) -> Result<Vec<(String, Allocation)>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::GetAllocations;
+        let StateResponse::GetAllocations { allocations } = self.send_request(payload).await?
+        else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(allocations)
+    }
+
+    pub async fn set_expiry(
+        &self,
+        hash: String,
+        expires_at: SystemTime,
+    ) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::SetExpiry { hash, expires_at };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    /// Returns the hashes whose expiry time has passed, with that time, and stops tracking them.
+    pub async fn take_expired_hashes(
+        &self,
+    ) -> Result<Vec<(String, SystemTime)>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::TakeExpiredHashes;
+        let StateResponse::TakeExpiredHashes { hashes } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(hashes)
+    }
+
+    /// Adds `hash` to the end of the local pin queue. Pins already queued or pinning keep
+    /// their place and status, those that failed are queued again from scratch.
+    pub async fn enqueue_pin(
+        &self,
+        hash: String,
+        size: Option<u64>,
+    ) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::EnqueuePin { hash, size };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    /// Takes the pin queued longest ago that is not waiting to be retried and marks it as
+    /// pinning, with its size if known.
+    pub async fn next_queued_pin(
+        &self,
+    ) -> Result<Option<(String, PinStatus, Option<u64>)>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::NextQueuedPin;
+        let StateResponse::NextQueuedPin { next } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(next)
+    }
+
+    /// Records the outcome of pinning a queued pin. Pinned ones leave the queue, queued
+    /// ones are retried from `retry_at`.
+    pub async fn update_queued_pin(
+        &self,
+        hash: String,
+        status: PinStatus,
+        retry_at: Option<SystemTime>,
+    ) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::UpdateQueuedPin {
+            hash,
+            status,
+            retry_at,
+        };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    /// Records the pin status `peer_id` reported for `hash`.
+    pub async fn set_pin_status(
+        &self,
+        hash: String,
+        peer_id: String,
+        status: PinStatus,
+    ) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::SetPinStatus {
+            hash,
+            peer_id,
+            status,
+        };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    pub async fn rm_pin_statuses(
+        &self,
+        hash: String,
+    ) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::RmPinStatuses { hash };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    /// Forgets the status `peer_id` reported for `hash`, leaving the pin queue as it is.
+    pub async fn rm_pin_status(
+        &self,
+        hash: String,
+        peer_id: String,
+    ) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::RmPinStatus { hash, peer_id };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    pub async fn get_pin_statuses(
+        &self,
+        hash: String,
+    ) -> Result<Vec<(String, PinStatus)>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::GetPinStatuses { hash };
+        let StateResponse::GetPinStatuses { statuses } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(statuses)
+    }
+
+    pub async fn get_refused_pins(
+        &self,
+    ) -> Result<Vec<(String, String)>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::GetRefusedPins;
+        let StateResponse::GetRefusedPins { refused_pins } = self.send_request(payload).await?
+        else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(refused_pins)
+    }
+
+    /// Counts a retried ipfs request.
+    pub async fn record_retry(&self) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::RecordRetry;
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    pub async fn get_retry_count(&self) -> Result<u64, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::GetRetryCount;
+        let StateResponse::GetRetryCount { count } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(count)
+    }
+
+    /// Points the ipns `name` at `hash`, returning the hash it pointed at before.
+    pub async fn set_name(
+        &self,
+        name: String,
+        hash: String,
+    ) -> Result<Option<String>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::SetName { name, hash };
+        let StateResponse::SetName { previous } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(previous)
+    }
+
+    pub async fn get_name(
+        &self,
+        name: String,
+    ) -> Result<Option<String>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::GetName { name };
+        let StateResponse::GetName { hash } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(hash)
+    }
+
+    /// Returns every name starting with `prefix` and the hash it points at.
+    pub async fn get_names(
+        &self,
+        prefix: String,
+    ) -> Result<Vec<(String, String)>, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::GetNames { prefix };
+        let StateResponse::GetNames { names } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(names)
+    }
+
+    /// Records that `hash` is pinned because a name points at it, unless it is pinned for
+    /// its own sake.
+    pub async fn add_name_pin(&self, hash: String) -> Result<(), StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::AddNamePin { hash };
+        self.send_request(payload).await?;
+        Ok(())
+    }
+
+    /// Returns whether `hash`, which a name stopped pointing at, can be unpinned: it was
+    /// pinned because of the name, is not pinned for its own sake and no other name points
+    /// at it.
+    pub async fn release_name_pin(
+        &self,
+        hash: String,
+    ) -> Result<bool, StateClientError<StateRequest>> {
+        let payload = StateRequestPayload::ReleaseNamePin { hash };
+        let StateResponse::ReleaseNamePin { released } = self.send_request(payload).await? else {
+            return Err(StateClientError::UnexpectedResponse);
+        };
+        Ok(released)
+    }
+
     async fn send_request(
         &self,
         payload: StateRequestPayload,
@@ -128,7 +491,39 @@ impl State {
         Self {
             added_ipfs_hashes: HashSet::new(),
             pinned_ipfs_hashes: HashSet::new(),
+            allocations: HashMap::new(),
+            expirations: HashMap::new(),
+            pin_queue: BTreeMap::new(),
+            next_pin_seq: 0,
+            pin_queue_file: None,
+            pin_statuses: HashMap::new(),
+            ipfs_retries: 0,
+            names: HashMap::new(),
+            name_pins: HashSet::new(),
+        }
+    }
+
+    /// Persists the pin queue to `path`, loading any queue left there by a previous run.
+    pub fn with_pin_queue_file(mut self, path: impl Into<PathBuf>) -> Result<Self, io::Error> {
+        let path = path.into();
+
+        if path.exists() {
+            let contents = std::fs::read_to_string(&path)?;
+            let mut pin_queue = serde_json::from_str::<BTreeMap<String, QueuedPin>>(&contents)?;
+
+            // Queues written before pinned pins were dropped may still hold them, and a pin
+            // that was in flight when the server stopped has to be tried again
+            pin_queue.retain(|_, pin| pin.status.state != PinState::Pinned);
+            pin_queue
+                .values_mut()
+                .filter(|pin| pin.status.state == PinState::Pinning)
+                .for_each(|pin| pin.status.state = PinState::Queued);
+            self.next_pin_seq = pin_queue.values().map(|pin| pin.seq + 1).max().unwrap_or(0);
+            self.pin_queue = pin_queue;
         }
+
+        self.pin_queue_file = Some(path);
+        Ok(self)
     }
 
     pub fn start(self) -> StateClient {
@@ -164,6 +559,7 @@ impl State {
                 }
                 StateRequestPayload::RmPinIpfsHash { hash } => {
                     self.pinned_ipfs_hashes.remove(&hash);
+                    self.expirations.remove(&hash);
                     Ok(StateResponse::RmIpfsHash)
                 }
                 StateRequestPayload::GetIpfsHashes => {
@@ -174,12 +570,204 @@ impl State {
                         .collect::<Vec<String>>();
                     Ok(StateResponse::GetIpfsHashes { hashes })
                 }
+                StateRequestPayload::SetAllocation { hash, allocation } => {
+                    self.allocations.insert(hash, allocation);
+                    Ok(StateResponse::SetAllocation)
+                }
+                StateRequestPayload::RmAllocation { hash } => {
+                    self.allocations.remove(&hash);
+                    Ok(StateResponse::RmAllocation)
+                }
+                StateRequestPayload::GetAllocations => {
+                    let allocations = self
+                        .allocations
+                        .iter()
+                        .map(|(hash, allocation)| (hash.clone(), allocation.clone()))
+                        .collect::<Vec<(String, Allocation)>>();
+                    Ok(StateResponse::GetAllocations { allocations })
+                }
+                StateRequestPayload::SetExpiry { hash, expires_at } => {
+                    self.expirations.insert(hash, expires_at);
+                    Ok(StateResponse::SetExpiry)
+                }
+                StateRequestPayload::TakeExpiredHashes => {
+                    let now = SystemTime::now();
+                    let hashes = self
+                        .expirations
+                        .iter()
+                        .filter(|(_, expires_at)| **expires_at <= now)
+                        .map(|(hash, expires_at)| (hash.clone(), *expires_at))
+                        .collect::<Vec<(String, SystemTime)>>();
+                    hashes.iter().for_each(|(hash, _)| {
+                        self.expirations.remove(hash);
+                    });
+                    Ok(StateResponse::TakeExpiredHashes { hashes })
+                }
+                StateRequestPayload::EnqueuePin { hash, size } => {
+                    let is_active = self.pin_queue.get(&hash).is_some_and(|pin| {
+                        matches!(pin.status.state, PinState::Queued | PinState::Pinning)
+                    });
+                    if !is_active {
+                        let pin = QueuedPin {
+                            status: PinStatus::new(PinState::Queued),
+                            seq: self.next_pin_seq,
+                            size,
+                            retry_at: None,
+                        };
+                        self.next_pin_seq += 1;
+                        self.pin_queue.insert(hash, pin);
+                        self.persist_pin_queue().await;
+                    }
+                    Ok(StateResponse::EnqueuePin)
+                }
+                StateRequestPayload::NextQueuedPin => {
+                    let now = SystemTime::now();
+                    let next = self
+                        .pin_queue
+                        .iter_mut()
+                        .filter(|(_, pin)| {
+                            pin.status.state == PinState::Queued
+                                && pin.retry_at.map_or(true, |retry_at| retry_at <= now)
+                        })
+                        .min_by_key(|(_, pin)| pin.seq)
+                        .map(|(hash, pin)| {
+                            pin.status.state = PinState::Pinning;
+                            (hash.clone(), pin.status.clone(), pin.size)
+                        });
+                    if next.is_some() {
+                        self.persist_pin_queue().await;
+                    }
+                    Ok(StateResponse::NextQueuedPin { next })
+                }
+                StateRequestPayload::UpdateQueuedPin {
+                    hash,
+                    status,
+                    retry_at,
+                } => {
+                    // The pin may have been removed while it was being worked on
+                    if status.state == PinState::Pinned {
+                        if self.pin_queue.remove(&hash).is_some() {
+                            self.persist_pin_queue().await;
+                        }
+                    } else if let Some(queued) = self.pin_queue.get_mut(&hash) {
+                        queued.status = status;
+                        queued.retry_at = retry_at;
+                        self.persist_pin_queue().await;
+                    }
+                    Ok(StateResponse::UpdateQueuedPin)
+                }
+                StateRequestPayload::SetPinStatus {
+                    hash,
+                    peer_id,
+                    status,
+                } => {
+                    self.pin_statuses
+                        .entry(hash)
+                        .or_default()
+                        .insert(peer_id, status);
+                    Ok(StateResponse::SetPinStatus)
+                }
+                StateRequestPayload::RmPinStatuses { hash } => {
+                    self.pin_statuses.remove(&hash);
+                    if self.pin_queue.remove(&hash).is_some() {
+                        self.persist_pin_queue().await;
+                    }
+                    Ok(StateResponse::RmPinStatuses)
+                }
+                StateRequestPayload::RmPinStatus { hash, peer_id } => {
+                    if let Some(statuses) = self.pin_statuses.get_mut(&hash) {
+                        statuses.remove(&peer_id);
+                    }
+                    Ok(StateResponse::RmPinStatus)
+                }
+                StateRequestPayload::GetPinStatuses { hash } => {
+                    let statuses = self
+                        .pin_statuses
+                        .get(&hash)
+                        .map(|statuses| {
+                            statuses
+                                .iter()
+                                .map(|(peer_id, status)| (peer_id.clone(), status.clone()))
+                                .collect::<Vec<(String, PinStatus)>>()
+                        })
+                        .unwrap_or_default();
+                    Ok(StateResponse::GetPinStatuses { statuses })
+                }
+                StateRequestPayload::GetRefusedPins => {
+                    let refused_pins = self
+                        .pin_statuses
+                        .iter()
+                        .flat_map(|(hash, statuses)| {
+                            statuses
+                                .iter()
+                                .filter(|(_, status)| status.state == PinState::Refused)
+                                .map(|(peer_id, _)| (hash.clone(), peer_id.clone()))
+                        })
+                        .collect::<Vec<(String, String)>>();
+                    Ok(StateResponse::GetRefusedPins { refused_pins })
+                }
+                StateRequestPayload::RecordRetry => {
+                    self.ipfs_retries += 1;
+                    Ok(StateResponse::RecordRetry)
+                }
+                StateRequestPayload::GetRetryCount => Ok(StateResponse::GetRetryCount {
+                    count: self.ipfs_retries,
+                }),
+                StateRequestPayload::SetName { name, hash } => {
+                    let previous = self.names.insert(name, hash);
+                    Ok(StateResponse::SetName { previous })
+                }
+                StateRequestPayload::GetName { name } => Ok(StateResponse::GetName {
+                    hash: self.names.get(&name).cloned(),
+                }),
+                StateRequestPayload::GetNames { prefix } => Ok(StateResponse::GetNames {
+                    names: self
+                        .names
+                        .iter()
+                        .filter(|(name, _)| name.starts_with(&prefix))
+                        .map(|(name, hash)| (name.clone(), hash.clone()))
+                        .collect(),
+                }),
+                StateRequestPayload::AddNamePin { hash } => {
+                    if !self.is_pinned(&hash) {
+                        self.name_pins.insert(hash);
+                    }
+                    Ok(StateResponse::AddNamePin)
+                }
+                StateRequestPayload::ReleaseNamePin { hash } => {
+                    let released = !self.is_pinned(&hash)
+                        && !self.names.values().any(|target| *target == hash)
+                        && self.name_pins.remove(&hash);
+                    Ok(StateResponse::ReleaseNamePin { released })
+                }
             };
 
             Self::send_response(resp, req.sender).await;
         }
     }
 
+    /// Whether `hash` is pinned for its own sake rather than because a name points at it.
+    fn is_pinned(&self, hash: &str) -> bool {
+        self.pinned_ipfs_hashes.contains(hash) || self.added_ipfs_hashes.contains(hash)
+    }
+
+    async fn persist_pin_queue(&self) {
+        let Some(path) = &self.pin_queue_file else {
+            return;
+        };
+
+        let contents = match serde_json::to_string(&self.pin_queue) {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!("Unable to serialize pin queue: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = fs::write(path, contents).await {
+            error!("Error writing pin queue to {}: {}", path.display(), err);
+        }
+    }
+
     async fn send_response(
         resp: Result<StateResponse, StateClientError<StateRequest>>,
         sender: oneshot::Sender<Result<StateResponse, StateClientError<StateRequest>>>,
@@ -216,3 +804,134 @@ pub enum StateClientError<T> {
     #[error("")]
     UnexpectedResponse,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn next(state_client: &StateClient) -> Option<String> {
+        state_client
+            .next_queued_pin()
+            .await
+            .unwrap()
+            .map(|(hash, _, _)| hash)
+    }
+
+    #[tokio::test]
+    async fn pins_are_taken_in_the_order_they_were_queued() {
+        let state_client = State::new().start();
+        for hash in ["c", "a", "b"] {
+            state_client.enqueue_pin(hash.into(), None).await.unwrap();
+        }
+
+        assert_eq!(next(&state_client).await.as_deref(), Some("c"));
+        assert_eq!(next(&state_client).await.as_deref(), Some("a"));
+        assert_eq!(next(&state_client).await.as_deref(), Some("b"));
+        assert_eq!(next(&state_client).await, None);
+    }
+
+    #[tokio::test]
+    async fn pinned_pins_leave_the_queue_and_failed_ones_are_queued_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let pin_queue_file = dir.path().join("pin-queue.json");
+        let state_client = State::new()
+            .with_pin_queue_file(&pin_queue_file)
+            .unwrap()
+            .start();
+        state_client
+            .enqueue_pin("pinned".into(), None)
+            .await
+            .unwrap();
+        state_client
+            .enqueue_pin("failed".into(), None)
+            .await
+            .unwrap();
+
+        next(&state_client).await.unwrap();
+        let pinned = PinStatus::new(PinState::Pinned);
+        state_client
+            .update_queued_pin("pinned".into(), pinned, None)
+            .await
+            .unwrap();
+        next(&state_client).await.unwrap();
+        let failed = PinStatus {
+            state: PinState::Failed,
+            retries: 3,
+            error: Some("timed out".into()),
+        };
+        state_client
+            .update_queued_pin("failed".into(), failed, None)
+            .await
+            .unwrap();
+        let queue = std::fs::read_to_string(&pin_queue_file).unwrap();
+        assert!(!queue.contains("pinned") && queue.contains("failed"));
+
+        state_client
+            .enqueue_pin("failed".into(), None)
+            .await
+            .unwrap();
+        let (hash, status, _) = state_client.next_queued_pin().await.unwrap().unwrap();
+        assert_eq!(hash, "failed");
+        assert_eq!(status.retries, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_pins_are_retried_after_their_backoff() {
+        let state_client = State::new().start();
+        state_client
+            .enqueue_pin("flaky".into(), None)
+            .await
+            .unwrap();
+        state_client
+            .enqueue_pin("later".into(), None)
+            .await
+            .unwrap();
+
+        next(&state_client).await.unwrap();
+        let status = PinStatus {
+            state: PinState::Queued,
+            retries: 1,
+            error: Some("timed out".into()),
+        };
+        let retry_at = SystemTime::now() + Duration::from_millis(200);
+        state_client
+            .update_queued_pin("flaky".into(), status, Some(retry_at))
+            .await
+            .unwrap();
+
+        assert_eq!(next(&state_client).await.as_deref(), Some("later"));
+        assert_eq!(next(&state_client).await, None);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(next(&state_client).await.as_deref(), Some("flaky"));
+    }
+
+    #[tokio::test]
+    async fn name_targets_are_only_released_when_nothing_else_pins_them() {
+        let state_client = State::new().start();
+        state_client.pin_ipfs_hash("pinned".into()).await.unwrap();
+        for (name, hash) in [
+            ("a", "pinned"),
+            ("b", "shared"),
+            ("c", "shared"),
+            ("d", "own"),
+        ] {
+            state_client
+                .set_name(name.into(), hash.into())
+                .await
+                .unwrap();
+            state_client.add_name_pin(hash.into()).await.unwrap();
+        }
+        for name in ["a", "b", "d"] {
+            state_client
+                .set_name(name.into(), "next".into())
+                .await
+                .unwrap();
+        }
+
+        let released = |hash: &str| state_client.release_name_pin(hash.into());
+        assert!(!released("pinned").await.unwrap());
+        assert!(!released("shared").await.unwrap());
+        assert!(!released("unknown").await.unwrap());
+        assert!(released("own").await.unwrap());
+    }
+}